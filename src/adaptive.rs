@@ -0,0 +1,206 @@
+use image::{DynamicImage, GenericImage, Rgba};
+
+/** Stops sampling a pixel once the estimate of its mean is precise enough */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AdaptiveSampling {
+    /** Samples every pixel gets before its noise is judged */
+    pub min_samples: u32,
+    pub max_samples: u32,
+    /** Standard error of the mean luminance relative to the luminance, 0.01 is 1% noise */
+    pub noise_threshold: f64,
+}
+
+impl Default for AdaptiveSampling {
+    fn default() -> AdaptiveSampling {
+        AdaptiveSampling {
+            min_samples: 16,
+            max_samples: 1024,
+            noise_threshold: 0.01,
+        }
+    }
+}
+
+impl AdaptiveSampling {
+    /** Whether a pixel with these statistics needs no further samples */
+    pub fn converged(&self, stats: &RunningStats) -> bool {
+        if stats.count < self.min_samples.max(2) {
+            return false;
+        }
+        // dark pixels are judged on an absolute scale, the 0-255 color range makes 1 almost black
+        stats.standard_error() <= self.noise_threshold * stats.mean.max(1.0)
+    }
+}
+
+/** Mean and variance of a stream of values, updated one value at a time after Welford */
+#[derive(Clone, Debug, Default)]
+pub struct RunningStats {
+    pub count: u32,
+    pub mean: f64,
+    // sum of the squared differences to the mean
+    m2: f64,
+}
+
+impl RunningStats {
+    pub fn add(&mut self, value: f64) {
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    /** Unbiased sample variance */
+    pub fn variance(&self) -> f64 {
+        if self.count < 2 {
+            0.0
+        } else {
+            self.m2 / (self.count - 1) as f64
+        }
+    }
+
+    /** Expected error of the mean */
+    pub fn standard_error(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            (self.variance() / self.count as f64).sqrt()
+        }
+    }
+}
+
+/** Sample counts as colors from blue (none) over green to red (max_samples) */
+pub fn sample_count_heatmap(counts: &[u32], width: u32, height: u32, max_samples: u32) -> DynamicImage {
+    let mut image = DynamicImage::new_rgb8(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            let t = (counts[(y * width + x) as usize] as f64 / max_samples.max(1) as f64).clamp(0.0, 1.0);
+            let (red, green, blue) = if t < 0.5 {
+                (0.0, 2.0 * t, 1.0 - 2.0 * t)
+            } else {
+                (2.0 * t - 1.0, 2.0 - 2.0 * t, 0.0)
+            };
+            image.put_pixel(x, y, Rgba([(red * 255.0).round() as u8, (green * 255.0).round() as u8, (blue * 255.0).round() as u8, 255]));
+        }
+    }
+    image
+}
+
+#[cfg(test)]
+mod test_adaptive {
+    use super::*;
+    use crate::integrator::{black, render_radiance_with_counts, Integrator, PixelSampling, RenderSettings, Spectrum};
+    use crate::intersection::Ray;
+    use crate::sampler::Sampler;
+    use crate::scene::{Color, Scene};
+
+    #[test]
+    fn running_stats_match_direct_formulas() {
+        let values = [3.0, 7.0, 7.0, 19.0, 4.0];
+        let mut stats = RunningStats::default();
+        for v in values.iter() {
+            stats.add(*v);
+        }
+        let mean = values.iter().sum::<f64>() / 5.0;
+        let variance = values.iter().map(|v| (v - mean) * (v - mean)).sum::<f64>() / 4.0;
+        assert!((stats.mean - mean).abs() < 1e-12);
+        assert!((stats.variance() - variance).abs() < 1e-12);
+        assert!((stats.standard_error() - (variance / 5.0).sqrt()).abs() < 1e-12);
+    }
+
+    // black to the left of the image center, noise around 100 to the right
+    struct HalfNoisy;
+
+    impl Integrator for HalfNoisy {
+        fn li(&self, ray: &Ray, _scene: &Scene, sampler: &mut dyn Sampler) -> Spectrum {
+            if ray.direction.x < 0.0 {
+                return black();
+            }
+            let v = 200.0 * sampler.get_1d();
+            Color {
+                red: v,
+                green: v,
+                blue: v,
+            }
+        }
+    }
+
+    #[test]
+    fn converged_pixels_stop_early() {
+        let scene = Scene {
+            width: 4,
+            height: 2,
+            fov: 90.0,
+            elements: Vec::new(),
+            lights: Vec::new(),
+            medium: None,
+        };
+        let adaptive = AdaptiveSampling {
+            min_samples: 8,
+            max_samples: 4096,
+            noise_threshold: 0.02,
+        };
+        let settings = RenderSettings {
+            adaptive: Some(adaptive),
+            ..Default::default()
+        };
+        let (radiance, counts) = render_radiance_with_counts(&scene, &HalfNoisy, &settings);
+        for y in 0..2 {
+            assert_eq!(counts[y * 4], 8);
+            assert_eq!(radiance[y * 4].red, 0.0);
+            // uniform noise has a relative deviation of 0.577, 2% needs about 830 samples
+            let noisy = counts[y * 4 + 3];
+            assert!(noisy > 400 && noisy < 4096, "{}", noisy);
+            assert!((radiance[y * 4 + 3].red - 100.0).abs() < 10.0);
+        }
+        let heatmap = sample_count_heatmap(&counts, 4, 2, adaptive.max_samples);
+        let rgb = heatmap.as_rgb8().unwrap();
+        assert!(rgb.get_pixel(0, 0).0[0] == 0 && rgb.get_pixel(0, 0).0[2] > 250);
+        assert!(rgb.get_pixel(3, 0).0[1] > rgb.get_pixel(0, 0).0[1]);
+    }
+
+    // film position of the ray in red and green, on top of a bright constant that converges at once
+    struct FilmPosition;
+
+    impl Integrator for FilmPosition {
+        fn li(&self, ray: &Ray, _scene: &Scene, _sampler: &mut dyn Sampler) -> Spectrum {
+            Color {
+                red: ray.direction.x / -ray.direction.z,
+                green: ray.direction.y / -ray.direction.z,
+                blue: 1000.0,
+            }
+        }
+    }
+
+    #[test]
+    fn early_stop_spreads_stratified_samples_over_the_pixel() {
+        // at a field of view of 90 degrees the film position spans [-1, 1] across the pixel
+        let scene = Scene {
+            width: 1,
+            height: 1,
+            fov: 90.0,
+            elements: Vec::new(),
+            lights: Vec::new(),
+            medium: None,
+        };
+        let (mut mean_x, mut mean_y) = (0.0, 0.0);
+        let seeds = 64;
+        for seed in 0..seeds {
+            let settings = RenderSettings {
+                seed,
+                pixel_sampling: PixelSampling::Stratified,
+                adaptive: Some(AdaptiveSampling {
+                    min_samples: 4,
+                    max_samples: 64,
+                    noise_threshold: 0.01,
+                }),
+                ..Default::default()
+            };
+            let (radiance, counts) = render_radiance_with_counts(&scene, &FilmPosition, &settings);
+            assert_eq!(counts[0], 4);
+            mean_x += (radiance[0].red + 1.0) / 2.0 / seeds as f64;
+            mean_y += (1.0 - radiance[0].green) / 2.0 / seeds as f64;
+        }
+        // the first 4 of 8x8 cells in grid order would all lie in the top row
+        assert!((mean_x - 0.5).abs() < 0.06, "{}", mean_x);
+        assert!((mean_y - 0.5).abs() < 0.06, "{}", mean_y);
+    }
+}
@@ -0,0 +1,585 @@
+use crate::material::Material;
+use crate::point::Point3;
+use crate::sampling::to_world;
+use crate::scene::{Color, Disk, Element, Sphere, Triangle};
+use crate::vector::Vector3;
+use std::borrow::Cow;
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+// below this solid angle the spherical triangles get numerically unstable, the light is sampled by area instead
+const MIN_SOLID_ANGLE: f64 = 1e-4;
+
+/** Strategy for picking points on an area light */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LightSampling {
+    /** Uniformly distributed over the surface */
+    Uniform,
+    /** Uniformly distributed over the solid angle the light covers, disks fall back to uniform sampling */
+    SolidAngle,
+}
+
+pub enum AreaShape {
+    /** Parallelogram spanned by two edges starting at a corner */
+    Rectangle {
+        corner: Point3,
+        edge1: Vector3,
+        edge2: Vector3,
+    },
+    Disk {
+        center: Point3,
+        normal: Vector3,
+        radius: f64,
+    },
+    Sphere {
+        center: Point3,
+        radius: f64,
+    },
+    /** Triangle corners, e.g. of a loaded mesh with an emissive material */
+    Mesh(Vec<[Point3; 3]>),
+}
+
+/** Emitting surface casting soft shadows, both sides of flat shapes emit */
+pub struct AreaLight {
+    pub shape: AreaShape,
+    /** Shared with the geometry of the light, the emission is the radiance leaving the surface */
+    pub material: Arc<Material>,
+    pub sampling: LightSampling,
+    /** Shadow rays per shaded point */
+    pub samples: u32,
+}
+
+/** Point on a light as seen from a shaded point */
+pub struct AreaSample {
+    pub point: Point3,
+    /** Unit direction from the shaded point towards the light */
+    pub direction: Vector3,
+    pub distance: f64,
+    /** Density of the direction over solid angle */
+    pub pdf: f64,
+}
+
+impl AreaLight {
+    /** Light with a black, purely emissive material, sampled by solid angle with one shadow ray */
+    pub fn new(shape: AreaShape, emission: Color) -> AreaLight {
+        AreaLight {
+            shape,
+            material: Arc::new(Material {
+                albedo: Color {
+                    red: 0.0,
+                    green: 0.0,
+                    blue: 0.0,
+                },
+                emission: Some(emission),
+                ..Default::default()
+            }),
+            sampling: LightSampling::SolidAngle,
+            samples: 1,
+        }
+    }
+
+    pub fn emission(&self) -> Color {
+        self.material.emission.clone().unwrap_or(Color {
+            red: 0.0,
+            green: 0.0,
+            blue: 0.0,
+        })
+    }
+
+    // rectangles are split in two triangles for solid angle sampling, meshes are borrowed as they are
+    fn triangles(&self) -> Cow<'_, [[Point3; 3]]> {
+        match &self.shape {
+            AreaShape::Rectangle { corner, edge1, edge2 } => {
+                let p1 = corner + edge1;
+                let p2 = &p1 + edge2;
+                let p3 = corner + edge2;
+                Cow::Owned(vec![[corner.clone(), p1, p2.clone()], [corner.clone(), p2, p3]])
+            }
+            AreaShape::Mesh(triangles) => Cow::Borrowed(triangles),
+            _ => Cow::Borrowed(&[]),
+        }
+    }
+
+    /** Geometry making the light visible to camera and bounce rays */
+    pub fn elements(&self) -> Vec<Element> {
+        match &self.shape {
+            AreaShape::Disk { center, normal, radius } => vec![Element::Disk(Disk {
+                center: center.clone(),
+                normal: normal.clone(),
+                radius: *radius,
+                material: self.material.clone(),
+            })],
+            AreaShape::Sphere { center, radius } => vec![Element::Sphere(Sphere {
+                center: center.clone(),
+                radius: *radius,
+                material: self.material.clone(),
+            })],
+            _ => self
+                .triangles()
+                .iter()
+                .cloned()
+                .map(|[point1, point2, point3]| {
+                    Element::Triangle(Triangle {
+                        point1,
+                        point2,
+                        point3,
+                        normals: None,
+                        st: None,
+                        tangent_frame: None,
+                        material: self.material.clone(),
+                    })
+                })
+                .collect(),
+        }
+    }
+
+    pub fn area(&self) -> f64 {
+        match &self.shape {
+            AreaShape::Disk { radius, .. } => PI * radius * radius,
+            AreaShape::Sphere { radius, .. } => 4.0 * PI * radius * radius,
+            _ => self.triangles().iter().map(triangle_area).sum(),
+        }
+    }
+
+    /** Picks a point on the light from two uniform numbers, None if it can't contribute to the shaded point */
+    pub fn sample(&self, point: &Point3, u1: f64, u2: f64) -> Option<AreaSample> {
+        match (&self.shape, self.sampling) {
+            (AreaShape::Sphere { center, radius }, LightSampling::SolidAngle) if (point - center).length() > *radius => {
+                Some(sample_sphere_cone(point, center, *radius, u1, u2))
+            }
+            (AreaShape::Rectangle { .. }, LightSampling::SolidAngle) | (AreaShape::Mesh(_), LightSampling::SolidAngle) => {
+                let triangles = self.triangles();
+                match sample_triangles_solid_angle(&triangles, point, u1, u2) {
+                    Some(sample) => Some(sample),
+                    None => self.sample_area(point, u1, u2),
+                }
+            }
+            _ => self.sample_area(point, u1, u2),
+        }
+    }
+
+    /** Density of sample() picking the direction towards a point on the light, over solid angle */
+    pub fn pdf(&self, point: &Point3, light_point: &Point3, light_normal: &Vector3) -> f64 {
+        match (&self.shape, self.sampling) {
+            (AreaShape::Sphere { center, radius }, LightSampling::SolidAngle) if (point - center).length() > *radius => {
+                let sin2_max = (radius * radius / (point - center).norm()).min(1.0);
+                1.0 / (2.0 * PI * (1.0 - (1.0 - sin2_max).sqrt()))
+            }
+            (AreaShape::Rectangle { .. }, LightSampling::SolidAngle) | (AreaShape::Mesh(_), LightSampling::SolidAngle) => {
+                let total: f64 = self.triangles().iter().map(|t| solid_angle(t, point)).sum();
+                if total < MIN_SOLID_ANGLE {
+                    self.area_pdf(point, light_point, light_normal)
+                } else {
+                    1.0 / total
+                }
+            }
+            _ => self.area_pdf(point, light_point, light_normal),
+        }
+    }
+
+    fn area_pdf(&self, point: &Point3, light_point: &Point3, light_normal: &Vector3) -> f64 {
+        let to_light = light_point - point;
+        let distance2 = to_light.norm();
+        let cos_light = light_normal.normalize().dot(&to_light.normalize()).abs();
+        if distance2 == 0.0 || cos_light < 1e-9 {
+            return 0.0;
+        }
+        distance2 / (cos_light * self.area())
+    }
+
+    /** Total emitted flux, up to the 0-255 color scale */
+    pub fn power(&self) -> f64 {
+        // flat shapes emit from both sides
+        let sides = if matches!(self.shape, AreaShape::Sphere { .. }) { 1.0 } else { 2.0 };
+        PI * self.area() * self.emission().luminance() * sides
+    }
+
+    // uniform point on the surface, the area density converted to solid angle
+    fn sample_area(&self, point: &Point3, u1: f64, u2: f64) -> Option<AreaSample> {
+        let (light_point, light_normal) = match &self.shape {
+            AreaShape::Rectangle { corner, edge1, edge2 } => {
+                (&(corner + &(edge1 * u1)) + &(edge2 * u2), edge1.cross(edge2).normalize())
+            }
+            AreaShape::Disk { center, normal, radius } => {
+                let r = radius * u1.sqrt();
+                let phi = 2.0 * PI * u2;
+                let local = Vector3 {
+                    x: r * phi.cos(),
+                    y: r * phi.sin(),
+                    z: 0.0,
+                };
+                (center + &to_world(&local, &normal.normalize()), normal.normalize())
+            }
+            AreaShape::Sphere { center, radius } => {
+                let z = 1.0 - 2.0 * u1;
+                let r = (1.0 - z * z).max(0.0).sqrt();
+                let phi = 2.0 * PI * u2;
+                let normal = Vector3 {
+                    x: r * phi.cos(),
+                    y: r * phi.sin(),
+                    z,
+                };
+                (center + &(&normal * *radius), normal)
+            }
+            AreaShape::Mesh(triangles) => {
+                // pick a triangle in proportion to its area, then reuse u1 inside it
+                let areas: Vec<f64> = triangles.iter().map(triangle_area).collect();
+                let (index, u1) = pick(&areas, u1)?;
+                let [p1, p2, p3] = &triangles[index];
+                // uniform barycentric coordinates
+                let s = u1.sqrt();
+                let p = &(p1 + &(&(p2 - p1) * (s * (1.0 - u2)))) + &(&(p3 - p1) * (s * u2));
+                (p, (p2 - p1).cross(&(p3 - p1)).normalize())
+            }
+        };
+        let to_light = &light_point - point;
+        let distance = to_light.length();
+        if distance == 0.0 {
+            return None;
+        }
+        let direction = &to_light * (1.0 / distance);
+        // the far side of a sphere is hidden behind its near side
+        if matches!(self.shape, AreaShape::Sphere { .. }) && light_normal.dot(&direction) > 0.0 {
+            return None;
+        }
+        let cos_light = light_normal.dot(&direction).abs();
+        if cos_light < 1e-9 {
+            return None;
+        }
+        Some(AreaSample {
+            point: light_point,
+            direction,
+            distance,
+            pdf: distance * distance / (cos_light * self.area()),
+        })
+    }
+}
+
+/** One area light per emissive material among the triangles, e.g. a .geo mesh loaded with an emissive material */
+pub fn emissive_mesh_lights(elements: &[Element], sampling: LightSampling, samples: u32) -> Vec<AreaLight> {
+    let mut lights: Vec<AreaLight> = Vec::new();
+    for element in elements {
+        if let Element::Triangle(t) = element {
+            if t.material.emission.is_none() {
+                continue;
+            }
+            let corners = [t.point1.clone(), t.point2.clone(), t.point3.clone()];
+            match lights.iter_mut().find(|l| Arc::ptr_eq(&l.material, &t.material)) {
+                Some(AreaLight {
+                    shape: AreaShape::Mesh(triangles),
+                    ..
+                }) => triangles.push(corners),
+                _ => lights.push(AreaLight {
+                    shape: AreaShape::Mesh(vec![corners]),
+                    material: t.material.clone(),
+                    sampling,
+                    samples,
+                }),
+            }
+        }
+    }
+    lights
+}
+
+fn triangle_area(triangle: &[Point3; 3]) -> f64 {
+    let [p1, p2, p3] = triangle;
+    0.5 * (p2 - p1).cross(&(p3 - p1)).length()
+}
+
+// chooses an index in proportion to the weights and rescales u to [0, 1) within it
+fn pick(weights: &[f64], u: f64) -> Option<(usize, f64)> {
+    let total: f64 = weights.iter().sum();
+    if total <= 0.0 {
+        return None;
+    }
+    let mut target = u * total;
+    for (i, w) in weights.iter().enumerate() {
+        if target < *w || i == weights.len() - 1 {
+            return Some((i, (target / w).clamp(0.0, 1.0 - f64::EPSILON)));
+        }
+        target -= w;
+    }
+    None
+}
+
+// uniform direction inside the cone of directions hitting the sphere
+fn sample_sphere_cone(point: &Point3, center: &Point3, radius: f64, u1: f64, u2: f64) -> AreaSample {
+    let to_center = center - point;
+    let d = to_center.length();
+    let axis = &to_center * (1.0 / d);
+    let sin2_max = (radius * radius / (d * d)).min(1.0);
+    let cos_max = (1.0 - sin2_max).sqrt();
+    let cos_theta = 1.0 - u1 * (1.0 - cos_max);
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * u2;
+    let local = Vector3 {
+        x: sin_theta * phi.cos(),
+        y: sin_theta * phi.sin(),
+        z: cos_theta,
+    };
+    let direction = to_world(&local, &axis);
+    // nearest intersection, at grazing angles rounding can leave no root and the tangent point is used
+    let b = d * cos_theta;
+    let c = d * d - radius * radius;
+    let distance = b - (b * b - c).max(0.0).sqrt();
+    AreaSample {
+        point: point + &(&direction * distance),
+        direction,
+        distance,
+        pdf: 1.0 / (2.0 * PI * (1.0 - cos_max)),
+    }
+}
+
+// angle between two unit vectors, accurate for nearly parallel ones
+fn angle_between(a: &Vector3, b: &Vector3) -> f64 {
+    if a.dot(b) < 0.0 {
+        PI - 2.0 * ((a.clone() + b.clone()).length() / 2.0).min(1.0).asin()
+    } else {
+        2.0 * ((b.clone() - a.clone()).length() / 2.0).min(1.0).asin()
+    }
+}
+
+// interior angles of the spherical triangle spanned by three unit vectors
+fn spherical_angles(a: &Vector3, b: &Vector3, c: &Vector3) -> Option<(f64, f64, f64)> {
+    let n_ab = a.cross(b);
+    let n_bc = b.cross(c);
+    let n_ca = c.cross(a);
+    if n_ab.length() == 0.0 || n_bc.length() == 0.0 || n_ca.length() == 0.0 {
+        return None;
+    }
+    let (n_ab, n_bc, n_ca) = (n_ab.normalize(), n_bc.normalize(), n_ca.normalize());
+    Some((
+        angle_between(&n_ab, &(&n_ca * -1.0)),
+        angle_between(&n_bc, &(&n_ab * -1.0)),
+        angle_between(&n_ca, &(&n_bc * -1.0)),
+    ))
+}
+
+/** Solid angle of the triangle seen from the point */
+pub fn solid_angle(triangle: &[Point3; 3], point: &Point3) -> f64 {
+    let [a, b, c] = triangle.clone().map(|p| (&p - point).normalize());
+    match spherical_angles(&a, &b, &c) {
+        Some((alpha, beta, gamma)) => (alpha + beta + gamma - PI).max(0.0),
+        None => 0.0,
+    }
+}
+
+// uniform direction inside a spherical triangle (Arvo 1995, in the formulation of pbrt-v4)
+fn sample_spherical_triangle(a: &Vector3, b: &Vector3, c: &Vector3, u1: f64, u2: f64) -> Option<Vector3> {
+    let (alpha, beta, gamma) = spherical_angles(a, b, c)?;
+    let area_pi = alpha + beta + gamma;
+    // the sub-triangle with the chosen fraction of the area
+    let sub_area_pi = PI + u1 * (area_pi - PI);
+    let (cos_alpha, sin_alpha) = (alpha.cos(), alpha.sin());
+    let sin_phi = sub_area_pi.sin() * cos_alpha - sub_area_pi.cos() * sin_alpha;
+    let cos_phi = sub_area_pi.cos() * cos_alpha + sub_area_pi.sin() * sin_alpha;
+    let k1 = cos_phi + cos_alpha;
+    let k2 = sin_phi - sin_alpha * a.dot(b);
+    let cos_bp = ((k2 + (k2 * cos_phi - k1 * sin_phi) * cos_alpha) / ((k2 * sin_phi + k1 * cos_phi) * sin_alpha)).clamp(-1.0, 1.0);
+    let sin_bp = (1.0 - cos_bp * cos_bp).max(0.0).sqrt();
+    let cp = a * cos_bp + &gram_schmidt(c, a) * sin_bp;
+    // uniform along the arc from b towards the new corner
+    let cos_theta = 1.0 - u2 * (1.0 - cp.dot(b));
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    Some(b * cos_theta + &gram_schmidt(&cp, b) * sin_theta)
+}
+
+// unit vector along the part of v perpendicular to the unit vector w
+fn gram_schmidt(v: &Vector3, w: &Vector3) -> Vector3 {
+    (v.clone() - w * v.dot(w)).normalize()
+}
+
+// picks a triangle by its solid angle, None when the light is too small or degenerate for that
+fn sample_triangles_solid_angle(triangles: &[[Point3; 3]], point: &Point3, u1: f64, u2: f64) -> Option<AreaSample> {
+    let solid_angles: Vec<f64> = triangles.iter().map(|t| solid_angle(t, point)).collect();
+    let total: f64 = solid_angles.iter().sum();
+    if total < MIN_SOLID_ANGLE {
+        return None;
+    }
+    let (index, u1) = pick(&solid_angles, u1)?;
+    let [p1, p2, p3] = &triangles[index];
+    let [a, b, c] = [p1, p2, p3].map(|p| (p - point).normalize());
+    let direction = sample_spherical_triangle(&a, &b, &c, u1, u2)?.normalize();
+    let normal = (p2 - p1).cross(&(p3 - p1));
+    let denom = normal.dot(&direction);
+    if denom.abs() < 1e-12 {
+        return None;
+    }
+    let distance = (p1 - point).dot(&normal) / denom;
+    if distance <= 0.0 {
+        return None;
+    }
+    Some(AreaSample {
+        point: point + &(&direction * distance),
+        direction,
+        distance,
+        // the triangles of a light don't overlap, together they cover the total solid angle uniformly
+        pdf: 1.0 / total,
+    })
+}
+
+#[cfg(test)]
+mod test_area_light {
+    use super::*;
+    use crate::sampler::IndependentSampler;
+    use crate::sampling::Rng;
+    use crate::scene::Scene;
+    use crate::shading::visible_lights;
+
+    const WHITE: Color = Color {
+        red: 255.0,
+        green: 255.0,
+        blue: 255.0,
+    };
+
+    // Monte Carlo irradiance at the point from a light of unit radiance, for an upward facing surface
+    fn irradiance(light: &AreaLight, point: &Point3, n: u32) -> f64 {
+        let mut rng = Rng::new(11, 0);
+        let mut sum = 0.0;
+        for _ in 0..n {
+            if let Some(sample) = light.sample(point, rng.next_f64(), rng.next_f64()) {
+                sum += sample.direction.y.max(0.0) / sample.pdf;
+            }
+        }
+        sum / n as f64
+    }
+
+    fn with_sampling(mut light: AreaLight, sampling: LightSampling) -> AreaLight {
+        light.sampling = sampling;
+        light
+    }
+
+    #[test]
+    fn sphere_irradiance_matches_analytic() {
+        let shape = || AreaShape::Sphere {
+            center: Point3 { x: 0.0, y: 4.0, z: 0.0 },
+            radius: 1.0,
+        };
+        // a sphere straight above looks like a point source: E = pi L (r / d)^2
+        let expected = PI / 16.0;
+        for &sampling in &[LightSampling::Uniform, LightSampling::SolidAngle] {
+            let light = with_sampling(AreaLight::new(shape(), WHITE), sampling);
+            let e = irradiance(&light, &Point3::zero(), 20000);
+            assert!((e - expected).abs() < 0.03 * expected);
+        }
+    }
+
+    #[test]
+    fn disk_irradiance_matches_analytic() {
+        let light = AreaLight::new(
+            AreaShape::Disk {
+                center: Point3 { x: 0.0, y: 2.0, z: 0.0 },
+                normal: Vector3 { x: 0.0, y: -1.0, z: 0.0 },
+                radius: 1.0,
+            },
+            WHITE,
+        );
+        // E = pi L r^2 / (h^2 + r^2)
+        let expected = PI / 5.0;
+        let e = irradiance(&light, &Point3::zero(), 20000);
+        assert!((e - expected).abs() < 0.02 * expected);
+    }
+
+    #[test]
+    fn rectangle_sampling_strategies_agree() {
+        let rectangle = || AreaShape::Rectangle {
+            corner: Point3 { x: -1.0, y: 1.0, z: -0.5 },
+            edge1: Vector3 { x: 2.0, y: 0.0, z: 0.0 },
+            edge2: Vector3 { x: 0.0, y: 0.5, z: 1.0 },
+        };
+        let uniform = irradiance(&with_sampling(AreaLight::new(rectangle(), WHITE), LightSampling::Uniform), &Point3::zero(), 40000);
+        let solid_angle = irradiance(&AreaLight::new(rectangle(), WHITE), &Point3::zero(), 40000);
+        assert!((uniform - solid_angle).abs() < 0.02 * uniform);
+        // the same rectangle as a mesh
+        let light = AreaLight::new(rectangle(), WHITE);
+        let mesh = AreaLight::new(AreaShape::Mesh(light.triangles().into_owned()), WHITE);
+        assert!((mesh.area() - light.area()).abs() < 1e-9);
+        assert!((irradiance(&mesh, &Point3::zero(), 40000) - solid_angle).abs() < 0.02 * uniform);
+    }
+
+    #[test]
+    fn solid_angle_of_an_octant() {
+        let triangle = [
+            Point3 { x: 1.0, y: 0.0, z: 0.0 },
+            Point3 { x: 0.0, y: 1.0, z: 0.0 },
+            Point3 { x: 0.0, y: 0.0, z: 1.0 },
+        ];
+        // seen from the origin the triangle covers one eighth of the sphere
+        assert!((solid_angle(&triangle, &Point3::zero()) - PI / 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn emissive_meshes_become_lights() {
+        let emissive = AreaLight::new(
+            AreaShape::Rectangle {
+                corner: Point3::zero(),
+                edge1: Vector3 { x: 1.0, y: 0.0, z: 0.0 },
+                edge2: Vector3 { x: 0.0, y: 1.0, z: 0.0 },
+            },
+            WHITE,
+        );
+        let mut elements = emissive.elements();
+        elements.push(Element::Triangle(Triangle {
+            point1: Point3::zero(),
+            point2: Point3 { x: 1.0, y: 0.0, z: 0.0 },
+            point3: Point3 { x: 0.0, y: 1.0, z: 0.0 },
+            normals: None,
+            st: None,
+            tangent_frame: None,
+            material: Arc::new(Material::default()),
+        }));
+        let lights = emissive_mesh_lights(&elements, LightSampling::Uniform, 4);
+        assert_eq!(lights.len(), 1);
+        assert!(Arc::ptr_eq(&lights[0].material, &emissive.material));
+        assert!((lights[0].area() - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn occluder_casts_penumbra() {
+        let scene = |elements: Vec<Element>| {
+            let mut light = AreaLight::new(
+                AreaShape::Rectangle {
+                    corner: Point3 { x: -1.0, y: 4.0, z: -1.0 },
+                    edge1: Vector3 { x: 2.0, y: 0.0, z: 0.0 },
+                    edge2: Vector3 { x: 0.0, y: 0.0, z: 2.0 },
+                },
+                WHITE,
+            );
+            light.samples = 256;
+            let mut scene = Scene {
+                width: 1,
+                height: 1,
+                fov: 90.0,
+                elements,
+                lights: Vec::new(),
+                medium: None,
+            };
+            scene.add_area_light(light);
+            scene
+        };
+        // a large blocker halfway between light and floor, covering x < 0
+        let blocker = Element::Triangle(Triangle {
+            point1: Point3 { x: 0.0, y: 2.0, z: -50.0 },
+            point2: Point3 { x: 0.0, y: 2.0, z: 50.0 },
+            point3: Point3 { x: -50.0, y: 2.0, z: 0.0 },
+            normals: None,
+            st: None,
+            tangent_frame: None,
+            material: Arc::new(Material::default()),
+        });
+        let shadowed = scene(vec![blocker]);
+        let open = scene(Vec::new());
+        let up = Vector3 { x: 0.0, y: 1.0, z: 0.0 };
+        let mut sampler = IndependentSampler::new(0);
+        let mut lit = |scene: &Scene, x: f64| -> f64 {
+            let point = Point3 { x, y: 0.0, z: 0.0 };
+            visible_lights(scene, &point, &up, &up, 0.0, &mut sampler).iter().map(|l| l.color.red).sum()
+        };
+        assert_eq!(lit(&shadowed, -3.0), 0.0);
+        // below the edge of the blocker half of the light is hidden
+        let ratio = lit(&shadowed, 0.0) / lit(&open, 0.0);
+        assert!(ratio > 0.4 && ratio < 0.6);
+        // nothing in the way, solid angle samples all carry the same weight
+        assert!((lit(&shadowed, 3.0) - lit(&open, 3.0)).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,424 @@
+use crate::intersection::{Intersectable, Ray};
+use crate::load_geo_scene::{
+    apply_material, create_smooth_triangles, create_trianglemesh, create_trianglemesh_attributes,
+    create_trianglemesh_normals, GeoData,
+};
+use crate::point::Point3;
+use crate::material::Material;
+use crate::scene::{Element, Scene};
+use crate::vector::Vector3;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{Error, ErrorKind};
+use std::sync::Arc;
+
+// starting points for the newton iteration, spread over the (u, v) domain
+const NEWTON_SEEDS: [f64; 4] = [0.125, 0.375, 0.625, 0.875];
+const NEWTON_MAX_ITERATIONS: usize = 20;
+const NEWTON_TOLERANCE: f64 = 1e-9;
+
+/** Bicubic bezier patch, 16 control points stored row by row (index = 4 * row + column) */
+pub struct BezierPatch {
+    pub control_points: Vec<Point3>,
+    pub material: Arc<Material>,
+}
+
+// cubic bernstein polynomials
+fn bernstein(i: usize, t: f64) -> f64 {
+    let s = 1.0 - t;
+    match i {
+        0 => s * s * s,
+        1 => 3.0 * t * s * s,
+        2 => 3.0 * t * t * s,
+        _ => t * t * t,
+    }
+}
+
+fn bernstein_derivative(i: usize, t: f64) -> f64 {
+    let s = 1.0 - t;
+    match i {
+        0 => -3.0 * s * s,
+        1 => 3.0 * s * s - 6.0 * t * s,
+        2 => 6.0 * t * s - 3.0 * t * t,
+        _ => 3.0 * t * t,
+    }
+}
+
+impl BezierPatch {
+    pub fn evaluate(&self, u: f64, v: f64) -> Point3 {
+        self.blend(u, v, bernstein, bernstein).to_point()
+    }
+
+    pub fn derivative_u(&self, u: f64, v: f64) -> Vector3 {
+        self.blend(u, v, bernstein_derivative, bernstein)
+    }
+
+    pub fn derivative_v(&self, u: f64, v: f64) -> Vector3 {
+        self.blend(u, v, bernstein, bernstein_derivative)
+    }
+
+    pub fn normal(&self, u: f64, v: f64) -> Vector3 {
+        // collapsed rows (e.g. the tip of the teapot lid) have a vanishing derivative at the border,
+        // so the normal is taken slightly inside the patch
+        let u = u.clamp(1e-4, 1.0 - 1e-4);
+        let v = v.clamp(1e-4, 1.0 - 1e-4);
+        self.derivative_u(u, v).cross(&self.derivative_v(u, v)).normalize()
+    }
+
+    fn blend(&self, u: f64, v: f64, basis_u: fn(usize, f64) -> f64, basis_v: fn(usize, f64) -> f64) -> Vector3 {
+        let mut res = Vector3::zero();
+        for i in 0..4 {
+            for j in 0..4 {
+                let weight = basis_u(i, u) * basis_v(j, v);
+                res = res + &self.control_points[4 * i + j].to_vector() * weight;
+            }
+        }
+        res
+    }
+
+    /** Evaluates the patch on a (divisions + 1)^2 grid, one quad face per grid cell */
+    pub fn tessellate(&self, divisions: usize) -> GeoData {
+        let mut vertex_array: Vec<Point3> = Vec::new();
+        let mut vertex_normals: Vec<Point3> = Vec::new();
+        let mut vertex_st: Vec<(f64, f64)> = Vec::new();
+        for i in 0..=divisions {
+            for j in 0..=divisions {
+                let u = i as f64 / divisions as f64;
+                let v = j as f64 / divisions as f64;
+                vertex_array.push(self.evaluate(u, v));
+                vertex_normals.push(self.normal(u, v).to_point());
+                vertex_st.push((u, v));
+            }
+        }
+
+        let mut face_index_array: Vec<usize> = Vec::new();
+        let mut vertex_index_array: Vec<usize> = Vec::new();
+        let mut normal_array: Vec<Point3> = Vec::new();
+        let mut st_array: Vec<(f64, f64)> = Vec::new();
+        for i in 0..divisions {
+            for j in 0..divisions {
+                let corner = i * (divisions + 1) + j;
+                let quad = [corner, corner + 1, corner + divisions + 2, corner + divisions + 1];
+                face_index_array.push(4);
+                for index in quad.iter() {
+                    vertex_index_array.push(*index);
+                    normal_array.push(vertex_normals[*index].clone());
+                    st_array.push(vertex_st[*index]);
+                }
+            }
+        }
+        GeoData {
+            num_face: divisions * divisions,
+            face_index_array,
+            vertex_index_array,
+            vertex_array,
+            normal_array,
+            st_array,
+        }
+    }
+
+    /** Intersects the ray directly with the patch, returns (distance, u, v) of the nearest hit */
+    pub fn intersect_uv(&self, ray: &Ray) -> Option<(f64, f64, f64)> {
+        if !self.hits_bounding_box(ray) {
+            return None;
+        }
+        // the ray is the intersection of two planes, a hit is a (u, v) lying in both planes
+        let d = &ray.direction;
+        let plane1 = if d.x.abs() > d.y.abs() && d.x.abs() > d.z.abs() {
+            Vector3 { x: d.y, y: -d.x, z: 0.0 }
+        } else {
+            Vector3 { x: 0.0, y: d.z, z: -d.y }
+        }
+        .normalize();
+        let plane2 = plane1.cross(d).normalize();
+        let offset1 = -plane1.dot(&ray.origin.to_vector());
+        let offset2 = -plane2.dot(&ray.origin.to_vector());
+
+        let mut nearest: Option<(f64, f64, f64)> = None;
+        for seed_u in NEWTON_SEEDS.iter() {
+            for seed_v in NEWTON_SEEDS.iter() {
+                let (mut u, mut v) = (*seed_u, *seed_v);
+                for _ in 0..NEWTON_MAX_ITERATIONS {
+                    let p = self.evaluate(u, v).to_vector();
+                    let f1 = plane1.dot(&p) + offset1;
+                    let f2 = plane2.dot(&p) + offset2;
+                    if f1.abs() + f2.abs() < NEWTON_TOLERANCE {
+                        break;
+                    }
+                    let su = self.derivative_u(u, v);
+                    let sv = self.derivative_v(u, v);
+                    let (j11, j12) = (plane1.dot(&su), plane1.dot(&sv));
+                    let (j21, j22) = (plane2.dot(&su), plane2.dot(&sv));
+                    let det = j11 * j22 - j12 * j21;
+                    if det.abs() < 1e-12 {
+                        break;
+                    }
+                    u -= (j22 * f1 - j12 * f2) / det;
+                    v -= (j11 * f2 - j21 * f1) / det;
+                    if !(-0.5..=1.5).contains(&u) || !(-0.5..=1.5).contains(&v) {
+                        break; // diverging away from the patch
+                    }
+                }
+                if !(-1e-6..=1.0 + 1e-6).contains(&u) || !(-1e-6..=1.0 + 1e-6).contains(&v) {
+                    continue;
+                }
+                let p = self.evaluate(u, v).to_vector();
+                if (plane1.dot(&p) + offset1).abs() + (plane2.dot(&p) + offset2).abs() > 1e-6 {
+                    continue; // did not converge
+                }
+                let t = (p - ray.origin.to_vector()).dot(d) / d.norm();
+                if t > 1e-6 && nearest.is_none_or(|(nearest_t, _, _)| t < nearest_t) {
+                    nearest = Some((t, u, v));
+                }
+            }
+        }
+        nearest
+    }
+
+    // the patch lies within the convex hull of its control points
+    fn hits_bounding_box(&self, ray: &Ray) -> bool {
+        let mut min = self.control_points[0].clone();
+        let mut max = self.control_points[0].clone();
+        for p in &self.control_points {
+            min = Point3 { x: min.x.min(p.x), y: min.y.min(p.y), z: min.z.min(p.z) };
+            max = Point3 { x: max.x.max(p.x), y: max.y.max(p.y), z: max.z.max(p.z) };
+        }
+        let slabs = [
+            (ray.origin.x, ray.direction.x, min.x, max.x),
+            (ray.origin.y, ray.direction.y, min.y, max.y),
+            (ray.origin.z, ray.direction.z, min.z, max.z),
+        ];
+        let mut t_near = f64::NEG_INFINITY;
+        let mut t_far = f64::INFINITY;
+        for (origin, direction, low, high) in slabs.iter() {
+            if direction.abs() < 1e-12 {
+                if origin < low || origin > high {
+                    return false;
+                }
+                continue;
+            }
+            let t0 = (low - origin) / direction;
+            let t1 = (high - origin) / direction;
+            t_near = t_near.max(t0.min(t1));
+            t_far = t_far.min(t0.max(t1));
+        }
+        t_near <= t_far && t_far >= 0.0
+    }
+}
+
+impl Intersectable for BezierPatch {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        self.intersect_uv(ray).map(|(t, _, _)| t)
+    }
+}
+
+/** Tessellates all patches into one polygon mesh */
+pub fn tessellate_patches(patches: &[BezierPatch], divisions: usize) -> GeoData {
+    let mut res = GeoData {
+        num_face: 0,
+        face_index_array: Vec::new(),
+        vertex_index_array: Vec::new(),
+        vertex_array: Vec::new(),
+        normal_array: Vec::new(),
+        st_array: Vec::new(),
+    };
+    for patch in patches {
+        let mesh = patch.tessellate(divisions);
+        let offset = res.vertex_array.len();
+        res.num_face += mesh.num_face;
+        res.face_index_array.extend(mesh.face_index_array);
+        res.vertex_index_array.extend(mesh.vertex_index_array.iter().map(|i| i + offset));
+        res.vertex_array.extend(mesh.vertex_array);
+        res.normal_array.extend(mesh.normal_array);
+        res.st_array.extend(mesh.st_array);
+    }
+    res
+}
+
+/** Loads the ".bpt" bezier patch format: patch count, then per patch "3 3" followed by 16 control points */
+pub fn load_bpt_file(file_path: String) -> std::io::Result<Vec<BezierPatch>> {
+    let mut file = File::open(file_path)?;
+    let mut file_as_string = String::new();
+    file.read_to_string(&mut file_as_string)?;
+
+    let invalid = |msg: &str| Error::new(ErrorKind::InvalidData, msg.to_string());
+    let mut numbers = file_as_string.split_whitespace();
+    let mut next_number = || -> std::io::Result<f64> {
+        numbers
+            .next()
+            .ok_or_else(|| invalid("unexpected end of file"))?
+            .parse::<f64>()
+            .map_err(|_| invalid("expected a number"))
+    };
+
+    let num_patches = next_number()? as usize;
+    let mut patches: Vec<BezierPatch> = Vec::new();
+    // the patches of a file make up one model and share its material
+    let material = Arc::new(Material::default());
+    for _ in 0..num_patches {
+        let degree_u = next_number()?;
+        let degree_v = next_number()?;
+        if degree_u != 3.0 || degree_v != 3.0 {
+            return Err(invalid("only bicubic patches are supported"));
+        }
+        let mut control_points: Vec<Point3> = Vec::new();
+        for _ in 0..16 {
+            control_points.push(Point3 {
+                x: next_number()?,
+                y: next_number()?,
+                z: next_number()?,
+            });
+        }
+        patches.push(BezierPatch {
+            control_points,
+            material: material.clone(),
+        });
+    }
+    Ok(patches)
+}
+
+/** Bezier scene, tessellated into triangles when divisions is given, otherwise intersected directly */
+pub fn create_scene_from_bpt_file(path: String, divisions: Option<usize>) -> std::io::Result<Scene> {
+    let mut patches = load_bpt_file(path)?;
+    for patch in patches.iter_mut() {
+        for p in patch.control_points.iter_mut() {
+            // .bpt files are z-up, move the model in front of the camera
+            *p = Point3 {
+                x: p.x,
+                y: p.z - 1.5,
+                z: -p.y - 6.0,
+            };
+        }
+    }
+    let elements: Vec<Element> = match divisions {
+        Some(n) => {
+            let geo_data = tessellate_patches(&patches, n);
+            let triangle_index_array: Vec<usize> = create_trianglemesh(&geo_data);
+            let triangle_normal_array: Vec<Point3> = create_trianglemesh_normals(&geo_data);
+            let triangle_st_array: Vec<(f64, f64)> = create_trianglemesh_attributes(&geo_data, &geo_data.st_array);
+            let mut triangles = create_smooth_triangles(geo_data.vertex_array, triangle_index_array, triangle_normal_array);
+            if let Some(patch) = patches.first() {
+                apply_material(&mut triangles, Some(triangle_st_array), &patch.material);
+            }
+            triangles
+        }
+        None => patches.into_iter().map(Element::BezierPatch).collect(),
+    };
+    Ok(Scene {
+        width: 600,
+        height: 400,
+        fov: 90.0,
+        elements,
+        lights: Vec::new(),
+        medium: None,
+    })
+}
+
+#[cfg(test)]
+mod test_bezier {
+    use super::*;
+
+    fn flat_patch() -> BezierPatch {
+        let mut control_points: Vec<Point3> = Vec::new();
+        for i in 0..4 {
+            for j in 0..4 {
+                control_points.push(Point3 {
+                    x: i as f64 - 1.5,
+                    y: j as f64 - 1.5,
+                    z: -5.0,
+                });
+            }
+        }
+        BezierPatch {
+            control_points,
+            material: Arc::new(Material::default()),
+        }
+    }
+
+    #[test]
+    fn patch_interpolates_corners() {
+        let patch = flat_patch();
+        let corner = patch.evaluate(1.0, 0.0);
+        assert!((corner.x - 1.5).abs() < 1e-12);
+        assert!((corner.y + 1.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn tessellate_creates_quads() {
+        let geo_data = flat_patch().tessellate(4);
+        assert_eq!(geo_data.num_face, 16);
+        assert_eq!(geo_data.vertex_array.len(), 25);
+        assert_eq!(create_trianglemesh(&geo_data).len(), 16 * 2 * 3);
+    }
+
+    #[test]
+    fn newton_intersection_hits_flat_patch() {
+        let ray = Ray {
+            origin: Point3::zero(),
+            direction: Vector3 {
+                x: 0.1,
+                y: 0.2,
+                z: -1.0,
+            }
+            .normalize(),
+            time: 0.0,
+        };
+        let (t, _, _) = flat_patch().intersect_uv(&ray).unwrap();
+        let hit = &ray.origin + &(&ray.direction * t);
+        assert!((hit.z + 5.0).abs() < 1e-6);
+
+        let miss = Ray {
+            origin: Point3::zero(),
+            direction: Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: 1.0,
+            },
+            time: 0.0,
+        };
+        assert!(flat_patch().intersect(&miss).is_none());
+    }
+
+    #[test]
+    fn traced_patch_carries_its_uv() {
+        let scene = Scene {
+            width: 1,
+            height: 1,
+            fov: 90.0,
+            elements: vec![Element::BezierPatch(flat_patch())],
+            lights: Vec::new(),
+            medium: None,
+        };
+        let ray = Ray {
+            origin: Point3::zero(),
+            direction: Vector3 {
+                x: 0.3,
+                y: -0.2,
+                z: -1.0,
+            }
+            .normalize(),
+            time: 0.0,
+        };
+        let (_, _, uv) = scene.nearest_hit(&ray).unwrap();
+        let (u, v) = uv.unwrap();
+        assert!((flat_patch().normal(u, v).z - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn load_teapot() {
+        let patches = load_bpt_file(String::from("geometry/teapot.bpt")).unwrap();
+        assert_eq!(patches.len(), 32);
+    }
+
+    #[test]
+    fn tessellated_teapot_is_smooth() {
+        let scene = create_scene_from_bpt_file(String::from("geometry/teapot.bpt"), Some(4)).unwrap();
+        assert_eq!(scene.elements.len(), 32 * 16 * 2);
+        for element in &scene.elements {
+            match element {
+                Element::Triangle(t) => assert!(t.normals.is_some() && t.st.is_some()),
+                _ => panic!("expected triangles"),
+            }
+        }
+    }
+}
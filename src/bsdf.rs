@@ -0,0 +1,829 @@
+use crate::material::Material;
+use crate::sampling::{cosine_sample_hemisphere, orthonormal_basis, to_world};
+use crate::scene::Color;
+use crate::shading::{Lambert, LightSample, ShadingContext, ShadingModel};
+use crate::spectral::Dispersion;
+use crate::vector::Vector3;
+use std::f64::consts::PI;
+
+/** Direction chosen by a bsdf together with its Monte Carlo weight */
+pub struct BsdfSample {
+    /** Unit direction from the point towards where the light comes from */
+    pub direction: Vector3,
+    /** bsdf * cos / pdf, the factor the path throughput is multiplied with */
+    pub weight: Color,
+    pub pdf: f64,
+    /** Perfectly smooth scattering, eval and pdf are zero for this direction */
+    pub specular: bool,
+}
+
+/** Physically based scattering function, reflectances are in [0, 1] while the albedo keeps the 0-255 scale */
+pub trait Bsdf: Send + Sync {
+    /** bsdf(view, direction) * cos of the angle between direction and normal */
+    fn eval(&self, context: &ShadingContext, direction: &Vector3) -> Color;
+    /** Density of sample() choosing the direction, over solid angle */
+    fn pdf(&self, context: &ShadingContext, direction: &Vector3) -> f64;
+    /** Picks a direction from uniform numbers, u_lobe chooses between reflection and transmission or similar lobes */
+    fn sample(&self, context: &ShadingContext, u_lobe: f64, u1: f64, u2: f64) -> Option<BsdfSample>;
+}
+
+// sample towards a direction chosen by a non-delta bsdf, weighted with its eval and pdf
+fn weighted_sample(bsdf: &dyn Bsdf, context: &ShadingContext, direction: Vector3) -> Option<BsdfSample> {
+    let pdf = bsdf.pdf(context, &direction);
+    if pdf <= 0.0 {
+        return None;
+    }
+    let f = bsdf.eval(context, &direction);
+    Some(BsdfSample {
+        direction,
+        weight: Color {
+            red: f.red / pdf,
+            green: f.green / pdf,
+            blue: f.blue / pdf,
+        },
+        pdf,
+        specular: false,
+    })
+}
+
+/** Lights a bsdf with point samples, the light colors are pi times the irradiance they deliver */
+pub fn shade_bsdf(bsdf: &dyn Bsdf, context: &ShadingContext, lights: &[LightSample]) -> Color {
+    let mut res = black();
+    for light in lights {
+        let f = bsdf.eval(context, &light.direction);
+        res += &(&(&f * &light.color) * PI);
+    }
+    res
+}
+
+fn reflectance(albedo: &Color) -> Color {
+    albedo * (1.0 / 255.0)
+}
+
+impl Bsdf for Lambert {
+    fn eval(&self, context: &ShadingContext, direction: &Vector3) -> Color {
+        let cos_theta = context.normal.dot(direction).max(0.0);
+        &reflectance(context.albedo) * (cos_theta / PI)
+    }
+
+    fn pdf(&self, context: &ShadingContext, direction: &Vector3) -> f64 {
+        context.normal.dot(direction).max(0.0) / PI
+    }
+
+    fn sample(&self, context: &ShadingContext, _u_lobe: f64, u1: f64, u2: f64) -> Option<BsdfSample> {
+        let local = cosine_sample_hemisphere(u1, u2);
+        let pdf = local.z / PI;
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            direction: to_world(&local, &context.normal),
+            weight: reflectance(context.albedo),
+            pdf,
+            specular: false,
+        })
+    }
+}
+
+/** glTF style metallic-roughness material, the albedo is the base color */
+pub struct MetallicRoughness {
+    /** 0 for dielectrics, 1 for metals tinted by the base color */
+    pub metallic: f64,
+    /** Perceptual roughness, the GGX alpha is its square */
+    pub roughness: f64,
+}
+
+// reflectance of dielectrics at normal incidence, the glTF default
+const DIELECTRIC_F0: f64 = 0.04;
+
+/** GGX normal distribution, cos_h is the cosine between half vector and normal */
+pub fn ggx_distribution(cos_h: f64, alpha: f64) -> f64 {
+    if cos_h <= 0.0 {
+        return 0.0;
+    }
+    let a2 = alpha * alpha;
+    let d = cos_h * cos_h * (a2 - 1.0) + 1.0;
+    a2 / (PI * d * d)
+}
+
+/** Smith masking of one direction for the GGX distribution */
+pub fn smith_g1(cos_v: f64, alpha: f64) -> f64 {
+    if cos_v <= 0.0 {
+        return 0.0;
+    }
+    let a2 = alpha * alpha;
+    2.0 * cos_v / (cos_v + (a2 + (1.0 - a2) * cos_v * cos_v).sqrt())
+}
+
+/** Schlick's approximation of the fresnel reflectance */
+pub fn schlick_fresnel(f0: f64, cos_theta: f64) -> f64 {
+    f0 + (1.0 - f0) * (1.0 - cos_theta.clamp(0.0, 1.0)).powi(5)
+}
+
+/** Unpolarized fresnel reflectance of a dielectric, eta is the index behind the surface over the one in front */
+pub fn fresnel_dielectric(cos_i: f64, eta: f64) -> f64 {
+    let cos_i = cos_i.clamp(0.0, 1.0);
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    0.5 * (parallel * parallel + perpendicular * perpendicular)
+}
+
+/** Unpolarized fresnel reflectance of a conductor with complex index of refraction eta + i k */
+pub fn fresnel_conductor(cos_i: f64, eta: f64, k: f64) -> f64 {
+    let cos2 = cos_i.clamp(0.0, 1.0).powi(2);
+    let sin2 = 1.0 - cos2;
+    let t0 = eta * eta - k * k - sin2;
+    let a2_plus_b2 = (t0 * t0 + 4.0 * eta * eta * k * k).sqrt();
+    let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+    let t1 = a2_plus_b2 + cos2;
+    let t2 = 2.0 * cos2.sqrt() * a;
+    let perpendicular = (t1 - t2) / (t1 + t2);
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let parallel = perpendicular * (t3 - t4) / (t3 + t4);
+    0.5 * (parallel + perpendicular)
+}
+
+/** Samples a microfacet normal visible from the view direction, both in the local frame around +z (Heitz 2018) */
+pub fn sample_ggx_vndf(view: &Vector3, alpha: f64, u1: f64, u2: f64) -> Vector3 {
+    // stretch the view so the distribution becomes a hemisphere
+    let vh = Vector3 {
+        x: alpha * view.x,
+        y: alpha * view.y,
+        z: view.z,
+    }
+    .normalize();
+    let lensq = vh.x * vh.x + vh.y * vh.y;
+    let t1 = if lensq > 0.0 {
+        Vector3 {
+            x: -vh.y,
+            y: vh.x,
+            z: 0.0,
+        }
+        .normalize()
+    } else {
+        Vector3 { x: 1.0, y: 0.0, z: 0.0 }
+    };
+    let t2 = vh.cross(&t1);
+    // uniform point on the projected, partly hidden disk
+    let r = u1.sqrt();
+    let phi = 2.0 * PI * u2;
+    let p1 = r * phi.cos();
+    let s = 0.5 * (1.0 + vh.z);
+    let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+    let nh = &t1 * p1 + &t2 * p2 + &vh * (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+    // unstretch
+    Vector3 {
+        x: alpha * nh.x,
+        y: alpha * nh.y,
+        z: nh.z.max(0.0),
+    }
+    .normalize()
+}
+
+fn to_local(v: &Vector3, normal: &Vector3) -> Vector3 {
+    let (tangent, bitangent) = orthonormal_basis(normal);
+    Vector3 {
+        x: v.dot(&tangent),
+        y: v.dot(&bitangent),
+        z: v.dot(normal),
+    }
+}
+
+fn reflect(v: &Vector3, n: &Vector3) -> Vector3 {
+    n * (2.0 * v.dot(n)) - v.clone()
+}
+
+// v and n on the same side, eta is the index behind the surface over the one in front, None on total internal reflection
+fn refract(v: &Vector3, n: &Vector3, eta: f64) -> Option<Vector3> {
+    let cos_i = v.dot(n);
+    let sin2_t = (1.0 - cos_i * cos_i).max(0.0) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    Some(n * (cos_i / eta - cos_t) - v * (1.0 / eta))
+}
+
+// density of reflecting off a visible microfacet, over the reflected directions
+fn reflection_pdf(view: &Vector3, light: &Vector3, alpha: f64) -> f64 {
+    let half = (view.clone() + light.clone()).normalize();
+    // visible normal density divided by the jacobian of the reflection
+    smith_g1(view.z, alpha) * ggx_distribution(half.z, alpha) / (4.0 * view.z)
+}
+
+// surfaces with a smaller GGX alpha are treated as perfectly smooth
+const SMOOTH_ALPHA: f64 = 1e-3;
+
+fn black() -> Color {
+    Color {
+        red: 0.0,
+        green: 0.0,
+        blue: 0.0,
+    }
+}
+
+impl MetallicRoughness {
+    fn alpha(&self) -> f64 {
+        // a perfectly smooth surface would make the distribution a dirac
+        (self.roughness * self.roughness).max(1e-3)
+    }
+
+    // chance of sampling the specular lobe instead of the diffuse one
+    fn specular_probability(&self) -> f64 {
+        0.5 + 0.5 * self.metallic.clamp(0.0, 1.0)
+    }
+
+}
+
+impl Bsdf for MetallicRoughness {
+    fn eval(&self, context: &ShadingContext, direction: &Vector3) -> Color {
+        let view = to_local(&context.view_direction, &context.normal);
+        let light = to_local(direction, &context.normal);
+        if view.z <= 0.0 || light.z <= 0.0 {
+            return black();
+        }
+        let alpha = self.alpha();
+        let metallic = self.metallic.clamp(0.0, 1.0);
+        let half = (view.clone() + light.clone()).normalize();
+        let specular = ggx_distribution(half.z, alpha) * smith_g1(view.z, alpha) * smith_g1(light.z, alpha) / (4.0 * view.z);
+        let cos_d = view.dot(&half);
+        let base = reflectance(context.albedo);
+        let channel = |base: f64| {
+            let f0 = DIELECTRIC_F0 + (base - DIELECTRIC_F0) * metallic;
+            let fresnel = schlick_fresnel(f0, cos_d);
+            let diffuse = (1.0 - fresnel) * (1.0 - metallic) * base / PI * light.z;
+            diffuse + fresnel * specular
+        };
+        Color {
+            red: channel(base.red),
+            green: channel(base.green),
+            blue: channel(base.blue),
+        }
+    }
+
+    fn pdf(&self, context: &ShadingContext, direction: &Vector3) -> f64 {
+        let view = to_local(&context.view_direction, &context.normal);
+        let light = to_local(direction, &context.normal);
+        if view.z <= 0.0 || light.z <= 0.0 {
+            return 0.0;
+        }
+        let p = self.specular_probability();
+        p * reflection_pdf(&view, &light, self.alpha()) + (1.0 - p) * light.z / PI
+    }
+
+    fn sample(&self, context: &ShadingContext, u_lobe: f64, u1: f64, u2: f64) -> Option<BsdfSample> {
+        let view = to_local(&context.view_direction, &context.normal);
+        if view.z <= 0.0 {
+            return None;
+        }
+        let light = if u_lobe < self.specular_probability() {
+            let half = sample_ggx_vndf(&view, self.alpha(), u1, u2);
+            reflect(&view, &half)
+        } else {
+            cosine_sample_hemisphere(u1, u2)
+        };
+        if light.z <= 0.0 {
+            return None;
+        }
+        weighted_sample(self, context, to_world(&light, &context.normal))
+    }
+}
+
+impl ShadingModel for MetallicRoughness {
+    fn shade(&self, context: &ShadingContext, lights: &[LightSample]) -> Color {
+        shade_bsdf(self, context, lights)
+    }
+
+    fn bsdf(&self) -> Option<&dyn Bsdf> {
+        Some(self)
+    }
+}
+
+/** Metal with a complex index of refraction per color channel, the albedo is not used */
+pub struct Conductor {
+    /** Real part of the index of refraction for red, green and blue */
+    pub eta: [f64; 3],
+    /** Absorption coefficient, the imaginary part of the index of refraction */
+    pub k: [f64; 3],
+    /** 0 is a perfect mirror, otherwise the GGX alpha is its square */
+    pub roughness: f64,
+}
+
+impl Conductor {
+    pub fn gold(roughness: f64) -> Conductor {
+        Conductor {
+            eta: [0.143, 0.374, 1.442],
+            k: [3.983, 2.385, 1.603],
+            roughness,
+        }
+    }
+
+    pub fn copper(roughness: f64) -> Conductor {
+        Conductor {
+            eta: [0.200, 0.924, 1.102],
+            k: [3.912, 2.452, 2.142],
+            roughness,
+        }
+    }
+
+    pub fn aluminium(roughness: f64) -> Conductor {
+        Conductor {
+            eta: [1.657, 0.880, 0.521],
+            k: [9.224, 6.270, 4.837],
+            roughness,
+        }
+    }
+
+    fn alpha(&self) -> f64 {
+        self.roughness * self.roughness
+    }
+
+    fn fresnel(&self, cos_i: f64) -> Color {
+        Color {
+            red: fresnel_conductor(cos_i, self.eta[0], self.k[0]),
+            green: fresnel_conductor(cos_i, self.eta[1], self.k[1]),
+            blue: fresnel_conductor(cos_i, self.eta[2], self.k[2]),
+        }
+    }
+}
+
+impl Bsdf for Conductor {
+    fn eval(&self, context: &ShadingContext, direction: &Vector3) -> Color {
+        let view = to_local(&context.view_direction, &context.normal);
+        let light = to_local(direction, &context.normal);
+        let alpha = self.alpha();
+        if alpha < SMOOTH_ALPHA || view.z <= 0.0 || light.z <= 0.0 {
+            return black();
+        }
+        let half = (view.clone() + light.clone()).normalize();
+        let specular = ggx_distribution(half.z, alpha) * smith_g1(view.z, alpha) * smith_g1(light.z, alpha) / (4.0 * view.z);
+        &self.fresnel(view.dot(&half)) * specular
+    }
+
+    fn pdf(&self, context: &ShadingContext, direction: &Vector3) -> f64 {
+        let view = to_local(&context.view_direction, &context.normal);
+        let light = to_local(direction, &context.normal);
+        if self.alpha() < SMOOTH_ALPHA || view.z <= 0.0 || light.z <= 0.0 {
+            return 0.0;
+        }
+        reflection_pdf(&view, &light, self.alpha())
+    }
+
+    fn sample(&self, context: &ShadingContext, _u_lobe: f64, u1: f64, u2: f64) -> Option<BsdfSample> {
+        let view = to_local(&context.view_direction, &context.normal);
+        if view.z <= 0.0 {
+            return None;
+        }
+        if self.alpha() < SMOOTH_ALPHA {
+            let light = Vector3 {
+                x: -view.x,
+                y: -view.y,
+                z: view.z,
+            };
+            return Some(BsdfSample {
+                direction: to_world(&light, &context.normal),
+                weight: self.fresnel(view.z),
+                pdf: 1.0,
+                specular: true,
+            });
+        }
+        let half = sample_ggx_vndf(&view, self.alpha(), u1, u2);
+        let light = reflect(&view, &half);
+        if light.z <= 0.0 {
+            return None;
+        }
+        weighted_sample(self, context, to_world(&light, &context.normal))
+    }
+}
+
+impl ShadingModel for Conductor {
+    fn shade(&self, context: &ShadingContext, lights: &[LightSample]) -> Color {
+        shade_bsdf(self, context, lights)
+    }
+
+    fn bsdf(&self) -> Option<&dyn Bsdf> {
+        Some(self)
+    }
+}
+
+/** Glass-like boundary of a transparent medium, the albedo is not used */
+pub struct Dielectric {
+    /** Index of refraction of the medium on the inner side, the outside is vacuum */
+    pub index_of_refraction: f64,
+    /** 0 is perfectly smooth, otherwise the GGX alpha is its square */
+    pub roughness: f64,
+    /** Index changing with the wavelength, replaces index_of_refraction on spectral paths */
+    pub dispersion: Option<Dispersion>,
+}
+
+impl Dielectric {
+    pub fn glass(roughness: f64) -> Dielectric {
+        Dielectric {
+            index_of_refraction: 1.5,
+            roughness,
+            dispersion: None,
+        }
+    }
+
+    /** Glass whose index changes with the wavelength, rgb paths use its index at the helium d line */
+    pub fn dispersive(dispersion: Dispersion, roughness: f64) -> Dielectric {
+        Dielectric {
+            index_of_refraction: dispersion.index_of_refraction(587.6),
+            roughness,
+            dispersion: Some(dispersion),
+        }
+    }
+
+    /** Material for a loaded mesh like the lenses of glasses.geo, a dispersive one splits the light in spectral renders */
+    pub fn into_material(self) -> Material {
+        Material {
+            shading_model: Box::new(self),
+            ..Default::default()
+        }
+    }
+
+    fn alpha(&self) -> f64 {
+        self.roughness * self.roughness
+    }
+
+    // index on the far side of the surface over the one on the viewer's side
+    fn relative_eta(&self, context: &ShadingContext) -> f64 {
+        let index = match (self.dispersion, context.wavelength) {
+            (Some(dispersion), Some(wavelength)) => dispersion.index_of_refraction(wavelength),
+            _ => self.index_of_refraction,
+        };
+        if context.front_face {
+            index
+        } else {
+            1.0 / index
+        }
+    }
+
+    // microfacet normal between view and light, None for configurations no facet can produce
+    fn half_vector(view: &Vector3, light: &Vector3, eta: f64) -> Option<Vector3> {
+        let reflected = light.z > 0.0;
+        let half = if reflected { view.clone() + light.clone() } else { view.clone() + light * eta };
+        if half.length() == 0.0 {
+            return None;
+        }
+        let half = half.normalize();
+        let half = if half.z < 0.0 { &half * -1.0 } else { half };
+        // the light has to leave on the same side of the facet for reflection, on the other for refraction
+        if view.dot(&half) <= 0.0 || (light.dot(&half) > 0.0) != reflected {
+            return None;
+        }
+        Some(half)
+    }
+}
+
+impl Bsdf for Dielectric {
+    fn eval(&self, context: &ShadingContext, direction: &Vector3) -> Color {
+        let view = to_local(&context.view_direction, &context.normal);
+        let light = to_local(direction, &context.normal);
+        let alpha = self.alpha();
+        let eta = self.relative_eta(context);
+        if alpha < SMOOTH_ALPHA || view.z <= 0.0 || light.z == 0.0 {
+            return black();
+        }
+        let half = match Dielectric::half_vector(&view, &light, eta) {
+            Some(half) => half,
+            None => return black(),
+        };
+        let fresnel = fresnel_dielectric(view.dot(&half), eta);
+        let d_g = ggx_distribution(half.z, alpha) * smith_g1(view.z, alpha) * smith_g1(light.z.abs(), alpha);
+        let value = if light.z > 0.0 {
+            fresnel * d_g / (4.0 * view.z)
+        } else {
+            let denom = light.dot(&half) + view.dot(&half) / eta;
+            // radiance is compressed into the smaller solid angle of the denser medium
+            (1.0 - fresnel) * d_g * (light.dot(&half) * view.dot(&half)).abs() / (view.z * denom * denom) / (eta * eta)
+        };
+        Color {
+            red: value,
+            green: value,
+            blue: value,
+        }
+    }
+
+    fn pdf(&self, context: &ShadingContext, direction: &Vector3) -> f64 {
+        let view = to_local(&context.view_direction, &context.normal);
+        let light = to_local(direction, &context.normal);
+        let alpha = self.alpha();
+        let eta = self.relative_eta(context);
+        if alpha < SMOOTH_ALPHA || view.z <= 0.0 || light.z == 0.0 {
+            return 0.0;
+        }
+        let half = match Dielectric::half_vector(&view, &light, eta) {
+            Some(half) => half,
+            None => return 0.0,
+        };
+        let fresnel = fresnel_dielectric(view.dot(&half), eta);
+        if light.z > 0.0 {
+            fresnel * reflection_pdf(&view, &light, alpha)
+        } else {
+            let denom = light.dot(&half) + view.dot(&half) / eta;
+            let visible_normal_pdf = smith_g1(view.z, alpha) * ggx_distribution(half.z, alpha) * view.dot(&half) / view.z;
+            (1.0 - fresnel) * visible_normal_pdf * light.dot(&half).abs() / (denom * denom)
+        }
+    }
+
+    fn sample(&self, context: &ShadingContext, u_lobe: f64, u1: f64, u2: f64) -> Option<BsdfSample> {
+        let view = to_local(&context.view_direction, &context.normal);
+        if view.z <= 0.0 {
+            return None;
+        }
+        let eta = self.relative_eta(context);
+        let smooth = self.alpha() < SMOOTH_ALPHA;
+        let half = if smooth {
+            Vector3 { x: 0.0, y: 0.0, z: 1.0 }
+        } else {
+            sample_ggx_vndf(&view, self.alpha(), u1, u2)
+        };
+        let fresnel = fresnel_dielectric(view.dot(&half), eta);
+        let reflected = u_lobe < fresnel;
+        let light = if reflected { reflect(&view, &half) } else { refract(&view, &half, eta)? };
+        if smooth {
+            // choosing the lobe by its fresnel weight cancels the fresnel term
+            let weight = if reflected { 1.0 } else { 1.0 / (eta * eta) };
+            return Some(BsdfSample {
+                direction: to_world(&light, &context.normal),
+                weight: Color {
+                    red: weight,
+                    green: weight,
+                    blue: weight,
+                },
+                pdf: if reflected { fresnel } else { 1.0 - fresnel },
+                specular: true,
+            });
+        }
+        if (light.z > 0.0) != reflected {
+            return None;
+        }
+        weighted_sample(self, context, to_world(&light, &context.normal))
+    }
+}
+
+impl ShadingModel for Dielectric {
+    fn shade(&self, context: &ShadingContext, lights: &[LightSample]) -> Color {
+        shade_bsdf(self, context, lights)
+    }
+
+    fn bsdf(&self) -> Option<&dyn Bsdf> {
+        Some(self)
+    }
+}
+
+#[cfg(test)]
+mod test_bsdf {
+    use super::*;
+    use crate::sampling::Rng;
+
+    const WHITE: Color = Color {
+        red: 255.0,
+        green: 255.0,
+        blue: 255.0,
+    };
+
+    fn context(albedo: &Color, view_z: f64) -> ShadingContext<'_> {
+        ShadingContext {
+            normal: Vector3 { x: 0.0, y: 0.0, z: 1.0 },
+            view_direction: Vector3 {
+                x: (1.0 - view_z * view_z).sqrt(),
+                y: 0.0,
+                z: view_z,
+            },
+            albedo,
+            front_face: true,
+            wavelength: None,
+        }
+    }
+
+    // average sample weight, the fraction of the energy reflected under uniform white light
+    fn furnace(bsdf: &dyn Bsdf, context: &ShadingContext, n: u32) -> f64 {
+        let mut rng = Rng::new(3, 0);
+        let mut sum = 0.0;
+        for _ in 0..n {
+            if let Some(sample) = bsdf.sample(context, rng.next_f64(), rng.next_f64(), rng.next_f64()) {
+                sum += sample.weight.green;
+            }
+        }
+        sum / n as f64
+    }
+
+    #[test]
+    fn lambert_bsdf_matches_lambert_shading() {
+        let albedo = Color {
+            red: 200.0,
+            green: 100.0,
+            blue: 50.0,
+        };
+        let context = context(&albedo, 0.8);
+        let lights = vec![LightSample {
+            direction: Vector3 { x: 0.0, y: 0.6, z: 0.8 },
+            color: WHITE,
+        }];
+        let a = shade_bsdf(&Lambert, &context, &lights);
+        let b = Lambert.shade(&context, &lights);
+        assert!((a.red - b.red).abs() < 1e-9);
+        assert!((a.blue - b.blue).abs() < 1e-9);
+        assert!((furnace(&Lambert, &context, 1000) - 100.0 / 255.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn sample_weight_is_eval_over_pdf() {
+        let material = MetallicRoughness {
+            metallic: 0.3,
+            roughness: 0.4,
+        };
+        let context = context(&WHITE, 0.6);
+        let mut rng = Rng::new(1, 1);
+        for _ in 0..100 {
+            if let Some(sample) = material.sample(&context, rng.next_f64(), rng.next_f64(), rng.next_f64()) {
+                let f = material.eval(&context, &sample.direction);
+                let pdf = material.pdf(&context, &sample.direction);
+                assert!((sample.pdf - pdf).abs() < 1e-9);
+                assert!((sample.weight.red - f.red / pdf).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn pdf_integrates_to_sampled_fraction() {
+        let material = MetallicRoughness {
+            metallic: 0.5,
+            roughness: 0.6,
+        };
+        let context = context(&WHITE, 0.7);
+        // uniform hemisphere estimate of the integral of the pdf
+        let mut rng = Rng::new(5, 2);
+        let n = 200000;
+        let mut integral = 0.0;
+        let mut sampled = 0;
+        for _ in 0..n {
+            let z = rng.next_f64();
+            let r = (1.0 - z * z).sqrt();
+            let phi = 2.0 * PI * rng.next_f64();
+            let direction = Vector3 {
+                x: r * phi.cos(),
+                y: r * phi.sin(),
+                z,
+            };
+            integral += material.pdf(&context, &direction) * 2.0 * PI;
+            if material.sample(&context, rng.next_f64(), rng.next_f64(), rng.next_f64()).is_some() {
+                sampled += 1;
+            }
+        }
+        // reflections off microfacets can point below the horizon, those samples are dropped
+        let fraction = sampled as f64 / n as f64;
+        assert!(fraction < 1.0);
+        assert!((integral / n as f64 - fraction).abs() < 0.01);
+    }
+
+    #[test]
+    fn white_metal_conserves_energy() {
+        let mut previous = f64::INFINITY;
+        for &roughness in &[0.1, 0.5, 1.0] {
+            let material = MetallicRoughness { metallic: 1.0, roughness };
+            let reflected = furnace(&material, &context(&WHITE, 0.9), 20000);
+            // single scattering loses energy on rough surfaces but never creates any
+            assert!(reflected <= 1.0 + 1e-6);
+            assert!(reflected < previous);
+            if roughness <= 0.5 {
+                assert!(reflected > 0.85);
+            }
+            previous = reflected;
+        }
+    }
+
+    #[test]
+    fn smooth_metal_reflects_mirror_direction() {
+        let material = MetallicRoughness {
+            metallic: 1.0,
+            roughness: 0.0,
+        };
+        let context = context(&WHITE, 0.8);
+        let sample = material.sample(&context, 0.1, 0.3, 0.7).unwrap();
+        assert!((sample.direction.x + 0.6).abs() < 0.01);
+        assert!((sample.direction.z - 0.8).abs() < 0.01);
+    }
+
+    // like furnace(), transmitted light is scaled back by the squared relative index to compare energy instead of radiance
+    fn dielectric_furnace(material: &Dielectric, context: &ShadingContext, n: u32) -> f64 {
+        let eta = material.relative_eta(context);
+        let mut rng = Rng::new(9, 4);
+        let mut sum = 0.0;
+        for _ in 0..n {
+            if let Some(sample) = material.sample(context, rng.next_f64(), rng.next_f64(), rng.next_f64()) {
+                let transmitted = sample.direction.dot(&context.normal) < 0.0;
+                sum += sample.weight.green * if transmitted { eta * eta } else { 1.0 };
+            }
+        }
+        sum / n as f64
+    }
+
+    #[test]
+    fn fresnel_limits() {
+        // 4% reflection of glass at normal incidence, total reflection at grazing angles
+        assert!((fresnel_dielectric(1.0, 1.5) - 0.04).abs() < 1e-9);
+        assert_eq!(fresnel_dielectric(0.0, 1.5), 1.0);
+        assert_eq!(fresnel_dielectric(0.3, 1.0 / 1.5), 1.0);
+        // a conductor without absorption is a dielectric
+        assert!((fresnel_conductor(0.7, 1.5, 0.0) - fresnel_dielectric(0.7, 1.5)).abs() < 1e-9);
+        assert!(fresnel_conductor(1.0, 0.2, 4.0) > 0.9);
+    }
+
+    #[test]
+    fn metal_presets_are_tinted() {
+        let context = context(&WHITE, 1.0);
+        let gold = Conductor::gold(0.0).sample(&context, 0.5, 0.5, 0.5).unwrap();
+        assert!(gold.specular);
+        assert!(gold.weight.red > gold.weight.blue);
+        let copper = Conductor::copper(0.0).sample(&context, 0.5, 0.5, 0.5).unwrap();
+        assert!(copper.weight.red > copper.weight.green);
+        let aluminium = Conductor::aluminium(0.0).sample(&context, 0.5, 0.5, 0.5).unwrap();
+        assert!(aluminium.weight.blue > 0.9);
+    }
+
+    #[test]
+    fn white_furnace_conductor() {
+        // a strongly absorbing conductor reflects nearly everything, like a white metal
+        for &roughness in &[0.0, 0.3, 0.5] {
+            let conductor = Conductor {
+                eta: [1.0; 3],
+                k: [1e3; 3],
+                roughness,
+            };
+            let reflected = furnace(&conductor, &context(&WHITE, 0.8), 20000);
+            assert!(reflected <= 1.0 + 1e-6);
+            assert!(reflected > 0.85);
+        }
+    }
+
+    #[test]
+    fn white_furnace_dielectric() {
+        for &front_face in &[true, false] {
+            let mut context = context(&WHITE, 0.8);
+            context.front_face = front_face;
+            // the smooth interface splits the energy without any loss
+            let smooth = dielectric_furnace(&Dielectric::glass(0.0), &context, 20000);
+            assert!((smooth - 1.0).abs() < 1e-9);
+            for &roughness in &[0.2, 0.5] {
+                let rough = dielectric_furnace(&Dielectric::glass(roughness), &context, 20000);
+                assert!(rough <= 1.0 + 1e-2);
+                assert!(rough > 0.85);
+            }
+        }
+    }
+
+    #[test]
+    fn rough_dielectric_sample_weight_is_eval_over_pdf() {
+        let material = Dielectric::glass(0.4);
+        let mut context = context(&WHITE, 0.6);
+        let mut rng = Rng::new(2, 8);
+        for &front_face in &[true, false] {
+            context.front_face = front_face;
+            let mut transmitted = 0;
+            for _ in 0..200 {
+                if let Some(sample) = material.sample(&context, rng.next_f64(), rng.next_f64(), rng.next_f64()) {
+                    let f = material.eval(&context, &sample.direction);
+                    let pdf = material.pdf(&context, &sample.direction);
+                    assert!((sample.pdf - pdf).abs() < 1e-9 * pdf.max(1.0));
+                    assert!((sample.weight.red - f.red / pdf).abs() < 1e-9 * sample.weight.red.max(1.0));
+                    if sample.direction.z < 0.0 {
+                        transmitted += 1;
+                    }
+                }
+            }
+            assert!(transmitted > 0);
+        }
+    }
+
+    #[test]
+    fn dispersive_glass_refracts_by_wavelength() {
+        let glass = Dielectric {
+            dispersion: Some(Dispersion::dense_flint()),
+            ..Dielectric::glass(0.0)
+        };
+        let refracted = |wavelength: Option<f64>| {
+            let context = ShadingContext {
+                wavelength,
+                ..context(&WHITE, 0.5)
+            };
+            // the largest lobe value always picks refraction
+            glass.sample(&context, 0.999, 0.5, 0.5).unwrap().direction
+        };
+        let (blue, red, rgb) = (refracted(Some(450.0)), refracted(Some(650.0)), refracted(None));
+        assert!(blue.z < 0.0 && red.z < 0.0);
+        // the denser glass for blue bends it closer to the normal
+        assert!(blue.x.abs() < red.x.abs() - 0.005);
+        // without a wavelength the constant index applies
+        let plain = Dielectric::glass(0.0).sample(&context(&WHITE, 0.5), 0.999, 0.5, 0.5).unwrap().direction;
+        assert!((rgb.x - plain.x).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,344 @@
+use crate::intersection::Ray;
+use crate::point::Point3;
+use crate::sampler::Sampler;
+use crate::scene::Scene;
+use crate::vector::Vector3;
+use std::f64::consts::PI;
+
+/** Thin lens in front of the pinhole camera: points at the focus distance are sharp, the rest is blurred */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ThinLens {
+    pub aperture_radius: f64,
+    /** Distance along the view direction of the plane in focus */
+    pub focus_distance: f64,
+    /** Number of aperture blades shaping the bokeh, below 3 the aperture is round */
+    pub blades: u32,
+    /** Rotation of the blade polygon, in degrees */
+    pub blade_rotation: f64,
+}
+
+impl Default for ThinLens {
+    fn default() -> ThinLens {
+        ThinLens {
+            aperture_radius: 0.05,
+            focus_distance: 5.0,
+            blades: 0,
+            blade_rotation: 0.0,
+        }
+    }
+}
+
+impl ThinLens {
+    /** Uniformly distributed point of the aperture, in the lens plane */
+    pub fn sample_aperture(&self, u1: f64, u2: f64) -> (f64, f64) {
+        if self.blades < 3 {
+            let r = self.aperture_radius * u1.sqrt();
+            let phi = 2.0 * PI * u2;
+            return (r * phi.cos(), r * phi.sin());
+        }
+        // pick one of the triangles between the center and two neighbouring corners, then a point in it
+        let n = self.blades as f64;
+        let blade = (u1 * n).floor().min(n - 1.0);
+        let u1 = u1 * n - blade;
+        let corner = |k: f64| {
+            let angle = self.blade_rotation.to_radians() + 2.0 * PI * k / n;
+            (self.aperture_radius * angle.cos(), self.aperture_radius * angle.sin())
+        };
+        let (a, b) = (corner(blade), corner(blade + 1.0));
+        let s = u1.sqrt();
+        (s * ((1.0 - u2) * a.0 + u2 * b.0), s * ((1.0 - u2) * a.1 + u2 * b.1))
+    }
+
+    /** Moves the start of a pinhole camera ray onto the aperture, keeping the point it reaches at the focus distance */
+    pub fn apply(&self, ray: &Ray, sampler: &mut dyn Sampler) -> Ray {
+        let (u1, u2) = sampler.get_2d();
+        let direction = ray.direction.normalize();
+        if self.aperture_radius <= 0.0 || direction.z >= 0.0 {
+            return Ray {
+                origin: ray.origin.clone(),
+                direction,
+                time: ray.time,
+            };
+        }
+        let t = self.focus_distance / -direction.z;
+        let focus = &ray.origin + &(&direction * t);
+        let (x, y) = self.sample_aperture(u1, u2);
+        let origin = &ray.origin + &Vector3 { x, y, z: 0.0 };
+        Ray {
+            direction: (&focus - &origin).normalize(),
+            origin,
+            time: ray.time,
+        }
+    }
+
+    /** Focuses on the first surface seen through the image center, returns its distance */
+    pub fn autofocus(&mut self, scene: &Scene) -> Option<f64> {
+        let ray = Ray::create_prime_at(scene.width as f64 / 2.0, scene.height as f64 / 2.0, scene);
+        let hit = scene.trace_hit(&ray)?;
+        // distance along the view direction, not along the ray
+        let depth = -(&hit.point - &Point3::zero()).z;
+        self.focus_distance = depth;
+        Some(depth)
+    }
+}
+
+/** Projection turning points of the film into primary rays. Film positions are in pixels from the top left corner,
+cameras sit at the origin looking down -z with +y up. */
+pub trait Camera: Sync {
+    /** Ray at the given time, None where the film shows no direction, like the corners around a fisheye circle */
+    fn generate_ray(&self, film_x: f64, film_y: f64, time: f64, width: u32, height: u32, sampler: &mut dyn Sampler) -> Option<Ray>;
+}
+
+/** Pinhole camera, optionally with a thin lens */
+pub struct PerspectiveCamera {
+    /** Vertical field of view in degrees */
+    pub fov: f64,
+    pub lens: Option<ThinLens>,
+}
+
+impl Camera for PerspectiveCamera {
+    fn generate_ray(&self, film_x: f64, film_y: f64, time: f64, width: u32, height: u32, sampler: &mut dyn Sampler) -> Option<Ray> {
+        let ray = Ray {
+            time,
+            ..Ray::create_perspective(film_x, film_y, width, height, self.fov)
+        };
+        match &self.lens {
+            Some(lens) => Some(lens.apply(&ray, sampler)),
+            None => Some(ray),
+        }
+    }
+}
+
+/** Parallel rays without perspective distortion, for technical views */
+pub struct OrthographicCamera {
+    /** Height of the viewed region in world units, the width follows from the aspect ratio */
+    pub view_height: f64,
+}
+
+impl Camera for OrthographicCamera {
+    fn generate_ray(&self, film_x: f64, film_y: f64, time: f64, width: u32, height: u32, _sampler: &mut dyn Sampler) -> Option<Ray> {
+        let aspect_ratio = width as f64 / height as f64;
+        let x = (film_x / width as f64 * 2.0 - 1.0) * aspect_ratio * self.view_height / 2.0;
+        let y = (1.0 - film_y / height as f64 * 2.0) * self.view_height / 2.0;
+        Some(Ray {
+            origin: Point3 { x, y, z: 0.0 },
+            direction: Vector3 { x: 0.0, y: 0.0, z: -1.0 },
+            time,
+        })
+    }
+}
+
+/** Equidistant fisheye: the angle to the view direction grows linearly with the distance to the image center */
+pub struct FisheyeCamera {
+    /** Field of view across the image circle, in degrees, may exceed 180 */
+    pub fov: f64,
+}
+
+impl Camera for FisheyeCamera {
+    fn generate_ray(&self, film_x: f64, film_y: f64, time: f64, width: u32, height: u32, _sampler: &mut dyn Sampler) -> Option<Ray> {
+        // the image circle touches the shorter side
+        let radius = width.min(height) as f64 / 2.0;
+        let (dx, dy) = ((film_x - width as f64 / 2.0) / radius, (height as f64 / 2.0 - film_y) / radius);
+        let r = (dx * dx + dy * dy).sqrt();
+        if r > 1.0 {
+            return None;
+        }
+        let theta = r * self.fov.to_radians() / 2.0;
+        let phi = dy.atan2(dx);
+        Some(Ray {
+            origin: Point3::zero(),
+            direction: Vector3 {
+                x: theta.sin() * phi.cos(),
+                y: theta.sin() * phi.sin(),
+                z: -theta.cos(),
+            },
+            time,
+        })
+    }
+}
+
+/** Full 360° panorama in the layout of environment maps, straight ahead in the middle */
+pub struct EquirectangularCamera;
+
+impl Camera for EquirectangularCamera {
+    fn generate_ray(&self, film_x: f64, film_y: f64, time: f64, width: u32, height: u32, _sampler: &mut dyn Sampler) -> Option<Ray> {
+        let phi = (film_x / width as f64 - 0.5) * 2.0 * PI;
+        let theta = film_y / height as f64 * PI;
+        Some(Ray {
+            origin: Point3::zero(),
+            direction: Vector3 {
+                x: theta.sin() * phi.sin(),
+                y: theta.cos(),
+                z: -theta.sin() * phi.cos(),
+            },
+            time,
+        })
+    }
+}
+
+/** Left and right eye side by side, each half of the image rendered by the eye camera from a shifted position */
+pub struct StereoCamera {
+    pub eye: Box<dyn Camera>,
+    /** Distance between the eyes */
+    pub eye_separation: f64,
+    /** Distance at which the views of both eyes meet, objects there appear on the screen plane */
+    pub convergence_distance: f64,
+}
+
+impl Camera for StereoCamera {
+    fn generate_ray(&self, film_x: f64, film_y: f64, time: f64, width: u32, height: u32, sampler: &mut dyn Sampler) -> Option<Ray> {
+        let half = (width / 2).max(1);
+        let (film_x, side) = if film_x < half as f64 { (film_x, -1.0) } else { (film_x - half as f64, 1.0) };
+        let ray = self.eye.generate_ray(film_x, film_y, time, half, height, sampler)?;
+        // both eyes aim at the point the center ray reaches at the convergence distance
+        let target = &ray.origin + &(&ray.direction.normalize() * self.convergence_distance);
+        let origin = &ray.origin
+            + &Vector3 {
+                x: side * self.eye_separation / 2.0,
+                y: 0.0,
+                z: 0.0,
+            };
+        Some(Ray {
+            direction: (&target - &origin).normalize(),
+            origin,
+            time: ray.time,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test_camera {
+    use super::*;
+    use crate::material::Material;
+    use crate::sampler::IndependentSampler;
+    use crate::scene::{Element, Sphere};
+    use std::sync::Arc;
+
+    #[test]
+    fn focused_points_stay_sharp() {
+        let lens = ThinLens {
+            aperture_radius: 0.2,
+            focus_distance: 4.0,
+            ..Default::default()
+        };
+        let pinhole = Ray {
+            origin: Point3::zero(),
+            direction: Vector3 { x: 0.3, y: -0.1, z: -1.0 },
+            time: 0.0,
+        };
+        let focus = &pinhole.direction * 4.0;
+        let mut sampler = IndependentSampler::new(0);
+        for _ in 0..32 {
+            let ray = lens.apply(&pinhole, &mut sampler);
+            assert!(ray.origin.z == 0.0 && (ray.origin.x * ray.origin.x + ray.origin.y * ray.origin.y).sqrt() <= 0.2);
+            // every ray passes the same point on the focus plane
+            let t = 4.0 / -ray.direction.z;
+            let reached = &ray.origin + &(&ray.direction * t);
+            assert!((reached.x - focus.x).abs() < 1e-9 && (reached.y - focus.y).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn blades_shape_the_aperture() {
+        let hexagon = ThinLens {
+            aperture_radius: 1.0,
+            blades: 6,
+            ..Default::default()
+        };
+        // the inner radius of a hexagon is cos(30°) of its corner radius
+        let inner = (PI / 6.0).cos();
+        let mut outside_circle = 0;
+        let mut sampler = IndependentSampler::new(4);
+        for _ in 0..2000 {
+            let (u1, u2) = sampler.get_2d();
+            let (x, y) = hexagon.sample_aperture(u1, u2);
+            let angle = y.atan2(x).rem_euclid(PI / 3.0) - PI / 6.0;
+            // distance to the nearest edge along its normal
+            assert!((x * x + y * y).sqrt() * angle.cos() <= inner + 1e-9);
+            if (x * x + y * y).sqrt() > inner {
+                outside_circle += 1;
+            }
+        }
+        // the corners are reached too
+        assert!(outside_circle > 0);
+    }
+
+    #[test]
+    fn autofocus_finds_the_center_object() {
+        let mut scene = Scene {
+            width: 5,
+            height: 5,
+            fov: 60.0,
+            elements: vec![Element::Sphere(Sphere {
+                center: Point3 { x: 0.0, y: 0.0, z: -7.0 },
+                radius: 1.0,
+                material: Arc::new(Material::default()),
+            })],
+            lights: Vec::new(),
+            medium: None,
+        };
+        let mut lens = ThinLens::default();
+        assert!((lens.autofocus(&scene).unwrap() - 6.0).abs() < 1e-6);
+        assert!((lens.focus_distance - 6.0).abs() < 1e-6);
+        scene.elements.clear();
+        assert_eq!(lens.autofocus(&scene), None);
+        assert!((lens.focus_distance - 6.0).abs() < 1e-6);
+    }
+
+    fn generate(camera: &dyn Camera, film_x: f64, film_y: f64, width: u32, height: u32) -> Option<Ray> {
+        camera.generate_ray(film_x, film_y, 0.0, width, height, &mut IndependentSampler::new(0))
+    }
+
+    #[test]
+    fn perspective_matches_prime_rays() {
+        let camera = PerspectiveCamera { fov: 60.0, lens: None };
+        let ray = generate(&camera, 2.5, 7.25, 8, 10).unwrap();
+        let prime = Ray::create_perspective(2.5, 7.25, 8, 10, 60.0);
+        assert_eq!((ray.direction.x, ray.direction.y, ray.direction.z), (prime.direction.x, prime.direction.y, prime.direction.z));
+    }
+
+    #[test]
+    fn orthographic_rays_are_parallel() {
+        let camera = OrthographicCamera { view_height: 4.0 };
+        let corner = generate(&camera, 0.0, 0.0, 20, 10).unwrap();
+        let center = generate(&camera, 10.0, 5.0, 20, 10).unwrap();
+        assert_eq!((corner.origin.x, corner.origin.y), (-4.0, 2.0));
+        assert_eq!((center.origin.x, center.origin.y), (0.0, 0.0));
+        assert_eq!(corner.direction.z, -1.0);
+        assert_eq!(corner.direction.x, center.direction.x);
+    }
+
+    #[test]
+    fn fisheye_and_panorama_cover_wide_angles() {
+        let fisheye = FisheyeCamera { fov: 180.0 };
+        assert!(generate(&fisheye, 0.0, 0.0, 10, 10).is_none());
+        let center = generate(&fisheye, 5.0, 5.0, 10, 10).unwrap();
+        assert!((center.direction.z + 1.0).abs() < 1e-12);
+        // the rim of the image circle looks sideways
+        let rim = generate(&fisheye, 10.0, 5.0, 10, 10).unwrap();
+        assert!((rim.direction.x - 1.0).abs() < 1e-12 && rim.direction.z.abs() < 1e-12);
+        let top = generate(&fisheye, 5.0, 2.5, 10, 10).unwrap();
+        assert!((top.direction.y - (PI / 4.0).sin()).abs() < 1e-12);
+        let panorama = EquirectangularCamera;
+        let behind = generate(&panorama, 0.0, 5.0, 20, 10).unwrap();
+        assert!((behind.direction.z - 1.0).abs() < 1e-12);
+        let up = generate(&panorama, 7.0, 0.0, 20, 10).unwrap();
+        assert!((up.direction.y - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn stereo_eyes_converge() {
+        let camera = StereoCamera {
+            eye: Box::new(PerspectiveCamera { fov: 60.0, lens: None }),
+            eye_separation: 0.2,
+            convergence_distance: 3.0,
+        };
+        // the centers of both halves
+        let left = generate(&camera, 5.0, 5.0, 20, 10).unwrap();
+        let right = generate(&camera, 15.0, 5.0, 20, 10).unwrap();
+        assert!((left.origin.x + 0.1).abs() < 1e-12 && (right.origin.x - 0.1).abs() < 1e-12);
+        let meet = |ray: &Ray| &ray.origin + &(&ray.direction * (3.0 / -ray.direction.z));
+        let (a, b) = (meet(&left), meet(&right));
+        assert!(a.x.abs() < 1e-9 && b.x.abs() < 1e-9 && (a.z - b.z).abs() < 1e-9);
+    }
+}
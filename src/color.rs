@@ -0,0 +1,156 @@
+use std::ops::{Add, AddAssign, Mul};
+
+/** Linear color, 255 is the reflectance of a white surface */
+#[derive(Clone, Debug)]
+pub struct Color {
+    pub red: f64,
+    pub green: f64,
+    pub blue: f64,
+}
+
+impl Color {
+    pub fn from_one(v: f64) -> Color {
+        Color {
+            red: v,
+            green: v,
+            blue: v,
+        }
+    }
+
+    /** Rec. 709 luminance, the brightness the eye perceives */
+    pub fn luminance(&self) -> f64 {
+        0.2126 * self.red + 0.7152 * self.green + 0.0722 * self.blue
+    }
+
+    /** Color between the two, self at t = 0 and other at t = 1 */
+    pub fn lerp(&self, other: &Color, t: f64) -> Color {
+        &(self * (1.0 - t)) + &(other * t)
+    }
+
+    /** Decodes an 8 bit sRGB pixel into the linear working space */
+    pub fn from_srgb8(rgb: [u8; 3]) -> Color {
+        let decode = |v: u8| 255.0 * TransferFunction::Srgb.decode(v as f64 / 255.0);
+        Color {
+            red: decode(rgb[0]),
+            green: decode(rgb[1]),
+            blue: decode(rgb[2]),
+        }
+    }
+
+    /** 8 bit pixel of the color, clamped into the displayable range and encoded */
+    pub fn to_rgb8(&self, encoding: TransferFunction) -> [u8; 3] {
+        let encode = |v: f64| (255.0 * encoding.encode((v / 255.0).clamp(0.0, 1.0))).round() as u8;
+        [encode(self.red), encode(self.green), encode(self.blue)]
+    }
+}
+
+impl Add<&Color> for &Color {
+    type Output = Color;
+
+    fn add(self, other: &Color) -> Color {
+        Color {
+            red: self.red + other.red,
+            green: self.green + other.green,
+            blue: self.blue + other.blue,
+        }
+    }
+}
+
+impl AddAssign<&Color> for Color {
+    fn add_assign(&mut self, other: &Color) {
+        self.red += other.red;
+        self.green += other.green;
+        self.blue += other.blue;
+    }
+}
+
+impl Mul<&Color> for &Color {
+    type Output = Color;
+
+    fn mul(self, other: &Color) -> Color {
+        Color {
+            red: self.red * other.red,
+            green: self.green * other.green,
+            blue: self.blue * other.blue,
+        }
+    }
+}
+
+impl Mul<f64> for &Color {
+    type Output = Color;
+
+    fn mul(self, other: f64) -> Color {
+        Color {
+            red: self.red * other,
+            green: self.green * other,
+            blue: self.blue * other,
+        }
+    }
+}
+
+/** Encoding between linear values in [0, 1] and the values stored in an image */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TransferFunction {
+    /** Values stored as they are, for data like normal maps */
+    Linear,
+    /** The piecewise sRGB curve, a linear toe followed by a 2.4 power */
+    Srgb,
+}
+
+impl TransferFunction {
+    /** Linear value to stored value (the OETF) */
+    pub fn encode(&self, v: f64) -> f64 {
+        match self {
+            TransferFunction::Linear => v,
+            TransferFunction::Srgb if v <= 0.0031308 => 12.92 * v,
+            TransferFunction::Srgb => 1.055 * v.powf(1.0 / 2.4) - 0.055,
+        }
+    }
+
+    /** Stored value to linear value */
+    pub fn decode(&self, v: f64) -> f64 {
+        match self {
+            TransferFunction::Linear => v,
+            TransferFunction::Srgb if v <= 0.04045 => v / 12.92,
+            TransferFunction::Srgb => ((v + 0.055) / 1.055).powf(2.4),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_color {
+    use super::*;
+
+    #[test]
+    fn srgb_round_trips_every_8_bit_value() {
+        for v in 0..=255u8 {
+            let linear = Color::from_srgb8([v, v, v]);
+            assert_eq!(linear.to_rgb8(TransferFunction::Srgb), [v, v, v]);
+        }
+        // middle gray of a display is about a fifth of white in linear light
+        assert!((Color::from_srgb8([128, 128, 128]).red / 255.0 - 0.2158).abs() < 1e-4);
+        assert_eq!(Color::from_one(0.5 * 255.0).to_rgb8(TransferFunction::Srgb), [188, 188, 188]);
+        assert_eq!(Color::from_one(1000.0).to_rgb8(TransferFunction::Linear), [255, 255, 255]);
+    }
+
+    #[test]
+    fn arithmetic_works_per_channel() {
+        let a = Color {
+            red: 1.0,
+            green: 2.0,
+            blue: 3.0,
+        };
+        let b = Color {
+            red: 4.0,
+            green: 5.0,
+            blue: 6.0,
+        };
+        assert_eq!((&a + &b).blue, 9.0);
+        assert_eq!((&a * &b).green, 10.0);
+        assert_eq!((&a * 2.0).red, 2.0);
+        assert_eq!(a.lerp(&b, 0.25).red, 1.75);
+        let mut sum = a.clone();
+        sum += &b;
+        assert_eq!(sum.green, 7.0);
+    }
+}
@@ -0,0 +1,95 @@
+use crate::load_geo_scene::GeoData;
+use crate::material::Displacement;
+use crate::shading::sample_height;
+use crate::subdivision::smooth_normals;
+use crate::vector::Vector3;
+use std::io::{Error, ErrorKind};
+
+/** Moves every vertex along its normal by the height texture and recomputes the normals, meshes without texture coordinates are an error */
+pub fn displace(geo_data: &mut GeoData, displacement: &Displacement) -> std::io::Result<()> {
+    if geo_data.st_array.len() != geo_data.vertex_index_array.len() {
+        return Err(Error::new(ErrorKind::InvalidData, "mesh has no texture coordinates for the displacement"));
+    }
+    let face_vertex_normals = if geo_data.normal_array.len() == geo_data.vertex_index_array.len() {
+        geo_data.normal_array.clone()
+    } else {
+        smooth_normals(geo_data)
+    };
+
+    // a vertex shared by several faces takes the average normal and its first texture coordinate
+    let num_vertices = geo_data.vertex_array.len();
+    let mut normals: Vec<Vector3> = vec![Vector3::zero(); num_vertices];
+    let mut st: Vec<Option<(f64, f64)>> = vec![None; num_vertices];
+    for (k, &v) in geo_data.vertex_index_array.iter().enumerate() {
+        normals[v] = normals[v].clone() + face_vertex_normals[k].to_vector();
+        if st[v].is_none() {
+            st[v] = Some(geo_data.st_array[k]);
+        }
+    }
+
+    for v in 0..num_vertices {
+        if let Some((s, t)) = st[v] {
+            let height = sample_height(&displacement.texture, s, t) - displacement.midlevel;
+            let offset = &normals[v].normalize() * (height * displacement.scale);
+            geo_data.vertex_array[v] = &geo_data.vertex_array[v] + &offset;
+        }
+    }
+    geo_data.normal_array = smooth_normals(geo_data);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test_displacement {
+    use super::*;
+    use crate::point::Point3;
+    use crate::scene::Color;
+    use crate::texture::Texture;
+
+    fn quad() -> GeoData {
+        GeoData {
+            num_face: 1,
+            face_index_array: vec![4],
+            vertex_index_array: vec![0, 1, 2, 3],
+            vertex_array: vec![
+                Point3 { x: 0.0, y: 0.0, z: 0.0 },
+                Point3 { x: 1.0, y: 0.0, z: 0.0 },
+                Point3 { x: 1.0, y: 1.0, z: 0.0 },
+                Point3 { x: 0.0, y: 1.0, z: 0.0 },
+            ],
+            normal_array: Vec::new(),
+            st_array: vec![(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)],
+        }
+    }
+
+    fn white_displacement() -> Displacement {
+        Displacement {
+            texture: Texture::from_fn(1, 1, |_, _| Color {
+                red: 255.0,
+                green: 255.0,
+                blue: 255.0,
+            }),
+            scale: 0.5,
+            midlevel: 0.0,
+        }
+    }
+
+    #[test]
+    fn displace_flat_quad() {
+        let mut geo_data = quad();
+        displace(&mut geo_data, &white_displacement()).unwrap();
+        for p in &geo_data.vertex_array {
+            assert!((p.z - 0.5).abs() < 1e-12);
+        }
+        assert_eq!(geo_data.normal_array.len(), 4);
+    }
+
+    #[test]
+    fn displacement_needs_texture_coordinates() {
+        let mut geo_data = GeoData {
+            st_array: Vec::new(),
+            ..quad()
+        };
+        assert!(displace(&mut geo_data, &white_displacement()).is_err());
+        assert!(geo_data.vertex_array.iter().all(|p| p.z == 0.0));
+    }
+}
@@ -0,0 +1,242 @@
+use crate::sampling::Distribution1D;
+use crate::scene::Color;
+use crate::vector::Vector3;
+use std::f64::consts::PI;
+
+/** Light arriving from infinitely far away, stored as an equirectangular image */
+pub struct EnvironmentLight {
+    pub width: usize,
+    pub height: usize,
+    // linear radiance on the 0-255 color scale, row by row from the top
+    pixels: Vec<Color>,
+    // rows picked by their summed luminance, then a pixel within the row
+    rows: Distribution1D,
+    columns: Vec<Distribution1D>,
+    average_luminance: f64,
+    /** Rotation around the vertical axis, in degrees */
+    pub rotation: f64,
+    /** Factor applied to the image radiance */
+    pub intensity: f64,
+    /** Shadow rays per shaded point for integrators that sample every light */
+    pub samples: u32,
+}
+
+/** Direction towards the environment, with the radiance arriving from it and its solid angle density */
+pub struct EnvironmentSample {
+    pub direction: Vector3,
+    pub radiance: Color,
+    pub pdf: f64,
+}
+
+impl EnvironmentLight {
+    /** Image with +y at the top row and -z in the middle column */
+    pub fn new(width: usize, height: usize, pixels: Vec<Color>) -> EnvironmentLight {
+        assert_eq!(pixels.len(), width * height, "environment needs width * height pixels");
+        let mut columns = Vec::with_capacity(height);
+        let mut row_sums = Vec::with_capacity(height);
+        let mut average_luminance = 0.0;
+        for y in 0..height {
+            let (top, bottom) = (PI * y as f64 / height as f64, PI * (y + 1) as f64 / height as f64);
+            let row_solid_angle = 2.0 * PI * (top.cos() - bottom.cos());
+            let row_luminance: f64 = pixels[y * width..(y + 1) * width].iter().map(Color::luminance).sum();
+            average_luminance += row_luminance / width as f64 * row_solid_angle / (4.0 * PI);
+            // rows near the poles cover a smaller solid angle
+            let sin_theta = (PI * (y as f64 + 0.5) / height as f64).sin();
+            let row: Vec<f64> = pixels[y * width..(y + 1) * width].iter().map(|p| p.luminance() * sin_theta).collect();
+            let distribution = Distribution1D::new(row);
+            row_sums.push(distribution.integral);
+            columns.push(distribution);
+        }
+        EnvironmentLight {
+            width,
+            height,
+            pixels,
+            rows: Distribution1D::new(row_sums),
+            columns,
+            average_luminance,
+            rotation: 0.0,
+            intensity: 1.0,
+            samples: 1,
+        }
+    }
+
+    /** Equirectangular Radiance .hdr or OpenEXR file, a value of 1 is white */
+    pub fn load(path: &str) -> image::ImageResult<EnvironmentLight> {
+        let image = image::open(path)?.to_rgb32f();
+        let pixels = image
+            .pixels()
+            .map(|p| Color {
+                red: p[0] as f64 * 255.0,
+                green: p[1] as f64 * 255.0,
+                blue: p[2] as f64 * 255.0,
+            })
+            .collect();
+        Ok(EnvironmentLight::new(image.width() as usize, image.height() as usize, pixels))
+    }
+
+    /** Single colored sky, useful as a uniform ambient light */
+    pub fn constant(color: Color) -> EnvironmentLight {
+        EnvironmentLight::new(1, 1, vec![color])
+    }
+
+    // turns a world direction into image coordinates in [0, 1)
+    fn uv_at(&self, direction: &Vector3) -> (f64, f64) {
+        let d = direction.normalize();
+        let (sin_r, cos_r) = self.rotation.to_radians().sin_cos();
+        let x = d.x * cos_r - d.z * sin_r;
+        let z = d.x * sin_r + d.z * cos_r;
+        let u = 0.5 + x.atan2(-z) / (2.0 * PI);
+        let v = d.y.clamp(-1.0, 1.0).acos() / PI;
+        (u.rem_euclid(1.0), v)
+    }
+
+    fn direction_at(&self, u: f64, v: f64) -> Vector3 {
+        let phi = (u - 0.5) * 2.0 * PI;
+        let theta = v * PI;
+        let (x, y, z) = (theta.sin() * phi.sin(), theta.cos(), -theta.sin() * phi.cos());
+        // the inverse of the rotation in uv_at
+        let (sin_r, cos_r) = self.rotation.to_radians().sin_cos();
+        Vector3 {
+            x: x * cos_r + z * sin_r,
+            y,
+            z: -x * sin_r + z * cos_r,
+        }
+    }
+
+    fn pixel(&self, u: f64, v: f64) -> (usize, usize) {
+        let x = ((u * self.width as f64) as usize).min(self.width - 1);
+        let y = ((v * self.height as f64) as usize).min(self.height - 1);
+        (x, y)
+    }
+
+    /** Radiance arriving along a ray travelling in the opposite direction */
+    pub fn radiance(&self, direction: &Vector3) -> Color {
+        let (u, v) = self.uv_at(direction);
+        let (x, y) = self.pixel(u, v);
+        &self.pixels[y * self.width + x] * self.intensity
+    }
+
+    /** Direction picked in proportion to the luminance of the image */
+    pub fn sample(&self, u1: f64, u2: f64) -> Option<EnvironmentSample> {
+        let (v, row_pdf, y) = self.rows.sample(u1);
+        let (u, column_pdf, _) = self.columns[y].sample(u2);
+        let sin_theta = (v * PI).sin();
+        if row_pdf * column_pdf == 0.0 || sin_theta <= 0.0 {
+            return None;
+        }
+        let direction = self.direction_at(u, v);
+        Some(EnvironmentSample {
+            radiance: self.radiance(&direction),
+            direction,
+            // from the image square to the sphere
+            pdf: row_pdf * column_pdf / (2.0 * PI * PI * sin_theta),
+        })
+    }
+
+    /** Density of sample() returning the direction, over solid angle */
+    pub fn pdf(&self, direction: &Vector3) -> f64 {
+        let (u, v) = self.uv_at(direction);
+        let sin_theta = (v * PI).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        let (x, y) = self.pixel(u, v);
+        self.rows.pdf(y) * self.columns[y].pdf(x) / (2.0 * PI * PI * sin_theta)
+    }
+
+    /** Luminance averaged over all directions */
+    pub fn average_luminance(&self) -> f64 {
+        self.average_luminance * self.intensity
+    }
+}
+
+#[cfg(test)]
+mod test_environment {
+    use super::*;
+    use crate::sampling::Rng;
+
+    fn gradient() -> EnvironmentLight {
+        // brighter towards the right, with one very bright pixel
+        let (width, height) = (16, 8);
+        let mut pixels: Vec<Color> = (0..width * height)
+            .map(|i| {
+                let v = (i % width) as f64 * 10.0;
+                Color {
+                    red: v,
+                    green: v,
+                    blue: v,
+                }
+            })
+            .collect();
+        pixels[3 * width + 5] = Color {
+            red: 5000.0,
+            green: 5000.0,
+            blue: 5000.0,
+        };
+        EnvironmentLight::new(width, height, pixels)
+    }
+
+    #[test]
+    fn directions_map_back_to_themselves() {
+        let mut light = gradient();
+        light.rotation = 30.0;
+        for (u, v) in [(0.1, 0.2), (0.5, 0.5), (0.9, 0.7)] {
+            let (u2, v2) = light.uv_at(&light.direction_at(u, v));
+            assert!((u - u2).abs() < 1e-9 && (v - v2).abs() < 1e-9);
+        }
+        // the middle of the unrotated image lies straight ahead
+        let forward = EnvironmentLight::constant(Color {
+            red: 1.0,
+            green: 1.0,
+            blue: 1.0,
+        })
+        .direction_at(0.5, 0.5);
+        assert!((forward.z + 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn importance_sampling_integrates_radiance() {
+        // the sampled estimate of the irradiance over the whole sphere matches a uniform estimate
+        let light = gradient();
+        let mut rng = Rng::new(3, 0);
+        let n = 20000;
+        let mut sampled = 0.0;
+        for _ in 0..n {
+            if let Some(sample) = light.sample(rng.next_f64(), rng.next_f64()) {
+                assert!((sample.pdf - light.pdf(&sample.direction)).abs() < 1e-6 * sample.pdf);
+                sampled += sample.radiance.red / sample.pdf;
+            }
+        }
+        sampled /= n as f64;
+        let expected = 4.0 * PI * light.average_luminance();
+        assert!((sampled - expected).abs() < 0.01 * expected, "{} {}", sampled, expected);
+    }
+
+    #[test]
+    fn constant_sky() {
+        let light = EnvironmentLight::constant(Color {
+            red: 100.0,
+            green: 100.0,
+            blue: 100.0,
+        });
+        assert!((light.average_luminance() - 100.0).abs() < 1e-9);
+        let up = Vector3 { x: 0.0, y: 1.0, z: 0.0 };
+        assert_eq!(light.radiance(&up).green, 100.0);
+        let side = Vector3 { x: 1.0, y: 0.0, z: 0.0 };
+        assert!((light.pdf(&side) - 1.0 / (2.0 * PI * PI)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn loads_hdr_files() {
+        let mut image = image::Rgb32FImage::new(4, 2);
+        image.put_pixel(1, 0, image::Rgb([2.0, 1.0, 0.5]));
+        let path = std::env::temp_dir().join("raytracer_environment_test.hdr");
+        image::DynamicImage::ImageRgb32F(image).save(&path).unwrap();
+        let light = EnvironmentLight::load(path.to_str().unwrap()).unwrap();
+        assert_eq!((light.width, light.height), (4, 2));
+        // pixel (1, 0) looks upwards, a quarter turn to the left of straight ahead
+        let radiance = light.radiance(&light.direction_at(0.375, 0.25));
+        assert!((radiance.red - 510.0).abs() < 5.0 && (radiance.blue - 127.5).abs() < 2.0);
+        assert!(EnvironmentLight::load("missing.exr").is_err());
+    }
+}
@@ -0,0 +1,102 @@
+use std::f64::consts::PI;
+
+/** Reconstruction filter weighting the samples around a pixel center, distances are in pixels */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Filter {
+    /** Equal weights, a radius of 0.5 only uses the samples inside the pixel */
+    Box { radius: f64 },
+    /** Weights falling linearly to zero at the radius */
+    Tent { radius: f64 },
+    /** Gaussian with falloff alpha, shifted to reach zero at the radius */
+    Gaussian { radius: f64, alpha: f64 },
+    /** Mitchell-Netravali cubic, b = c = 1/3 is the recommended compromise between blur and ringing */
+    Mitchell { radius: f64, b: f64, c: f64 },
+    /** Sinc windowed by a wider sinc, tau is the number of lobes */
+    Lanczos { radius: f64, tau: f64 },
+}
+
+impl Default for Filter {
+    fn default() -> Filter {
+        Filter::Box { radius: 0.5 }
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-5 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+fn mitchell(x: f64, b: f64, c: f64) -> f64 {
+    let x = x.abs();
+    if x > 2.0 {
+        0.0
+    } else if x > 1.0 {
+        ((-b - 6.0 * c) * x * x * x + (6.0 * b + 30.0 * c) * x * x + (-12.0 * b - 48.0 * c) * x + (8.0 * b + 24.0 * c)) / 6.0
+    } else {
+        ((12.0 - 9.0 * b - 6.0 * c) * x * x * x + (-18.0 + 12.0 * b + 6.0 * c) * x * x + (6.0 - 2.0 * b)) / 6.0
+    }
+}
+
+impl Filter {
+    pub fn radius(&self) -> f64 {
+        match *self {
+            Filter::Box { radius } | Filter::Tent { radius } | Filter::Gaussian { radius, .. } => radius,
+            Filter::Mitchell { radius, .. } | Filter::Lanczos { radius, .. } => radius,
+        }
+    }
+
+    // one dimension of the separable filter
+    fn eval_1d(&self, x: f64) -> f64 {
+        let x = x.abs();
+        if x > self.radius() {
+            return 0.0;
+        }
+        match *self {
+            Filter::Box { .. } => 1.0,
+            Filter::Tent { radius } => radius - x,
+            Filter::Gaussian { radius, alpha } => ((-alpha * x * x).exp() - (-alpha * radius * radius).exp()).max(0.0),
+            // the cubic spans [-2, 2]
+            Filter::Mitchell { radius, b, c } => mitchell(2.0 * x / radius, b, c),
+            Filter::Lanczos { tau, .. } => sinc(x) * sinc(x / tau),
+        }
+    }
+
+    /** Weight of a sample offset by (dx, dy) from the pixel center, may be negative for Mitchell and Lanczos */
+    pub fn eval(&self, dx: f64, dy: f64) -> f64 {
+        self.eval_1d(dx) * self.eval_1d(dy)
+    }
+}
+
+#[cfg(test)]
+mod test_filter {
+    use super::*;
+
+    #[test]
+    fn filters_vanish_at_their_radius() {
+        let filters = [
+            Filter::Tent { radius: 1.0 },
+            Filter::Gaussian { radius: 1.5, alpha: 2.0 },
+            Filter::Mitchell {
+                radius: 2.0,
+                b: 1.0 / 3.0,
+                c: 1.0 / 3.0,
+            },
+            Filter::Lanczos { radius: 3.0, tau: 3.0 },
+        ];
+        for filter in filters.iter() {
+            let r = filter.radius();
+            assert!(filter.eval(0.0, 0.0) > 0.0);
+            assert!(filter.eval(r, 0.0).abs() < 1e-9);
+            assert_eq!(filter.eval(r + 0.1, 0.0), 0.0);
+            // symmetric
+            assert_eq!(filter.eval(0.3, -0.2), filter.eval(-0.3, 0.2));
+        }
+        assert_eq!(Filter::default().eval(0.4, 0.4), 1.0);
+        assert_eq!(Filter::default().eval(0.6, 0.0), 0.0);
+        // the negative lobe sharpens edges
+        assert!(Filter::Lanczos { radius: 3.0, tau: 3.0 }.eval(1.5, 0.0) < 0.0);
+    }
+}
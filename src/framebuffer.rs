@@ -0,0 +1,191 @@
+use crate::color::{Color, TransferFunction};
+use crate::integrator::{black, Spectrum};
+use image::{DynamicImage, GenericImage, Rgba};
+
+/** Operator compressing linear radiance into the displayable range, applied per channel */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ToneMapOperator {
+    /** Cuts everything brighter than white */
+    Clamp,
+    /** x / (1 + x), never quite reaches white */
+    Reinhard,
+    /** Reinhard reaching white at the given radiance, relative to the 0-255 scale */
+    ReinhardExtended { white: f64 },
+    /** Narkowicz's fit of the ACES filmic curve */
+    Aces,
+    /** Hable's filmic curve from Uncharted 2, white is the linear value mapped to white */
+    Uncharted2 { white: f64 },
+}
+
+/** How the linear framebuffer becomes an 8 bit image */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ToneMapping {
+    pub operator: ToneMapOperator,
+    /** Exposure in stops, every stop doubles the radiance before the operator */
+    pub exposure: f64,
+    /** Encoding of the tone mapped values in the 8 bit image */
+    pub encoding: TransferFunction,
+}
+
+impl Default for ToneMapping {
+    fn default() -> ToneMapping {
+        ToneMapping {
+            operator: ToneMapOperator::Clamp,
+            exposure: 0.0,
+            encoding: TransferFunction::Srgb,
+        }
+    }
+}
+
+fn hable(x: f64) -> f64 {
+    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+    (x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f) - e / f
+}
+
+impl ToneMapping {
+    /** Maps one channel of linear radiance on the 0-255 scale to the 0-255 display range */
+    pub fn map(&self, value: f64) -> f64 {
+        let value = value * self.exposure.exp2();
+        // the curves are defined on radiance where 1 is white
+        let x = value / 255.0;
+        let mapped = match self.operator {
+            ToneMapOperator::Clamp => return value.clamp(0.0, 255.0),
+            ToneMapOperator::Reinhard => x / (1.0 + x),
+            ToneMapOperator::ReinhardExtended { white } => {
+                let white = white / 255.0;
+                x * (1.0 + x / (white * white)) / (1.0 + x)
+            }
+            ToneMapOperator::Aces => (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14),
+            ToneMapOperator::Uncharted2 { white } => hable(x) / hable(white / 255.0),
+        };
+        (255.0 * mapped).clamp(0.0, 255.0)
+    }
+
+    pub fn map_color(&self, c: &Color) -> Color {
+        Color {
+            red: self.map(c.red),
+            green: self.map(c.green),
+            blue: self.map(c.blue),
+        }
+    }
+}
+
+/** Linear radiance accumulated from weighted samples, row by row */
+pub struct Framebuffer {
+    pub width: u32,
+    pub height: u32,
+    sums: Vec<Spectrum>,
+    weights: Vec<f64>,
+    /** Number of camera samples taken in each pixel */
+    pub sample_counts: Vec<u32>,
+}
+
+impl Framebuffer {
+    pub fn new(width: u32, height: u32) -> Framebuffer {
+        let size = (width * height) as usize;
+        Framebuffer {
+            width,
+            height,
+            sums: vec![black(); size],
+            weights: vec![0.0; size],
+            sample_counts: vec![0; size],
+        }
+    }
+
+    pub fn add_sample(&mut self, x: u32, y: u32, radiance: &Spectrum, weight: f64) {
+        let i = (y * self.width + x) as usize;
+        self.sums[i] += &(radiance * weight);
+        self.weights[i] += weight;
+    }
+
+    /** Weighted mean of the samples of a pixel, black without samples */
+    pub fn pixel(&self, x: u32, y: u32) -> Spectrum {
+        let i = (y * self.width + x) as usize;
+        let weight = self.weights[i];
+        if weight.abs() < 1e-12 {
+            return black();
+        }
+        &self.sums[i] * (1.0 / weight)
+    }
+
+    /** Linear radiance of every pixel */
+    pub fn radiance(&self) -> Vec<Spectrum> {
+        (0..self.height).flat_map(|y| (0..self.width).map(move |x| (x, y))).map(|(x, y)| self.pixel(x, y)).collect()
+    }
+
+    /** 8 bit image of the framebuffer, tone mapped and encoded, the radiance itself stays untouched */
+    pub fn to_image(&self, tone_mapping: &ToneMapping) -> DynamicImage {
+        let mut image = DynamicImage::new_rgb8(self.width, self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let [red, green, blue] = tone_mapping.map_color(&self.pixel(x, y)).to_rgb8(tone_mapping.encoding);
+                image.put_pixel(x, y, Rgba([red, green, blue, 255]));
+            }
+        }
+        image
+    }
+}
+
+#[cfg(test)]
+mod test_framebuffer {
+    use super::*;
+
+    const OPERATORS: [ToneMapOperator; 5] = [
+        ToneMapOperator::Clamp,
+        ToneMapOperator::Reinhard,
+        ToneMapOperator::ReinhardExtended { white: 1020.0 },
+        ToneMapOperator::Aces,
+        ToneMapOperator::Uncharted2 { white: 2856.0 },
+    ];
+
+    #[test]
+    fn operators_are_monotonic_and_bounded() {
+        for operator in OPERATORS.iter() {
+            let tone_mapping = ToneMapping {
+                operator: *operator,
+                ..Default::default()
+            };
+            assert_eq!(tone_mapping.map(0.0), 0.0);
+            let mut previous = 0.0;
+            for i in 1..200 {
+                let mapped = tone_mapping.map(i as f64 * 20.0);
+                assert!(mapped >= previous && mapped <= 255.0, "{:?}", operator);
+                previous = mapped;
+            }
+        }
+        let extended = ToneMapping {
+            operator: ToneMapOperator::ReinhardExtended { white: 1020.0 },
+            ..Default::default()
+        };
+        assert!((extended.map(1020.0) - 255.0).abs() < 1e-9);
+        let filmic = ToneMapping {
+            operator: ToneMapOperator::Uncharted2 { white: 2856.0 },
+            ..Default::default()
+        };
+        assert!((filmic.map(2856.0) - 255.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn exposure_is_applied_at_output_only() {
+        let mut framebuffer = Framebuffer::new(2, 1);
+        let bright = Color {
+            red: 1000.0,
+            green: 100.0,
+            blue: 10.0,
+        };
+        framebuffer.add_sample(0, 0, &bright, 1.0);
+        framebuffer.add_sample(0, 0, &black(), 3.0);
+        assert_eq!(framebuffer.pixel(0, 0).red, 250.0);
+        assert_eq!(framebuffer.pixel(1, 0).red, 0.0);
+        let brighter = ToneMapping {
+            operator: ToneMapOperator::Reinhard,
+            exposure: 1.0,
+            encoding: TransferFunction::Linear,
+        };
+        let rgb = framebuffer.to_image(&brighter).to_rgb8();
+        // 250 doubled is almost twice white, x / (1 + x) of that is just under two thirds
+        assert_eq!(rgb.get_pixel(0, 0).0[0], 169);
+        assert_eq!(rgb.get_pixel(1, 0).0, [0, 0, 0]);
+        assert_eq!(framebuffer.pixel(0, 0).red, 250.0);
+    }
+}
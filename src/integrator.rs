@@ -0,0 +1,605 @@
+use crate::adaptive::{AdaptiveSampling, RunningStats};
+use crate::camera::{Camera, PerspectiveCamera, ThinLens};
+use crate::filter::Filter;
+use crate::framebuffer::{Framebuffer, ToneMapping};
+use crate::intersection::Ray;
+use crate::point::Point3;
+use crate::sampler::{hash, permutation_element, Sampler, SamplerKind};
+use crate::sampling::{cosine_sample_hemisphere, to_world};
+use crate::scene::{Color, Hit, Scene};
+use crate::shading::{visible_lights, ShadingContext};
+use crate::vector::Vector3;
+use image::{DynamicImage, GrayImage, Luma};
+use std::sync::Arc;
+
+/** Radiance carried along a ray, on the 0-255 color scale */
+pub type Spectrum = Color;
+
+/** Strategy computing the light arriving at the camera along a ray */
+pub trait Integrator: Sync {
+    fn li(&self, ray: &Ray, scene: &Scene, sampler: &mut dyn Sampler) -> Spectrum;
+}
+
+/** Where the camera rays of a pixel pass through it */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PixelSampling {
+    /** Every ray through the pixel center, aliased edges */
+    Center,
+    /** Offsets drawn from the sampler, stratified when the sampler is */
+    Jittered,
+    /** One offset per cell of a grid, or per row and column (n-rooks) when the sample count is not a square */
+    Stratified,
+}
+
+pub struct RenderSettings {
+    pub samples_per_pixel: u32,
+    pub seed: u64,
+    pub pixel_sampling: PixelSampling,
+    /** Filter weighting the samples of each pixel and its neighbours */
+    pub filter: Filter,
+    /** Source of the camera offsets and of every random decision of the integrator */
+    pub sampler: SamplerKind,
+    /** Varies the samples per pixel with the noise instead of taking samples_per_pixel everywhere */
+    pub adaptive: Option<AdaptiveSampling>,
+    /** Depth of field of the default perspective camera, a pinhole camera without it */
+    pub lens: Option<ThinLens>,
+    /** Projection of the primary rays, a perspective camera with the field of view of the scene when None */
+    pub camera: Option<Arc<dyn Camera>>,
+    /** Interval the rays' times are spread over, moving elements blur when it is not empty */
+    pub shutter_open: f64,
+    pub shutter_close: f64,
+    /** Turns the linear radiance into display values when an image is written */
+    pub tone_mapping: ToneMapping,
+}
+
+impl Default for RenderSettings {
+    fn default() -> RenderSettings {
+        RenderSettings {
+            samples_per_pixel: 1,
+            seed: 0,
+            pixel_sampling: PixelSampling::Center,
+            filter: Filter::default(),
+            sampler: SamplerKind::Independent,
+            adaptive: None,
+            lens: None,
+            camera: None,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+            tone_mapping: ToneMapping::default(),
+        }
+    }
+}
+
+/** Offset of a sample within the pixel, in [0, 1), jittered by the sampler.
+Stratified samples visit the cells in an order shuffled by the pixel seed, so any first samples are spread over the pixel. */
+pub fn pixel_offset(sampling: PixelSampling, index: u32, count: u32, pixel_seed: u32, sampler: &mut dyn Sampler) -> (f64, f64) {
+    match sampling {
+        PixelSampling::Center => (0.5, 0.5),
+        PixelSampling::Jittered => sampler.get_2d(),
+        PixelSampling::Stratified => {
+            let n = count.max(1);
+            let cell = permutation_element(index % n, n, pixel_seed);
+            let (jx, jy) = sampler.get_2d();
+            let side = (n as f64).sqrt().round() as u32;
+            if side * side == n {
+                (((cell % side) as f64 + jx) / side as f64, ((cell / side) as f64 + jy) / side as f64)
+            } else {
+                // n-rooks, the cell is the column and a second permutation picks its row
+                let row = permutation_element(cell, n, pixel_seed ^ 0x9e37_79b9);
+                ((cell as f64 + jx) / n as f64, (row as f64 + jy) / n as f64)
+            }
+        }
+    }
+}
+
+pub fn black() -> Spectrum {
+    Color {
+        red: 0.0,
+        green: 0.0,
+        blue: 0.0,
+    }
+}
+
+// offset along the normal so rays leaving a surface don't hit it again
+pub fn spawn_ray(hit: &Hit, direction: Vector3) -> Ray {
+    let side = if direction.dot(&hit.geometric_normal) >= 0.0 { 1e-4 } else { -1e-4 };
+    let origin: Point3 = &hit.point + &(&hit.geometric_normal * side);
+    Ray {
+        origin,
+        direction,
+        time: hit.time,
+    }
+}
+
+/** Linear radiance of every pixel, stored row by row */
+pub fn render_radiance(scene: &Scene, integrator: &dyn Integrator, settings: &RenderSettings) -> Vec<Spectrum> {
+    render_framebuffer(scene, integrator, settings).radiance()
+}
+
+/** Linear radiance of every pixel together with the number of samples taken in it */
+pub fn render_radiance_with_counts(scene: &Scene, integrator: &dyn Integrator, settings: &RenderSettings) -> (Vec<Spectrum>, Vec<u32>) {
+    let framebuffer = render_framebuffer(scene, integrator, settings);
+    (framebuffer.radiance(), framebuffer.sample_counts)
+}
+
+/** Accumulates the filtered camera samples, the framebuffer holds linear radiance until it is tone mapped */
+pub fn render_framebuffer(scene: &Scene, integrator: &dyn Integrator, settings: &RenderSettings) -> Framebuffer {
+    let (width, height) = (scene.width as i64, scene.height as i64);
+    let mut framebuffer = Framebuffer::new(scene.width, scene.height);
+    // pixels further away than the radius get no weight
+    let radius = settings.filter.radius();
+    let (min_samples, max_samples) = match settings.adaptive {
+        Some(adaptive) => (adaptive.min_samples.min(adaptive.max_samples), adaptive.max_samples),
+        None => (settings.samples_per_pixel, settings.samples_per_pixel),
+    };
+    let mut sampler = settings.sampler.create(max_samples, settings.seed);
+    let perspective = PerspectiveCamera {
+        fov: scene.fov,
+        lens: settings.lens,
+    };
+    let camera: &dyn Camera = match &settings.camera {
+        Some(camera) => camera.as_ref(),
+        None => &perspective,
+    };
+    for y in 0..scene.height {
+        for x in 0..scene.width {
+            let mut stats = RunningStats::default();
+            // the strata cover every sample the pixel may take
+            let pixel_seed = hash(&[settings.seed, x as u64, y as u64]) as u32;
+            let mut index = 0;
+            while index < max_samples {
+                // the values only depend on the pixel and sample index, not on the traversal order
+                sampler.start_pixel_sample(x, y, index);
+                let (dx, dy) = pixel_offset(settings.pixel_sampling, index, max_samples, pixel_seed, sampler.as_mut());
+                let (film_x, film_y) = (x as f64 + dx, y as f64 + dy);
+                let time = if settings.shutter_close > settings.shutter_open {
+                    settings.shutter_open + sampler.get_1d() * (settings.shutter_close - settings.shutter_open)
+                } else {
+                    settings.shutter_open
+                };
+                let radiance = match camera.generate_ray(film_x, film_y, time, scene.width, scene.height, sampler.as_mut()) {
+                    Some(ray) => integrator.li(&ray, scene, sampler.as_mut()),
+                    None => black(),
+                };
+                // the sample counts for every pixel whose filter covers it
+                let (x0, x1) = ((film_x - 0.5 - radius).ceil() as i64, (film_x - 0.5 + radius).floor() as i64);
+                let (y0, y1) = ((film_y - 0.5 - radius).ceil() as i64, (film_y - 0.5 + radius).floor() as i64);
+                for py in y0.max(0)..=y1.min(height - 1) {
+                    for px in x0.max(0)..=x1.min(width - 1) {
+                        let weight = settings.filter.eval(film_x - (px as f64 + 0.5), film_y - (py as f64 + 0.5));
+                        framebuffer.add_sample(px as u32, py as u32, &radiance, weight);
+                    }
+                }
+                index += 1;
+                stats.add(radiance.luminance());
+                if let Some(adaptive) = settings.adaptive {
+                    if index >= min_samples && adaptive.converged(&stats) {
+                        break;
+                    }
+                }
+            }
+            framebuffer.sample_counts[(y * scene.width + x) as usize] = index;
+        }
+        println!("progress {}: out of {}", y, scene.height);
+    }
+    framebuffer
+}
+
+// the material's shading model lit by the scene lights, plus its emission
+fn shade_direct(ray: &Ray, hit: &Hit, scene: &Scene, sampler: &mut dyn Sampler) -> Spectrum {
+    let view_direction: Vector3 = &ray.direction.normalize() * -1.0;
+    let material = hit.element.material();
+    let lights = visible_lights(scene, &hit.point, &hit.normal, &view_direction, hit.time, sampler);
+    let context = ShadingContext {
+        normal: hit.normal.clone(),
+        view_direction,
+        albedo: &material.albedo,
+        front_face: hit.front_face,
+        wavelength: None,
+    };
+    let mut res = material.shading_model.shade(&context, &lights);
+    if let Some(emission) = &material.emission {
+        res += emission;
+    }
+    res
+}
+
+/** Shading models of the materials at the first hit, the facing ratio shader under the default headlight */
+pub struct DirectIntegrator;
+
+impl Integrator for DirectIntegrator {
+    fn li(&self, ray: &Ray, scene: &Scene, sampler: &mut dyn Sampler) -> Spectrum {
+        match scene.trace_hit(ray) {
+            Some(hit) => shade_direct(ray, &hit, scene, sampler),
+            None => scene.background(&ray.direction),
+        }
+    }
+}
+
+/** Direct shading plus recursive perfect mirror reflection and refraction */
+pub struct WhittedIntegrator {
+    pub max_depth: u32,
+}
+
+fn reflect(direction: &Vector3, normal: &Vector3) -> Vector3 {
+    direction.clone() - normal * (2.0 * direction.dot(normal))
+}
+
+// snell's law, None on total internal reflection
+fn refract(direction: &Vector3, normal: &Vector3, eta: f64) -> Option<Vector3> {
+    let cos_i = -direction.dot(normal);
+    let sin2_t = eta * eta * (1.0 - cos_i * cos_i);
+    if sin2_t > 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    Some(direction * eta + normal * (eta * cos_i - cos_t))
+}
+
+impl WhittedIntegrator {
+    fn trace(&self, ray: &Ray, scene: &Scene, sampler: &mut dyn Sampler, depth: u32) -> Spectrum {
+        let hit = match scene.trace_hit(ray) {
+            Some(hit) => hit,
+            None => return scene.background(&ray.direction),
+        };
+        let direct = shade_direct(ray, &hit, scene, sampler);
+        if depth >= self.max_depth {
+            return direct;
+        }
+        let material = hit.element.material();
+        let (reflectivity, transparency, ior) = (material.reflectivity, material.transparency, material.index_of_refraction);
+        let direction = ray.direction.normalize();
+        let diffuse = (1.0 - reflectivity - transparency).max(0.0);
+        let mut res = &direct * diffuse;
+        let mut add = |weight: f64, color: Spectrum| res += &(&color * weight);
+        let mirrored = reflect(&direction, &hit.normal);
+        if reflectivity > 0.0 {
+            add(reflectivity, self.trace(&spawn_ray(&hit, mirrored.clone()), scene, sampler, depth + 1));
+        }
+        if transparency > 0.0 {
+            let eta = if hit.front_face { 1.0 / ior } else { ior };
+            let transmitted = refract(&direction, &hit.normal, eta).unwrap_or(mirrored);
+            add(transparency, self.trace(&spawn_ray(&hit, transmitted), scene, sampler, depth + 1));
+        }
+        res
+    }
+}
+
+impl Integrator for WhittedIntegrator {
+    fn li(&self, ray: &Ray, scene: &Scene, sampler: &mut dyn Sampler) -> Spectrum {
+        self.trace(ray, scene, sampler, 0)
+    }
+}
+
+/** Fraction of cosine distributed rays escaping within max_distance, as a gray value */
+pub struct AmbientOcclusionIntegrator {
+    pub samples: u32,
+    pub max_distance: f64,
+}
+
+impl Integrator for AmbientOcclusionIntegrator {
+    fn li(&self, ray: &Ray, scene: &Scene, sampler: &mut dyn Sampler) -> Spectrum {
+        let hit = match scene.trace_hit(ray) {
+            Some(hit) => hit,
+            None => return black(),
+        };
+        let mut unoccluded = 0;
+        for _ in 0..self.samples {
+            let (u1, u2) = sampler.get_2d();
+            let local = cosine_sample_hemisphere(u1, u2);
+            let occlusion_ray = spawn_ray(&hit, to_world(&local, &hit.normal));
+            if !scene.occluded(&occlusion_ray, self.max_distance) {
+                unoccluded += 1;
+            }
+        }
+        let value = 255.0 * unoccluded as f64 / self.samples.max(1) as f64;
+        Color {
+            red: value,
+            green: value,
+            blue: value,
+        }
+    }
+}
+
+pub struct AmbientOcclusionSettings {
+    /** Occlusion rays per camera ray */
+    pub samples: u32,
+    /** Hits further away than this don't occlude */
+    pub max_distance: f64,
+    pub samples_per_pixel: u32,
+    pub seed: u64,
+}
+
+impl Default for AmbientOcclusionSettings {
+    fn default() -> AmbientOcclusionSettings {
+        AmbientOcclusionSettings {
+            samples: 16,
+            max_distance: f64::INFINITY,
+            samples_per_pixel: 1,
+            seed: 0,
+        }
+    }
+}
+
+/** Grayscale ambient occlusion pass, white where nothing blocks the hemisphere; the same seed gives the same image */
+pub fn render_ambient_occlusion(scene: &Scene, settings: &AmbientOcclusionSettings) -> DynamicImage {
+    let integrator = AmbientOcclusionIntegrator {
+        samples: settings.samples,
+        max_distance: settings.max_distance,
+    };
+    let render_settings = RenderSettings {
+        samples_per_pixel: settings.samples_per_pixel,
+        seed: settings.seed,
+        ..Default::default()
+    };
+    let radiance = render_radiance(scene, &integrator, &render_settings);
+    let mut image = GrayImage::new(scene.width, scene.height);
+    for (i, pixel) in image.pixels_mut().enumerate() {
+        *pixel = Luma([radiance[i].red.round().clamp(0.0, 255.0) as u8]);
+    }
+    DynamicImage::ImageLuma8(image)
+}
+
+pub enum DebugView {
+    /** Shading normal mapped from [-1, 1] to colors */
+    Normal,
+    /** Distance to the first hit, white at the camera and black at max_distance */
+    Depth { max_distance: f64 },
+    /** Surface color without any lighting */
+    Albedo,
+}
+
+/** Inspection views of the first hit */
+pub struct DebugIntegrator {
+    pub view: DebugView,
+}
+
+impl Integrator for DebugIntegrator {
+    fn li(&self, ray: &Ray, scene: &Scene, _sampler: &mut dyn Sampler) -> Spectrum {
+        let hit = match scene.trace_hit(ray) {
+            Some(hit) => hit,
+            None => return black(),
+        };
+        match self.view {
+            DebugView::Normal => Color {
+                red: (hit.normal.x + 1.0) * 0.5 * 255.0,
+                green: (hit.normal.y + 1.0) * 0.5 * 255.0,
+                blue: (hit.normal.z + 1.0) * 0.5 * 255.0,
+            },
+            DebugView::Depth { max_distance } => {
+                let value = 255.0 * (1.0 - hit.distance / max_distance).max(0.0);
+                Color {
+                    red: value,
+                    green: value,
+                    blue: value,
+                }
+            }
+            DebugView::Albedo => hit.element.material().albedo.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_integrator {
+    use super::*;
+    use crate::sampler::IndependentSampler;
+    use crate::environment::EnvironmentLight;
+    use crate::material::Material;
+    use crate::scene::{Element, Light, Triangle};
+    use std::sync::Arc;
+
+    fn wall(z: f64, color: Color, material: Material) -> Element {
+        Element::Triangle(Triangle {
+            point1: Point3 { x: -50.0, y: -50.0, z },
+            point2: Point3 { x: 50.0, y: -50.0, z },
+            point3: Point3 { x: 0.0, y: 50.0, z },
+            normals: None,
+            st: None,
+            tangent_frame: None,
+            material: Arc::new(Material { albedo: color, ..material }),
+        })
+    }
+
+    fn scene(elements: Vec<Element>) -> Scene {
+        Scene {
+            width: 3,
+            height: 3,
+            fov: 60.0,
+            elements,
+            lights: Vec::new(),
+            medium: None,
+        }
+    }
+
+    fn red() -> Color {
+        Color {
+            red: 200.0,
+            green: 0.0,
+            blue: 0.0,
+        }
+    }
+
+    #[test]
+    fn direct_matches_facing_ratio() {
+        let scene = scene(vec![wall(-5.0, red(), Material::default())]);
+        let radiance = render_radiance(&scene, &DirectIntegrator, &RenderSettings::default());
+        // the center ray hits the wall head on
+        assert!((radiance[4].red - 200.0).abs() < 1e-9);
+        assert!(radiance[0].red < 200.0);
+    }
+
+    #[test]
+    fn misses_show_the_environment() {
+        let mut scene = scene(vec![wall(-5.0, red(), Material::default())]);
+        let mut sky = EnvironmentLight::constant(Color {
+            red: 0.0,
+            green: 0.0,
+            blue: 150.0,
+        });
+        sky.rotation = 90.0;
+        scene.lights.push(Light::Environment(sky));
+        // looking away from the wall
+        let ray = Ray {
+            origin: Point3::zero(),
+            direction: Vector3 { x: 0.0, y: 0.0, z: 1.0 },
+            time: 0.0,
+        };
+        let mut sampler = IndependentSampler::new(0);
+        assert_eq!(DirectIntegrator.li(&ray, &scene, &mut sampler).blue, 150.0);
+        assert_eq!(WhittedIntegrator { max_depth: 2 }.li(&ray, &scene, &mut sampler).blue, 150.0);
+    }
+
+    #[test]
+    fn whitted_follows_mirrors() {
+        // a mirror in front of the camera reflects the red wall behind it
+        let mirror = Material {
+            reflectivity: 1.0,
+            ..Default::default()
+        };
+        let black_color = black();
+        let scene = scene(vec![wall(-5.0, black_color, mirror), wall(5.0, red(), Material::default())]);
+        let integrator = WhittedIntegrator { max_depth: 4 };
+        let radiance = render_radiance(&scene, &integrator, &RenderSettings::default());
+        assert!(radiance[4].red > 100.0);
+        assert_eq!(radiance[4].green, 0.0);
+    }
+
+    #[test]
+    fn ambient_occlusion_sees_enclosure() {
+        let integrator = AmbientOcclusionIntegrator {
+            samples: 32,
+            max_distance: 100.0,
+        };
+        let open = scene(vec![wall(-5.0, red(), Material::default())]);
+        assert_eq!(render_radiance(&open, &integrator, &RenderSettings::default())[4].red, 255.0);
+        // a wall behind the camera occludes the whole hemisphere
+        let closed = scene(vec![wall(-5.0, red(), Material::default()), wall(5.0, red(), Material::default())]);
+        assert!(render_radiance(&closed, &integrator, &RenderSettings::default())[4].red < 60.0);
+    }
+
+    #[test]
+    fn ambient_occlusion_pass_is_grayscale() {
+        // the wall behind the camera is 7 units from the wall in front, it only occludes within a larger max distance
+        let scene = scene(vec![wall(-5.0, red(), Material::default()), wall(2.0, red(), Material::default())]);
+        let near = AmbientOcclusionSettings {
+            max_distance: 20.0,
+            ..Default::default()
+        };
+        let image = render_ambient_occlusion(&scene, &near);
+        assert!(image.as_luma8().is_some());
+        assert_eq!(image.as_bytes(), render_ambient_occlusion(&scene, &near).as_bytes());
+        assert!(image.as_bytes()[4] < 128);
+        let other_seed = AmbientOcclusionSettings { seed: 1, ..near };
+        assert_ne!(image.as_bytes(), render_ambient_occlusion(&scene, &other_seed).as_bytes());
+        let short = AmbientOcclusionSettings {
+            max_distance: 5.0,
+            ..Default::default()
+        };
+        assert_eq!(render_ambient_occlusion(&scene, &short).as_bytes()[4], 255);
+    }
+
+    // the edge of the wall runs through the middle of the center column
+    fn half_wall() -> Element {
+        Element::Triangle(Triangle {
+            point1: Point3 { x: -100.0, y: -50.0, z: -5.0 },
+            point2: Point3 { x: 0.0, y: -50.0, z: -5.0 },
+            point3: Point3 { x: 0.0, y: 100.0, z: -5.0 },
+            normals: None,
+            st: None,
+            tangent_frame: None,
+            material: Arc::new(Material {
+                albedo: red(),
+                ..Default::default()
+            }),
+        })
+    }
+
+    #[test]
+    fn stratified_offsets_cover_the_pixel() {
+        let mut sampler = IndependentSampler::new(0);
+        let mut offsets = |sampling: PixelSampling, count: u32| -> Vec<(f64, f64)> {
+            (0..count).map(|index| pixel_offset(sampling, index, count, 7, &mut sampler)).collect()
+        };
+        let grid = offsets(PixelSampling::Stratified, 16);
+        for cell in 0..16 {
+            let (cx, cy) = ((cell % 4) as f64 / 4.0, (cell / 4) as f64 / 4.0);
+            assert_eq!(grid.iter().filter(|(x, y)| *x >= cx && *x < cx + 0.25 && *y >= cy && *y < cy + 0.25).count(), 1);
+        }
+        let rooks = offsets(PixelSampling::Stratified, 5);
+        for i in 0..5 {
+            let (lo, hi) = (i as f64 / 5.0, (i + 1) as f64 / 5.0);
+            assert_eq!(rooks.iter().filter(|(x, _)| *x >= lo && *x < hi).count(), 1);
+            assert_eq!(rooks.iter().filter(|(_, y)| *y >= lo && *y < hi).count(), 1);
+        }
+        assert_eq!(offsets(PixelSampling::Center, 2), vec![(0.5, 0.5); 2]);
+    }
+
+    #[test]
+    fn supersampling_smooths_edges() {
+        let scene = scene(vec![half_wall()]);
+        let settings = RenderSettings {
+            samples_per_pixel: 16,
+            pixel_sampling: PixelSampling::Stratified,
+            ..Default::default()
+        };
+        let radiance = render_radiance(&scene, &DirectIntegrator, &settings);
+        assert!(radiance[3].red > 150.0);
+        assert!((radiance[4].red - 100.0).abs() < 20.0, "{}", radiance[4].red);
+        assert_eq!(radiance[5].red, 0.0);
+        // jittered offsets from a stratified sampler cover the pixel as well
+        let sampled = RenderSettings {
+            samples_per_pixel: 16,
+            pixel_sampling: PixelSampling::Jittered,
+            sampler: SamplerKind::Stratified,
+            ..Default::default()
+        };
+        let sampled = render_radiance(&scene, &DirectIntegrator, &sampled);
+        assert!((sampled[4].red - 100.0).abs() < 20.0, "{}", sampled[4].red);
+        // a wider filter blends in the samples of the neighbours
+        let tent = RenderSettings {
+            filter: Filter::Tent { radius: 1.5 },
+            ..settings
+        };
+        let blurred = render_radiance(&scene, &DirectIntegrator, &tent);
+        assert!(blurred[5].red > 0.0 && blurred[3].red < radiance[3].red);
+        let again = render_radiance(&scene, &DirectIntegrator, &tent);
+        assert!(blurred.iter().zip(again.iter()).all(|(a, b)| a.red == b.red));
+    }
+
+    #[test]
+    fn defocus_blurs_the_edge() {
+        let scene = scene(vec![half_wall()]);
+        let lens = ThinLens {
+            aperture_radius: 1.0,
+            focus_distance: 5.0,
+            ..Default::default()
+        };
+        let settings = RenderSettings {
+            samples_per_pixel: 64,
+            lens: Some(lens),
+            ..Default::default()
+        };
+        // in focus the pixel centers still see a sharp edge
+        let sharp = render_radiance(&scene, &DirectIntegrator, &settings);
+        assert_eq!(sharp[5].red, 0.0);
+        let near = RenderSettings {
+            lens: Some(ThinLens { focus_distance: 1.0, ..lens }),
+            ..settings
+        };
+        let blurred = render_radiance(&scene, &DirectIntegrator, &near);
+        assert!(blurred[5].red > 10.0 && blurred[3].red < sharp[3].red);
+    }
+
+    #[test]
+    fn debug_views() {
+        let scene = scene(vec![wall(-5.0, red(), Material::default())]);
+        let normal = DebugIntegrator { view: DebugView::Normal };
+        let radiance = render_radiance(&scene, &normal, &RenderSettings::default());
+        assert_eq!(radiance[4].blue, 255.0);
+        let depth = DebugIntegrator {
+            view: DebugView::Depth { max_distance: 10.0 },
+        };
+        let radiance = render_radiance(&scene, &depth, &RenderSettings::default());
+        assert!((radiance[4].red - 127.5).abs() < 1e-9);
+    }
+}
@@ -1,20 +1,32 @@
 use crate::point::Point3;
-use crate::scene::{Color, Element, Plane, Scene, Sphere, Triangle};
+use crate::scene::{Disk, Element, Plane, Scene, Sphere, Triangle};
 use crate::vector::Vector3;
 
 pub struct Ray {
     pub origin: Point3,
     pub direction: Vector3,
+    /** Moment within the shutter interval, moving elements are intersected where they are at that time */
+    pub time: f64,
 }
 
 impl Ray {
     pub fn create_prime(x: u32, y: u32, scene: &Scene) -> Ray {
+        Ray::create_prime_at(x as f64 + 0.5, y as f64 + 0.5, scene)
+    }
+
+    /** Camera ray through a point on the film, in pixels from the top left corner */
+    pub fn create_prime_at(film_x: f64, film_y: f64, scene: &Scene) -> Ray {
+        Ray::create_perspective(film_x, film_y, scene.width, scene.height, scene.fov)
+    }
+
+    /** Pinhole camera ray at the origin looking down -z, with the vertical field of view in degrees */
+    pub fn create_perspective(film_x: f64, film_y: f64, width: u32, height: u32, fov: f64) -> Ray {
         //assert!(scene.width >= scene.height);
-        let fov_adjustment = (scene.fov.to_radians() / 2.0).tan();
-        let aspect_ratio = (scene.width as f64) / (scene.height as f64);
+        let fov_adjustment = (fov.to_radians() / 2.0).tan();
+        let aspect_ratio = (width as f64) / (height as f64);
         let sensor_x =
-            ((((x as f64 + 0.5) / scene.width as f64) * 2.0 - 1.0) * aspect_ratio) * fov_adjustment;
-        let sensor_y = (1.0 - ((y as f64 + 0.5) / scene.height as f64) * 2.0) * fov_adjustment;
+            (((film_x / width as f64) * 2.0 - 1.0) * aspect_ratio) * fov_adjustment;
+        let sensor_y = (1.0 - (film_y / height as f64) * 2.0) * fov_adjustment;
 
         Ray {
             origin: Point3::zero(),
@@ -24,6 +36,7 @@ impl Ray {
                 z: -1.0,
             }
             .normalize(),
+            time: 0.0,
         }
     }
 }
@@ -38,6 +51,9 @@ impl Intersectable for Element {
             Element::Sphere(ref s) => s.intersect(ray),
             Element::Plane(ref s) => s.intersect(ray),
             Element::Triangle(ref s) => s.intersect(ray),
+            Element::BezierPatch(ref s) => s.intersect(ray),
+            Element::Disk(ref s) => s.intersect(ray),
+            Element::Animated(ref s) => s.intersect(ray),
         }
     }
 }
@@ -57,7 +73,7 @@ impl Intersectable for Sphere {
         let radius2 = self.radius * self.radius;
 
         //If that length is greater than radius, the ray does not intersects the sphere
-        if l2_length > self.radius {
+        if l2_length > radius2 {
             return None;
         }
         let thc = (radius2 - l2_length).sqrt();
@@ -68,7 +84,8 @@ impl Intersectable for Sphere {
             return None;
         }
         // distance from sphere to camara (used to find the right element to render in front)
-        let distance = if t0 < t1 { t0 } else { t1 };
+        // from inside the sphere only the far side lies ahead
+        let distance = if t0 > 0.0 { t0 } else { t1 };
 
         Some(distance)
     }
@@ -133,7 +150,7 @@ impl Intersectable for Triangle {
         if c.dot(&normal) < 0.0 {
             return None; //Some(255.0);
         }
-        Some((ray.origin.to_vector() - p.to_vector()).norm())
+        Some(t)
     }
 }
 // math: https://en.wikipedia.org/wiki/Line%E2%80%93plane_intersection
@@ -146,33 +163,40 @@ impl Intersectable for Plane {
             // calculate interscetion point
             let v: Vector3 = &self.origin - &ray.origin; // (p0-l0)
             let d: f64 = v.dot(&normal) / denom; // ((p0-l0)·n) / l·n
-            let intersection = &ray.origin + &(&self.normal * d);
             if d > 0.0 {
-                return Some((intersection.z * 100.0).abs());
+                // distance along the ray, comparable with the other elements
+                return Some(d);
             }
         }
         None // ray and plane are parallel
     }
 }
 
-pub fn get_color(element: &Element) -> &Color {
-    match element {
-        Element::Triangle(t) => {
-            return &t.color;
-        },
-        _ => {print!("Not Triangle \n", )},
-    }
-    &Color {
-        red: 65.0,
-        green: 20.0,
-        blue: 150.0,
+impl Intersectable for Disk {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        let normal = self.normal.normalize();
+        let denom = normal.dot(&ray.direction);
+        if denom.abs() < 1e-12 {
+            return None;
+        }
+        let d = (&self.center - &ray.origin).dot(&normal) / denom;
+        if d <= 0.0 {
+            return None;
+        }
+        let p: Point3 = &ray.origin + &(&ray.direction * d);
+        if (&p - &self.center).norm() > self.radius * self.radius {
+            return None;
+        }
+        Some(d)
     }
 }
 
 #[cfg(test)]
 mod test_rendering {
     use super::*;
+    use crate::material::Material;
     use crate::scene::*;
+    use std::sync::Arc;
 
     #[test]
     fn intersect_sphere_ray() {
@@ -183,11 +207,14 @@ mod test_rendering {
                 z: -5.0,
             },
             radius: 5.0,
-            color: Color {
-                red: 155.0,
-                green: 155.0,
-                blue: 255.0,
-            },
+            material: Arc::new(Material {
+                albedo: Color {
+                    red: 155.0,
+                    green: 155.0,
+                    blue: 255.0,
+                },
+                ..Default::default()
+            }),
         };
         // ray hits center of sphere
         let prime_ray: Ray = Ray {
@@ -201,6 +228,7 @@ mod test_rendering {
                 y: 1.0,
                 z: -5.0,
             },
+            time: 0.0,
         };
         let intersection: bool = match sphere.intersect(&prime_ray) {
             Some(x) => true,
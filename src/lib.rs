@@ -1,52 +1,45 @@
+pub mod adaptive;
+pub mod area_light;
+pub mod bezier;
+pub mod bsdf;
+pub mod camera;
+pub mod color;
+pub mod displacement;
+pub mod environment;
+pub mod filter;
+pub mod framebuffer;
 pub mod load_geo_scene;
+pub mod material;
+pub mod medium;
+pub mod mis;
+pub mod motion;
+pub mod path_tracing;
 pub mod point;
+pub mod sampler;
+pub mod sampling;
+pub mod integrator;
 pub mod intersection;
 pub mod scene;
 pub mod vector;
 pub mod shading;
+pub mod sky;
+pub mod spectral;
+pub mod subdivision;
+pub mod subsurface;
+pub mod texture;
 pub mod transforming;
 
-use image::{DynamicImage, GenericImage, GenericImageView, ImageBuffer, Rgba};
-use load_geo_scene::create_scene_from_file;
-use point::Point3;
-use intersection::{get_color, Intersectable, Ray};
-use shading::facing_ratio;
-use scene::{Color, Element, Plane, Scene, Sphere, Triangle};
-use vector::{Vector3, Matrix3};
+use image::DynamicImage;
+use integrator::{render_framebuffer, DirectIntegrator, Integrator, RenderSettings};
+use scene::Scene;
 
 pub fn render(scene: &Scene) -> DynamicImage {
-    let mut image = DynamicImage::new_bgr8(scene.width, scene.height);
-    for x in 0..scene.width {
-        for y in 0..scene.height {
-            let ray = Ray::create_prime(x, y, scene);
-            let intersection: Option<&Element> = scene.trace(&ray);
-            match intersection {
-                Some(element) => {
-                    let color: &Color = get_color(element);
-                    let normal: Vector3;
-                    match element {
-                        Element::Triangle(t) => normal = t.calculate_normal(),
-                        _ => normal = Vector3::from_one(-1.0),
-                    }
-                    let ratio: f64 = facing_ratio(&ray, &normal);
-                    //print!("Ratio: {}\n", ratio);
-                    image.put_pixel(
-                        x,
-                        y,
-                        Rgba([
-                            (color.red * (ratio)) as u8,
-                            (color.green * (ratio)) as u8,
-                            (color.blue * (ratio)) as u8,
-                            255,
-                        ]),
-                    );
-                }
-                None => {}
-            }
-        }
-        print!("progress {}: out of {} \n", x, scene.width);
-    }
-    image
+    render_with_integrator(scene, &DirectIntegrator, &RenderSettings::default())
+}
+
+/** Renders the scene with any integrator, e.g. a path tracer or one of the debug views, tone mapped as the settings say */
+pub fn render_with_integrator(scene: &Scene, integrator: &dyn Integrator, settings: &RenderSettings) -> DynamicImage {
+    render_framebuffer(scene, integrator, settings).to_image(&settings.tone_mapping)
 }
 
 pub fn save_image(image: &DynamicImage) {
@@ -57,6 +50,11 @@ pub fn save_image(image: &DynamicImage) {
 mod integration_test {
 
     use super::*;
+    use material::Material;
+    use point::Point3;
+    use scene::{Color, Element, Plane, Sphere, Triangle};
+    use vector::{Matrix3, Vector3};
+    use std::sync::Arc;
 
     #[test]
     fn test_can_render_triangle_scene() {
@@ -81,11 +79,17 @@ mod integration_test {
                         y: 1.0,
                         z: -5.0,
                     },
-                    color: Color {
-                        red: 180.0,
-                        green: 20.0,
-                        blue: 20.0,
-                    },
+                    material: Arc::new(Material {
+                        albedo: Color {
+                            red: 180.0,
+                            green: 20.0,
+                            blue: 20.0,
+                        },
+                        ..Default::default()
+                    }),
+                    normals: None,
+                    st: None,
+                    tangent_frame: None,
                 }),
                 Element::Triangle(Triangle {
                     point1: Point3 {
@@ -103,13 +107,21 @@ mod integration_test {
                         y: 1.0,
                         z: -5.0,
                     },
-                    color: Color {
-                        red: 20.0,
-                        green: 180.0,
-                        blue: 20.0,
-                    },
+                    material: Arc::new(Material {
+                        albedo: Color {
+                            red: 20.0,
+                            green: 180.0,
+                            blue: 20.0,
+                        },
+                        ..Default::default()
+                    }),
+                    normals: None,
+                    st: None,
+                    tangent_frame: None,
                 }),
             ],
+            lights: Vec::new(),
+            medium: None,
         };
         let image = render(&scene);
         save_image(&image)
@@ -156,11 +168,17 @@ mod integration_test {
                         y: 1.0,
                         z: -5.0,
                     }).to_vector() * &rotation_matrix).to_point(),
-                    color: Color {
-                        red: 180.0,
-                        green: 20.0,
-                        blue: 20.0,
-                    },
+                    material: Arc::new(Material {
+                        albedo: Color {
+                            red: 180.0,
+                            green: 20.0,
+                            blue: 20.0,
+                        },
+                        ..Default::default()
+                    }),
+                    normals: None,
+                    st: None,
+                    tangent_frame: None,
                 }),
                 Element::Triangle(Triangle {
                     point1: Point3 {
@@ -178,13 +196,21 @@ mod integration_test {
                         y: 1.0,
                         z: -5.0,
                     },
-                    color: Color {
-                        red: 20.0,
-                        green: 180.0,
-                        blue: 20.0,
-                    },
+                    material: Arc::new(Material {
+                        albedo: Color {
+                            red: 20.0,
+                            green: 180.0,
+                            blue: 20.0,
+                        },
+                        ..Default::default()
+                    }),
+                    normals: None,
+                    st: None,
+                    tangent_frame: None,
                 }),
             ],
+            lights: Vec::new(),
+            medium: None,
         };
         let image = render(&scene);
         save_image(&image)
@@ -204,11 +230,14 @@ mod integration_test {
                         z: -6.0,
                     },
                     radius: 5.0,
-                    color: Color {
-                        red: 0.0,
-                        green: 155.0,
-                        blue: 0.0,
-                    },
+                    material: Arc::new(Material {
+                        albedo: Color {
+                            red: 0.0,
+                            green: 155.0,
+                            blue: 0.0,
+                        },
+                        ..Default::default()
+                    }),
                 }),
                 Element::Sphere(Sphere {
                     center: Point3 {
@@ -217,13 +246,18 @@ mod integration_test {
                         z: -5.0,
                     },
                     radius: 5.0,
-                    color: Color {
-                        red: 155.0,
-                        green: 0.0,
-                        blue: 0.0,
-                    },
+                    material: Arc::new(Material {
+                        albedo: Color {
+                            red: 155.0,
+                            green: 0.0,
+                            blue: 0.0,
+                        },
+                        ..Default::default()
+                    }),
                 }),
             ],
+            lights: Vec::new(),
+            medium: None,
         };
         let image = render(&scene);
         save_image(&image)
@@ -246,7 +280,17 @@ mod integration_test {
                     y: -0.9,
                     z: -0.1,
                 },
+                material: Arc::new(Material {
+                    albedo: Color {
+                        red: 65.0,
+                        green: 20.0,
+                        blue: 150.0,
+                    },
+                    ..Default::default()
+                }),
             })],
+            lights: Vec::new(),
+            medium: None,
         };
         let image = render(&scene);
         save_image(&image)
@@ -1,16 +1,30 @@
 use crate::point::Point3;
 use crate::vector::{Matrix3, Vector3};
-use crate::scene::{Element, Scene, Triangle, Color};
+use crate::displacement::displace;
+use crate::material::Material;
+use crate::scene::{Element, Scene, Triangle};
+use std::sync::Arc;
+use crate::subdivision::{subdivide, SubdivisionSettings};
 use crate::transforming::{rotate_object, Axis};
 use std::fs::File;
 use std::io::prelude::*;
+use std::io::{Error, ErrorKind};
+
+/** Mesh processing applied between loading a file and building its triangles */
+#[derive(Default)]
+pub struct LoadOptions {
+    pub subdivision: Option<SubdivisionSettings>,
+    pub material: Arc<Material>,
+}
 
 pub struct GeoData {
-    num_face: usize,
-    face_index_array: Vec<usize>,
-    vertex_index_array: Vec<usize>,
-    vertex_array: Vec<Point3>,
-    normal_array: Vec<Point3>,
+    pub num_face: usize,
+    pub face_index_array: Vec<usize>,
+    pub vertex_index_array: Vec<usize>,
+    pub vertex_array: Vec<Point3>,
+    pub normal_array: Vec<Point3>,
+    /** Texture coordinates per face-vertex, empty when the file has none */
+    pub st_array: Vec<(f64, f64)>,
 }
 
 pub fn load_geo_file(file_path: String) -> std::io::Result<GeoData> {
@@ -63,7 +77,27 @@ pub fn load_geo_file(file_path: String) -> std::io::Result<GeoData> {
         .collect(); // collect to a vector;
     let mut normal_array: Vec<Point3> = Vec::new();
 
-    for i in 0..(coordinate_array2.len() / 3) {
+    // one normal per face-vertex, the texture coordinates either follow on the same line or on the next
+    let num_face_vertices = vertex_index_array.len();
+    let st_coordinates: Vec<f64> = if coordinate_array2.len() >= 5 * num_face_vertices {
+        coordinate_array2[3 * num_face_vertices..5 * num_face_vertices].to_vec()
+    } else if content.len() > 5 && !content[5].trim().is_empty() {
+        content[5].trim()
+            .split(' ') // split string of numbers
+            .map(|s| s.parse().unwrap()) // pares strings to numbers
+            .collect() // collect to a vector;
+    } else {
+        Vec::new()
+    };
+    if !st_coordinates.is_empty() && st_coordinates.len() != 2 * num_face_vertices {
+        return Err(Error::new(ErrorKind::InvalidData, "expected one texture coordinate pair per face-vertex"));
+    }
+    let st_array: Vec<(f64, f64)> = st_coordinates
+        .chunks_exact(2)
+        .map(|st| (st[0], st[1]))
+        .collect();
+
+    for i in 0..(coordinate_array2.len() / 3).min(num_face_vertices) {
         normal_array.push(Point3 {
             x: coordinate_array2[3 * i],
             y: coordinate_array2[3 * i + 1],
@@ -76,6 +110,7 @@ pub fn load_geo_file(file_path: String) -> std::io::Result<GeoData> {
         vertex_index_array: vertex_index_array,
         vertex_array: vertex_array,
         normal_array: normal_array,
+        st_array,
     };
     print!("Done with file loadning \n", );
     Ok(res)
@@ -97,11 +132,31 @@ pub fn create_trianglemesh(geo_data: &GeoData) -> Vec<usize> {
     triangle_index_array
 }
 
+/** Face-vertex data in the same order as the indices of create_trianglemesh */
+pub fn create_trianglemesh_attributes<T: Clone>(geo_data: &GeoData, face_vertex_array: &[T]) -> Vec<T> {
+    let mut triangle_attribute_array: Vec<T> = Vec::new();
+    let mut k = 0;
+    for i in 0..geo_data.num_face {
+        for j in 0..geo_data.face_index_array[i] - 2 {
+            triangle_attribute_array.push(face_vertex_array[k].clone());
+            triangle_attribute_array.push(face_vertex_array[k + j + 1].clone());
+            triangle_attribute_array.push(face_vertex_array[k + j + 2].clone());
+        }
+        k += geo_data.face_index_array[i];
+    }
+    triangle_attribute_array
+}
+
+pub fn create_trianglemesh_normals(geo_data: &GeoData) -> Vec<Point3> {
+    create_trianglemesh_attributes(geo_data, &geo_data.normal_array)
+}
+
 pub fn create_triangles(
     vertex_array: Vec<Point3>,
     triangle_index_array: Vec<usize>,
 ) -> Vec<Element> {
     let mut triangles: Vec<Element> = Vec::new();
+    let material = Arc::new(Material::default());
     for i in 0..triangle_index_array.len() / 3 {
         let triangle: Triangle = Triangle {
             point1: vertex_array
@@ -116,22 +171,62 @@ pub fn create_triangles(
                 .get(*triangle_index_array.get(3 * i + 2).unwrap())
                 .unwrap()
                 .clone(),
-            color: Color {
-                red: 180.0,
-                green: 180.0,
-                blue: 180.0,
-            },
+            normals: None,
+            st: None,
+            tangent_frame: None,
+            material: material.clone(),
         };
         triangles.push(Element::Triangle(triangle));
     }
     triangles
 }
 
+/** Triangles shaded with interpolated vertex normals */
+pub fn create_smooth_triangles(
+    vertex_array: Vec<Point3>,
+    triangle_index_array: Vec<usize>,
+    triangle_normal_array: Vec<Point3>,
+) -> Vec<Element> {
+    let mut triangles: Vec<Element> = create_triangles(vertex_array, triangle_index_array);
+    for (i, element) in triangles.iter_mut().enumerate() {
+        if let Element::Triangle(t) = element {
+            t.normals = Some([
+                triangle_normal_array[3 * i].to_vector(),
+                triangle_normal_array[3 * i + 1].to_vector(),
+                triangle_normal_array[3 * i + 2].to_vector(),
+            ]);
+        }
+    }
+    triangles
+}
+
+/** Attaches the material, and the texture coordinates with their tangent frames when available */
+pub fn apply_material(
+    triangles: &mut [Element],
+    triangle_st_array: Option<Vec<(f64, f64)>>,
+    material: &Arc<Material>,
+) {
+    for (i, element) in triangles.iter_mut().enumerate() {
+        if let Element::Triangle(t) = element {
+            if let Some(st_array) = &triangle_st_array {
+                let st = [st_array[3 * i], st_array[3 * i + 1], st_array[3 * i + 2]];
+                t.tangent_frame = t.calculate_tangent_frame(&st);
+                t.st = Some(st);
+            }
+            t.material = material.clone();
+        }
+    }
+}
+
 /** Blob */
 pub fn create_scene_from_file(path: String) -> std::io::Result<Scene> {
+    create_scene_from_file_with_options(path, &LoadOptions::default())
+}
+
+pub fn create_scene_from_file_with_options(path: String, options: &LoadOptions) -> std::io::Result<Scene> {
     // load file
     let file_content = load_geo_file(path);
-    let geo_data: GeoData;
+    let mut geo_data: GeoData;
     match file_content {
         Ok(data) => geo_data = data,
         Err(e) => {
@@ -139,14 +234,35 @@ pub fn create_scene_from_file(path: String) -> std::io::Result<Scene> {
             return Err(e);
         }
     }
+    // refine the surface, subdivision first so the displacement has vertices to move
+    if let Some(settings) = &options.subdivision {
+        geo_data = subdivide(&geo_data, settings);
+    }
+    if let Some(displacement) = &options.material.displacement {
+        displace(&mut geo_data, displacement)?;
+    }
+
     // get triangle data
     let triangle_index_array: Vec<usize> = create_trianglemesh(&geo_data);
-    let triangles: Vec<Element> = create_triangles(geo_data.vertex_array, triangle_index_array);
+    let triangle_st_array: Option<Vec<(f64, f64)>> = if geo_data.st_array.len() == geo_data.vertex_index_array.len() {
+        Some(create_trianglemesh_attributes(&geo_data, &geo_data.st_array))
+    } else {
+        None
+    };
+    let mut triangles: Vec<Element> = if options.subdivision.is_some() || options.material.displacement.is_some() {
+        let triangle_normal_array: Vec<Point3> = create_trianglemesh_normals(&geo_data);
+        create_smooth_triangles(geo_data.vertex_array, triangle_index_array, triangle_normal_array)
+    } else {
+        create_triangles(geo_data.vertex_array, triangle_index_array)
+    };
+    apply_material(&mut triangles, triangle_st_array, &options.material);
     let res: Scene = Scene {
         width: 600,
         height: 400,
         fov: 90.0,
         elements: triangles,
+        lights: Vec::new(),
+        medium: None,
     };
     Ok(res)
 }
@@ -201,21 +317,19 @@ mod test_file_read {
                 point1: point1.clone(),
                 point2: point2.clone(),
                 point3: point3.clone(),
-                color: Color {
-                    red: 180.0,
-                    green: 180.0,
-                    blue: 180.0,
-                },
+                normals: None,
+                st: None,
+                tangent_frame: None,
+                material: Arc::new(Material::default()),
             },
             Triangle {
                 point1: point1.clone(),
                 point2: point3.clone(),
                 point3: point4.clone(),
-                color: Color {
-                    red: 180.0,
-                    green: 180.0,
-                    blue: 180.0,
-                },
+                normals: None,
+                st: None,
+                tangent_frame: None,
+                material: Arc::new(Material::default()),
             },
         ];
 
@@ -237,6 +351,44 @@ mod test_file_read {
         }
     }
 
+    #[test]
+    fn positiv_load_texture_coordinates() {
+        // texture coordinates on the normal line (backdrop) and on a line of their own (cow)
+        for path in ["geometry/backdrop.geo", "geometry/cow.geo"].iter() {
+            let geo_data = load_geo_file(String::from(*path)).unwrap();
+            assert_eq!(geo_data.normal_array.len(), geo_data.vertex_index_array.len());
+            assert_eq!(geo_data.st_array.len(), geo_data.vertex_index_array.len());
+        }
+    }
+
+    #[test]
+    fn negativ_load_odd_texture_coordinates() {
+        let path = std::env::temp_dir().join("odd_texture_coordinates.geo");
+        std::fs::write(&path, "1\n3\n0 1 2\n0 0 0 1 0 0 0 1 0\n0 0 1 0 0 1 0 0 1\n0 0 1 0 1\n").unwrap();
+        let err = load_geo_file(path.to_string_lossy().into_owned()).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn positiv_create_subdivided_scene() {
+        let options = LoadOptions {
+            subdivision: Some(SubdivisionSettings::default()),
+            ..Default::default()
+        };
+        let scene = create_scene_from_file_with_options(String::from("geometry/backdrop.geo"), &options).unwrap();
+        // every face of the quad dominant backdrop becomes one quad per corner, two triangles each
+        let geo_data = load_geo_file(String::from("geometry/backdrop.geo")).unwrap();
+        assert_eq!(scene.elements.len(), 2 * geo_data.vertex_index_array.len());
+        match &scene.elements[0] {
+            Element::Triangle(t) => {
+                assert!(t.normals.is_some());
+                assert!(t.st.is_some());
+                assert!(Arc::ptr_eq(&t.material, &options.material));
+            }
+            _ => panic!("expected a triangle"),
+        }
+    }
+
     #[test]
     fn dummy() {
         let string_index_array = " 4 4 4 4 4 4 4 4 3 3 3 3 3 3 3 3 3 3 3 3 3 3 3 3 ".trim();
@@ -0,0 +1,61 @@
+use crate::medium::Medium;
+use crate::scene::Color;
+use crate::shading::{Lambert, ShadingModel};
+use crate::texture::Texture;
+use std::sync::Arc;
+
+/** Surface description shared by all triangles of a loaded mesh */
+pub struct Material {
+    /** Surface color on the 0-255 scale, the base color of the physically based models */
+    pub albedo: Color,
+    pub shading_model: Box<dyn ShadingModel>,
+    /** Radiance leaving the surface on both sides, on the 0-255 color scale */
+    pub emission: Option<Color>,
+    /** Fraction of the light mirrored by the surface */
+    pub reflectivity: f64,
+    /** Fraction of the light refracted through the surface */
+    pub transparency: f64,
+    pub index_of_refraction: f64,
+    pub displacement: Option<Displacement>,
+    /** Tangent-space normals encoded as colors, (128, 128, 255) is the unperturbed normal */
+    pub normal_map: Option<Texture>,
+    pub bump_map: Option<BumpMap>,
+    /** Medium filling the inside of a closed mesh, for the path tracer */
+    pub interior: Option<Arc<Medium>>,
+}
+
+impl Default for Material {
+    fn default() -> Material {
+        Material {
+            albedo: Color {
+                red: 180.0,
+                green: 180.0,
+                blue: 180.0,
+            },
+            shading_model: Box::new(Lambert),
+            emission: None,
+            reflectivity: 0.0,
+            transparency: 0.0,
+            index_of_refraction: 1.5,
+            displacement: None,
+            normal_map: None,
+            bump_map: None,
+            interior: None,
+        }
+    }
+}
+
+/** Height texture moving mesh vertices along their normals */
+pub struct Displacement {
+    pub texture: Texture,
+    /** Distance a height of 1.0 moves a vertex */
+    pub scale: f64,
+    /** Height that leaves the vertex in place */
+    pub midlevel: f64,
+}
+
+/** Grayscale height texture tilting the shading normal along its slope */
+pub struct BumpMap {
+    pub texture: Texture,
+    pub strength: f64,
+}
@@ -0,0 +1,494 @@
+use crate::bsdf::{Bsdf, BsdfSample};
+use crate::intersection::Ray;
+use crate::point::Point3;
+use crate::sampler::Sampler;
+use crate::sampling::to_world;
+use crate::scene::{Color, Scene};
+use crate::shading::{LightSample, ShadingContext, ShadingModel};
+use crate::vector::Vector3;
+use std::f64::consts::PI;
+
+/** Henyey-Greenstein phase function, g is the mean cosine of the scattering angle */
+pub struct HenyeyGreenstein {
+    /** Between -1 and 1, negative values scatter backwards and 0 in all directions alike */
+    pub g: f64,
+}
+
+impl HenyeyGreenstein {
+    /** Density of scattering by the angle whose cosine is given, relative to the direction of travel */
+    pub fn eval(&self, cos_theta: f64) -> f64 {
+        let g = self.g;
+        let denom = 1.0 + g * g - 2.0 * g * cos_theta;
+        (1.0 - g * g) / (4.0 * PI * denom * denom.sqrt())
+    }
+
+    /** New direction of travel for a ray travelling along direction, with its density */
+    pub fn sample(&self, direction: &Vector3, u1: f64, u2: f64) -> (Vector3, f64) {
+        let g = self.g;
+        let cos_theta = if g.abs() < 1e-3 {
+            1.0 - 2.0 * u1
+        } else {
+            let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * u1);
+            ((1.0 + g * g - s * s) / (2.0 * g)).clamp(-1.0, 1.0)
+        };
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * u2;
+        let local = Vector3 {
+            x: sin_theta * phi.cos(),
+            y: sin_theta * phi.sin(),
+            z: cos_theta,
+        };
+        (to_world(&local, &direction.normalize()), self.eval(cos_theta))
+    }
+}
+
+// the phase function seen as a bsdf, the view direction points back along the incoming ray
+impl Bsdf for HenyeyGreenstein {
+    fn eval(&self, context: &ShadingContext, direction: &Vector3) -> Color {
+        let p = HenyeyGreenstein::eval(self, -context.view_direction.dot(direction));
+        Color {
+            red: p,
+            green: p,
+            blue: p,
+        }
+    }
+
+    fn pdf(&self, context: &ShadingContext, direction: &Vector3) -> f64 {
+        HenyeyGreenstein::eval(self, -context.view_direction.dot(direction))
+    }
+
+    fn sample(&self, context: &ShadingContext, _u_lobe: f64, u1: f64, u2: f64) -> Option<BsdfSample> {
+        let (direction, pdf) = HenyeyGreenstein::sample(self, &(&context.view_direction * -1.0), u1, u2);
+        // sampled exactly in proportion to the phase function
+        Some(BsdfSample {
+            direction,
+            weight: Color {
+                red: 1.0,
+                green: 1.0,
+                blue: 1.0,
+            },
+            pdf,
+            specular: false,
+        })
+    }
+}
+
+/** Densities on a regular grid over a box, interpolated between the voxel centers */
+pub struct DensityGrid {
+    pub min: Point3,
+    pub max: Point3,
+    /** Voxels along x, y and z */
+    pub resolution: [usize; 3],
+    // x varies fastest, then y, then z
+    values: Vec<f64>,
+    max_value: f64,
+}
+
+impl DensityGrid {
+    pub fn new(min: Point3, max: Point3, resolution: [usize; 3], values: Vec<f64>) -> DensityGrid {
+        assert_eq!(values.len(), resolution[0] * resolution[1] * resolution[2], "grid needs one value per voxel");
+        let max_value = values.iter().cloned().fold(0.0, f64::max);
+        DensityGrid {
+            min,
+            max,
+            resolution,
+            values,
+            max_value,
+        }
+    }
+
+    fn value(&self, x: usize, y: usize, z: usize) -> f64 {
+        self.values[(z * self.resolution[1] + y) * self.resolution[0] + x]
+    }
+
+    /** Trilinear density at a point, zero outside the box */
+    pub fn density(&self, point: &Point3) -> f64 {
+        let extent = [self.max.x - self.min.x, self.max.y - self.min.y, self.max.z - self.min.z];
+        let offset = [point.x - self.min.x, point.y - self.min.y, point.z - self.min.z];
+        let mut cells = [(0, 0, 0.0); 3];
+        for axis in 0..3 {
+            let relative = offset[axis] / extent[axis];
+            if !(0.0..=1.0).contains(&relative) {
+                return 0.0;
+            }
+            let n = self.resolution[axis];
+            // voxel centers sit at half integer positions
+            let position = (relative * n as f64 - 0.5).clamp(0.0, (n - 1) as f64);
+            let lower = (position.floor() as usize).min(n - 1);
+            cells[axis] = (lower, (lower + 1).min(n - 1), position - lower as f64);
+        }
+        let [(x0, x1, fx), (y0, y1, fy), (z0, z1, fz)] = cells;
+        let lerp = |a: f64, b: f64, t: f64| a + (b - a) * t;
+        let plane = |z: usize| {
+            lerp(
+                lerp(self.value(x0, y0, z), self.value(x1, y0, z), fx),
+                lerp(self.value(x0, y1, z), self.value(x1, y1, z), fx),
+                fy,
+            )
+        };
+        lerp(plane(z0), plane(z1), fz)
+    }
+
+    pub fn max_density(&self) -> f64 {
+        self.max_value
+    }
+
+    // part of the ray inside the box, as distances along the direction
+    fn clip(&self, ray: &Ray, t_max: f64) -> Option<(f64, f64)> {
+        let origin = [ray.origin.x, ray.origin.y, ray.origin.z];
+        let d = [ray.direction.x, ray.direction.y, ray.direction.z];
+        let (min, max) = ([self.min.x, self.min.y, self.min.z], [self.max.x, self.max.y, self.max.z]);
+        let (mut t0, mut t1) = (0.0, t_max);
+        for axis in 0..3 {
+            let inverse = 1.0 / d[axis];
+            let (mut near, mut far) = ((min[axis] - origin[axis]) * inverse, (max[axis] - origin[axis]) * inverse);
+            if near > far {
+                std::mem::swap(&mut near, &mut far);
+            }
+            // a ray parallel to the slab and outside it gives NaN and is rejected here
+            if near.is_nan() || far.is_nan() {
+                return None;
+            }
+            t0 = f64::max(t0, near);
+            t1 = f64::min(t1, far);
+        }
+        if t0 < t1 {
+            Some((t0, t1))
+        } else {
+            None
+        }
+    }
+}
+
+/** Participating medium absorbing and scattering light, coefficients are per unit length.
+Rays passed to its methods need unit directions so that distances along them are lengths. */
+pub struct Medium {
+    /** Absorption coefficient of the red, green and blue channel */
+    pub sigma_a: [f64; 3],
+    /** Scattering coefficient of the red, green and blue channel */
+    pub sigma_s: [f64; 3],
+    pub phase: HenyeyGreenstein,
+    /** Scales both coefficients, the medium is homogeneous without a grid */
+    pub density: Option<DensityGrid>,
+}
+
+/** Outcome of following a ray through a medium */
+pub enum MediumInteraction {
+    /** The ray scatters at the distance, weight is the factor for the path throughput */
+    Scattered { distance: f64, weight: Color },
+    /** The ray reaches the end of the segment */
+    Passed { weight: Color },
+}
+
+fn color(c: [f64; 3]) -> Color {
+    Color {
+        red: c[0],
+        green: c[1],
+        blue: c[2],
+    }
+}
+
+impl Medium {
+    /** Homogeneous fog of uniform color */
+    pub fn fog(sigma_a: f64, sigma_s: f64, g: f64) -> Medium {
+        Medium {
+            sigma_a: [sigma_a; 3],
+            sigma_s: [sigma_s; 3],
+            phase: HenyeyGreenstein { g },
+            density: None,
+        }
+    }
+
+    fn sigma_t(&self) -> [f64; 3] {
+        [0, 1, 2].map(|c| self.sigma_a[c] + self.sigma_s[c])
+    }
+
+    // density scale and the range of the ray it is non-zero in, with a bound on the density
+    fn extent(&self, ray: &Ray, t_max: f64) -> Option<(f64, f64, f64)> {
+        match &self.density {
+            None => Some((0.0, t_max, 1.0)),
+            Some(grid) => grid.clip(ray, t_max).map(|(t0, t1)| (t0, t1, grid.max_density())),
+        }
+    }
+
+    fn density_at(&self, point: &Point3) -> f64 {
+        self.density.as_ref().map_or(1.0, |grid| grid.density(point))
+    }
+
+    /** Delta tracking of a ray up to t_max, picking a scattering event with the chromatic coefficients as weights */
+    pub fn sample_interaction(&self, ray: &Ray, t_max: f64, sampler: &mut dyn Sampler) -> MediumInteraction {
+        let mut weight = [1.0; 3];
+        let (t0, t1, max_density) = match self.extent(ray, t_max) {
+            Some(extent) => extent,
+            None => return MediumInteraction::Passed { weight: color(weight) },
+        };
+        let sigma_t = self.sigma_t();
+        // majorant of the extinction over all channels
+        let majorant = sigma_t.iter().cloned().fold(0.0, f64::max) * max_density;
+        if majorant <= 0.0 {
+            return MediumInteraction::Passed { weight: color(weight) };
+        }
+        let mut t = t0;
+        loop {
+            t -= (1.0 - sampler.get_1d()).ln() / majorant;
+            if t >= t1 {
+                return MediumInteraction::Passed { weight: color(weight) };
+            }
+            let density = self.density_at(&(&ray.origin + &(&ray.direction * t)));
+            let scattering = [0, 1, 2].map(|c| self.sigma_s[c] * density);
+            let null = [0, 1, 2].map(|c| majorant - sigma_t[c] * density);
+            // absorption is left to the weights, the tracking only decides between scattering and null collisions
+            let mean_scattering = scattering.iter().sum::<f64>() / 3.0;
+            let mean_null = null.iter().sum::<f64>() / 3.0;
+            if mean_scattering + mean_null <= 0.0 {
+                return MediumInteraction::Passed { weight: color([0.0; 3]) };
+            }
+            let p_scatter = mean_scattering / (mean_scattering + mean_null);
+            if sampler.get_1d() < p_scatter {
+                for c in 0..3 {
+                    weight[c] *= scattering[c] / (majorant * p_scatter);
+                }
+                return MediumInteraction::Scattered {
+                    distance: t,
+                    weight: color(weight),
+                };
+            }
+            for c in 0..3 {
+                weight[c] *= null[c] / (majorant * (1.0 - p_scatter));
+            }
+        }
+    }
+
+    /** Fraction of light passing the first t_max of the ray, exact for homogeneous media and ratio tracked otherwise */
+    pub fn transmittance(&self, ray: &Ray, t_max: f64, sampler: &mut dyn Sampler) -> Color {
+        let sigma_t = self.sigma_t();
+        if self.density.is_none() {
+            return color(sigma_t.map(|s| (-s * t_max).exp()));
+        }
+        let mut res = [1.0; 3];
+        let (t0, t1, max_density) = match self.extent(ray, t_max) {
+            Some(extent) => extent,
+            None => return color(res),
+        };
+        let majorant = sigma_t.iter().cloned().fold(0.0, f64::max) * max_density;
+        if majorant <= 0.0 {
+            return color(res);
+        }
+        let mut t = t0;
+        loop {
+            t -= (1.0 - sampler.get_1d()).ln() / majorant;
+            if t >= t1 {
+                return color(res);
+            }
+            let density = self.density_at(&(&ray.origin + &(&ray.direction * t)));
+            for c in 0..3 {
+                res[c] *= 1.0 - sigma_t[c] * density / majorant;
+            }
+        }
+    }
+}
+
+/** Invisible boundary of the medium inside a closed mesh, rays pass straight through */
+pub struct Interface;
+
+impl ShadingModel for Interface {
+    fn shade(&self, _context: &ShadingContext, _lights: &[LightSample]) -> Color {
+        Color {
+            red: 0.0,
+            green: 0.0,
+            blue: 0.0,
+        }
+    }
+
+    fn bsdf(&self) -> Option<&dyn Bsdf> {
+        Some(self)
+    }
+
+    fn is_interface(&self) -> bool {
+        true
+    }
+}
+
+impl Bsdf for Interface {
+    fn eval(&self, _context: &ShadingContext, _direction: &Vector3) -> Color {
+        Color {
+            red: 0.0,
+            green: 0.0,
+            blue: 0.0,
+        }
+    }
+
+    fn pdf(&self, _context: &ShadingContext, _direction: &Vector3) -> f64 {
+        0.0
+    }
+
+    fn sample(&self, context: &ShadingContext, _u_lobe: f64, _u1: f64, _u2: f64) -> Option<BsdfSample> {
+        Some(BsdfSample {
+            direction: &context.view_direction * -1.0,
+            weight: Color {
+                red: 1.0,
+                green: 1.0,
+                blue: 1.0,
+            },
+            pdf: 1.0,
+            specular: true,
+        })
+    }
+}
+
+/** Medium a ray is in after crossing a surface, entering a closed mesh switches to its interior medium */
+pub fn medium_after<'a>(scene: &'a Scene, interior: Option<&'a Medium>, front_face: bool) -> Option<&'a Medium> {
+    // nested media are not tracked, leaving a mesh always returns to the scene's medium
+    if front_face {
+        interior
+    } else {
+        scene.medium.as_deref()
+    }
+}
+
+/** Fraction of light travelling the distance along a shadow ray, through medium boundaries and the media between them */
+pub fn shadow_transmittance(scene: &Scene, ray: &Ray, distance: f64, medium: Option<&Medium>, sampler: &mut dyn Sampler) -> Color {
+    // most shadow rays neither start in a medium nor cross a boundary
+    if !scene.occluded(ray, distance) && medium.is_none() {
+        return color([1.0; 3]);
+    }
+    let direction = ray.direction.normalize();
+    let mut res = color([1.0; 3]);
+    let mut origin = ray.origin.clone();
+    let mut remaining = distance;
+    let mut medium = medium;
+    loop {
+        let segment_ray = Ray {
+            origin: origin.clone(),
+            direction: direction.clone(),
+            time: ray.time,
+        };
+        // same allowance as Scene::occluded for the surface the shadow ray is aimed at
+        let hit = scene.trace_hit(&segment_ray).filter(|hit| hit.distance < remaining * (1.0 - 1e-6) - 1e-6);
+        let segment = hit.as_ref().map_or(remaining, |hit| hit.distance);
+        if let Some(m) = medium {
+            res = &res * &m.transmittance(&segment_ray, segment, sampler);
+        }
+        let hit = match hit {
+            Some(hit) => hit,
+            None => return res,
+        };
+        let material = hit.element.material();
+        match material.shading_model.shadow_transmission(direction.dot(&hit.normal).abs(), hit.front_face) {
+            Some(transmission) => res = &res * transmission,
+            None => return color([0.0; 3]),
+        }
+        medium = medium_after(scene, material.interior.as_deref(), hit.front_face);
+        origin = &hit.point + &(&direction * 1e-4);
+        remaining -= hit.distance + 1e-4;
+    }
+}
+
+#[cfg(test)]
+mod test_medium {
+    use super::*;
+    use crate::sampler::IndependentSampler;
+    use crate::sampling::Rng;
+
+    fn ray(direction: Vector3) -> Ray {
+        Ray {
+            origin: Point3::zero(),
+            direction,
+            time: 0.0,
+        }
+    }
+
+    #[test]
+    fn phase_function_is_normalized() {
+        let mut rng = Rng::new(5, 0);
+        for g in [-0.5, 0.0, 0.7] {
+            let phase = HenyeyGreenstein { g };
+            let forward = Vector3 { x: 0.0, y: 0.0, z: -1.0 };
+            let n = 50000;
+            let mut mean_cos = 0.0;
+            let mut integral = 0.0;
+            for _ in 0..n {
+                let (direction, pdf) = phase.sample(&forward, rng.next_f64(), rng.next_f64());
+                let cos_theta = direction.dot(&forward);
+                assert!((pdf - phase.eval(cos_theta)).abs() < 1e-9 * pdf.max(1.0));
+                mean_cos += cos_theta / n as f64;
+                // uniform directions estimate the integral over the sphere
+                integral += phase.eval(1.0 - 2.0 * rng.next_f64()) * 4.0 * PI / n as f64;
+            }
+            assert!((mean_cos - g).abs() < 0.01);
+            assert!((integral - 1.0).abs() < 0.02);
+        }
+    }
+
+    #[test]
+    fn homogeneous_fog_follows_beer_lambert() {
+        let fog = Medium::fog(0.2, 0.3, 0.0);
+        let mut rng = IndependentSampler::new(1);
+        let along = ray(Vector3 { x: 1.0, y: 0.0, z: 0.0 });
+        assert!((fog.transmittance(&along, 2.0, &mut rng).red - (-1.0_f64).exp()).abs() < 1e-12);
+        // the fraction of rays scattering before 2 units, weighted by the scattering albedo
+        let n = 20000;
+        let mut scattered = 0.0;
+        for _ in 0..n {
+            if let MediumInteraction::Scattered { weight, distance } = fog.sample_interaction(&along, 2.0, &mut rng) {
+                assert!(distance < 2.0);
+                scattered += weight.green / n as f64;
+            }
+        }
+        let expected = 0.6 * (1.0 - (-1.0_f64).exp());
+        assert!((scattered - expected).abs() < 0.01);
+    }
+
+    #[test]
+    fn grid_interpolates_between_voxels() {
+        let grid = DensityGrid::new(
+            Point3::zero(),
+            Point3 { x: 2.0, y: 1.0, z: 1.0 },
+            [2, 1, 1],
+            vec![1.0, 3.0],
+        );
+        assert_eq!(grid.density(&Point3 { x: 0.5, y: 0.5, z: 0.5 }), 1.0);
+        assert_eq!(grid.density(&Point3 { x: 1.0, y: 0.5, z: 0.5 }), 2.0);
+        assert_eq!(grid.density(&Point3 { x: 1.9, y: 0.2, z: 0.9 }), 3.0);
+        assert_eq!(grid.density(&Point3 { x: 2.5, y: 0.5, z: 0.5 }), 0.0);
+        assert_eq!(grid.max_density(), 3.0);
+    }
+
+    #[test]
+    fn ratio_tracking_matches_optical_depth() {
+        // density rising linearly from 1 to 3 along x over the middle voxels
+        let grid = DensityGrid::new(Point3::zero(), Point3 { x: 4.0, y: 1.0, z: 1.0 }, [4, 1, 1], vec![0.0, 1.0, 3.0, 0.0]);
+        let medium = Medium {
+            sigma_a: [0.5, 0.0, 0.0],
+            sigma_s: [0.0, 0.5, 0.0],
+            phase: HenyeyGreenstein { g: 0.0 },
+            density: Some(grid),
+        };
+        let along = Ray {
+            origin: Point3 { x: -1.0, y: 0.5, z: 0.5 },
+            direction: Vector3 { x: 1.0, y: 0.0, z: 0.0 },
+            time: 0.0,
+        };
+        // integral of the density from x = 1.5 to 2.5 is 2
+        let through_middle = Ray {
+            origin: Point3 { x: 1.5, y: 0.5, z: 0.5 },
+            ..along
+        };
+        let mut rng = IndependentSampler::new(2);
+        let n = 20000;
+        let mut sum = 0.0;
+        for _ in 0..n {
+            let t = medium.transmittance(&through_middle, 1.0, &mut rng);
+            assert_eq!(t.blue, 1.0);
+            sum += t.red / n as f64;
+        }
+        assert!((sum - (-1.0_f64).exp()).abs() < 0.01);
+        // parts outside the box don't attenuate
+        let parallel = Ray {
+            origin: Point3 { x: -1.0, y: 2.0, z: 0.5 },
+            direction: Vector3 { x: 1.0, y: 0.0, z: 0.0 },
+            time: 0.0,
+        };
+        assert_eq!(medium.transmittance(&parallel, 10.0, &mut rng).red, 1.0);
+    }
+}
@@ -0,0 +1,350 @@
+use crate::bsdf::Bsdf;
+use crate::intersection::Ray;
+use crate::medium::{shadow_transmittance, Medium};
+use crate::point::Point3;
+use crate::sampler::Sampler;
+use crate::scene::{Color, Element, Light, Scene};
+use crate::shading::{delta_light, ShadingContext};
+use crate::spectral::at_wavelength;
+use crate::vector::Vector3;
+use std::f64::consts::PI;
+
+/** Weighting of two sampling strategies estimating the same light */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MisHeuristic {
+    Balance,
+    /** Balance heuristic on squared densities, favours the locally better strategy */
+    Power,
+}
+
+impl MisHeuristic {
+    /** Weight of a sample drawn with density pdf which the other strategy would have drawn with other_pdf */
+    pub fn weight(&self, pdf: f64, other_pdf: f64) -> f64 {
+        let (a, b) = match self {
+            MisHeuristic::Balance => (pdf, other_pdf),
+            MisHeuristic::Power => (pdf * pdf, other_pdf * other_pdf),
+        };
+        if a + b == 0.0 {
+            0.0
+        } else {
+            a / (a + b)
+        }
+    }
+}
+
+/** How the light sampled at a path vertex is chosen */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LightSelection {
+    Uniform,
+    /** In proportion to the emitted power */
+    Power,
+}
+
+/** How a path tracer gathers the light arriving directly from the lights */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DirectLighting {
+    /** Shadow rays to every light, area and environment lights hit by bounce rays are not counted again */
+    LightSampling,
+    /** One shadow ray to a light picked from the distribution, area and environment lights hit by bounce rays are not counted again */
+    OneLight { selection: LightSelection },
+    /** Area and environment lights only count when a bounce ray hits them, point and directional lights are still sampled */
+    BsdfSampling,
+    /** One light per vertex, combined with the bounce ray by multiple importance sampling */
+    MultipleImportance {
+        heuristic: MisHeuristic,
+        selection: LightSelection,
+    },
+}
+
+impl Default for DirectLighting {
+    fn default() -> DirectLighting {
+        DirectLighting::MultipleImportance {
+            heuristic: MisHeuristic::Power,
+            selection: LightSelection::Power,
+        }
+    }
+}
+
+/** Probability of picking each light of a scene, indexed like scene.lights */
+pub struct LightDistribution {
+    pub probabilities: Vec<f64>,
+}
+
+impl LightDistribution {
+    pub fn new(scene: &Scene, selection: LightSelection) -> LightDistribution {
+        let weights: Vec<f64> = match selection {
+            LightSelection::Uniform => vec![1.0; scene.lights.len()],
+            LightSelection::Power => {
+                // only lights at infinity need the scene size, which takes a pass over every element
+                let radius = if scene.lights.iter().any(|l| matches!(l, Light::Directional(_) | Light::Environment(_))) {
+                    scene_radius(scene)
+                } else {
+                    1.0
+                };
+                scene.lights.iter().map(|light| light_power(light, radius)).collect()
+            }
+        };
+        LightDistribution::from_weights(weights)
+    }
+
+    /** Only point and directional lights, for paths that find the other lights by hitting them */
+    pub fn delta_lights(scene: &Scene) -> LightDistribution {
+        let weights = scene
+            .lights
+            .iter()
+            .map(|light| if matches!(light, Light::Area(_) | Light::Environment(_)) { 0.0 } else { 1.0 })
+            .collect();
+        LightDistribution::from_weights(weights)
+    }
+
+    fn from_weights(weights: Vec<f64>) -> LightDistribution {
+        let total: f64 = weights.iter().sum();
+        let probabilities = if total > 0.0 {
+            weights.iter().map(|w| w / total).collect()
+        } else {
+            vec![0.0; weights.len()]
+        };
+        LightDistribution { probabilities }
+    }
+
+    /** Index of the light for a uniform number, with its probability */
+    pub fn pick(&self, u: f64) -> Option<(usize, f64)> {
+        let mut cumulative = 0.0;
+        let mut last = None;
+        for (i, p) in self.probabilities.iter().enumerate() {
+            if *p == 0.0 {
+                continue;
+            }
+            cumulative += p;
+            if u < cumulative {
+                return Some((i, *p));
+            }
+            last = Some((i, *p));
+        }
+        // rounding may leave the sum slightly below one
+        last
+    }
+}
+
+/** Emitted power used for light selection, lights at infinity cover a disk of the scene's size */
+pub fn light_power(light: &Light, scene_radius: f64) -> f64 {
+    match light {
+        Light::Point(l) => 4.0 * PI * l.intensity * l.color.luminance(),
+        Light::Directional(l) => PI * scene_radius * scene_radius * l.intensity * l.color.luminance(),
+        Light::Area(l) => l.power(),
+        Light::Environment(l) => PI * PI * scene_radius * scene_radius * l.average_luminance(),
+    }
+}
+
+// distance from the origin to the farthest point of the bounded elements
+fn scene_radius(scene: &Scene) -> f64 {
+    scene.elements.iter().map(extent).fold(1.0, f64::max)
+}
+
+fn extent(element: &Element) -> f64 {
+    let length = |p: &Point3| p.to_vector().length();
+    match element {
+        Element::Triangle(t) => length(&t.point1).max(length(&t.point2)).max(length(&t.point3)),
+        Element::Sphere(s) => length(&s.center) + s.radius,
+        Element::Disk(d) => length(&d.center) + d.radius,
+        Element::BezierPatch(b) => b.control_points.iter().map(length).fold(0.0, f64::max),
+        // infinite planes would make the disk infinite
+        Element::Plane(_) => 0.0,
+        // rotations keep distances to the origin, the keyframes bound the whole motion
+        Element::Animated(a) => {
+            let inner = extent(&a.element);
+            a.keyframes.iter().map(|k| inner * k.transform.scale + k.transform.translation.length()).fold(0.0, f64::max)
+        }
+    }
+}
+
+/** Next event estimation, picking one light per shaded point from the distribution */
+pub struct DirectLightSampler<'a> {
+    pub scene: &'a Scene,
+    /** None sends shadow rays to every light, as many as the area and environment lights ask for */
+    pub distribution: Option<&'a LightDistribution>,
+    /** Weighting of area and environment lights against the bsdf sampling the same direction, None counts them fully */
+    pub heuristic: Option<MisHeuristic>,
+}
+
+impl<'a> DirectLightSampler<'a> {
+    /** Light arriving from the lights, reflected by the bsdf towards the viewer and dimmed by the media on the way */
+    pub fn sample(&self, bsdf: &dyn Bsdf, context: &ShadingContext, point: &Point3, time: f64, medium: Option<&Medium>, sampler: &mut dyn Sampler) -> Color {
+        // shadow rays as light indices with the probability of picking them, n rays to a light weigh like picks of probability n
+        let picks: Vec<(usize, f64)> = match self.distribution {
+            Some(distribution) => distribution.pick(sampler.get_1d()).into_iter().collect(),
+            None => self
+                .scene
+                .lights
+                .iter()
+                .enumerate()
+                .flat_map(|(index, light)| {
+                    let samples = match light {
+                        Light::Area(l) => l.samples.max(1),
+                        Light::Environment(l) => l.samples.max(1),
+                        _ => 1,
+                    };
+                    std::iter::repeat_n((index, samples as f64), samples as usize)
+                })
+                .collect(),
+        };
+        // start the shadow rays slightly above the surface to avoid hitting it again
+        let origin = point + &(&context.normal * 1e-4);
+        let mut res = Color::from_one(0.0);
+        for (index, probability) in picks {
+            let (u1, u2) = sampler.get_2d();
+            let light = &self.scene.lights[index];
+            // solid angle density of the direction, None for delta lights
+            let (direction, distance, radiance, light_pdf): (Vector3, f64, Color, Option<f64>) = match light {
+                Light::Area(l) => match l.sample(&origin, u1, u2) {
+                    Some(sample) => (sample.direction, sample.distance, l.emission(), Some(sample.pdf)),
+                    None => continue,
+                },
+                Light::Environment(l) => match l.sample(u1, u2) {
+                    Some(sample) => (sample.direction, f64::INFINITY, sample.radiance, Some(sample.pdf)),
+                    None => continue,
+                },
+                _ => match delta_light(light, &origin) {
+                    Some((direction, distance, color)) => (direction, distance, color, None),
+                    None => continue,
+                },
+            };
+            let weight = match (light_pdf, self.heuristic) {
+                (Some(pdf), Some(h)) => h.weight(probability * pdf, bsdf.pdf(context, &direction)) / (probability * pdf),
+                (Some(pdf), None) => 1.0 / (probability * pdf),
+                // the light colors of delta lights carry a factor pi
+                (None, _) => PI / probability,
+            };
+            let f = bsdf.eval(context, &direction);
+            if f.red == 0.0 && f.green == 0.0 && f.blue == 0.0 {
+                continue;
+            }
+            let shadow_ray = Ray {
+                origin: origin.clone(),
+                direction,
+                time,
+            };
+            let transmittance = shadow_transmittance(self.scene, &shadow_ray, distance, medium, sampler);
+            // a spectral path multiplies the spectra of the factors at its wavelength, not the spectrum of their product
+            let wavelength = context.wavelength;
+            let light = &at_wavelength(&radiance, wavelength) * &at_wavelength(&transmittance, wavelength);
+            res += &(&(&at_wavelength(&f, wavelength) * &light) * weight);
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod test_mis {
+    use super::*;
+    use crate::area_light::{AreaLight, AreaShape};
+    use crate::sampler::IndependentSampler;
+    use crate::scene::PointLight;
+    use crate::shading::Lambert;
+    use crate::spectral::rgb_to_spectrum;
+
+    fn rectangle(emission: f64) -> AreaLight {
+        AreaLight::new(
+            AreaShape::Rectangle {
+                corner: Point3::zero(),
+                edge1: Vector3 { x: 1.0, y: 0.0, z: 0.0 },
+                edge2: Vector3 { x: 0.0, y: 1.0, z: 0.0 },
+            },
+            Color {
+                red: emission,
+                green: emission,
+                blue: emission,
+            },
+        )
+    }
+
+    fn scene(lights: Vec<Light>) -> Scene {
+        Scene {
+            width: 1,
+            height: 1,
+            fov: 90.0,
+            elements: Vec::new(),
+            lights,
+            medium: None,
+        }
+    }
+
+    #[test]
+    fn heuristic_weights_sum_to_one() {
+        for heuristic in [MisHeuristic::Balance, MisHeuristic::Power] {
+            let sum = heuristic.weight(0.3, 1.7) + heuristic.weight(1.7, 0.3);
+            assert!((sum - 1.0).abs() < 1e-12);
+        }
+        assert_eq!(MisHeuristic::Balance.weight(1.0, 3.0), 0.25);
+        assert_eq!(MisHeuristic::Power.weight(1.0, 3.0), 0.1);
+        assert_eq!(MisHeuristic::Power.weight(0.0, 0.0), 0.0);
+    }
+
+    #[test]
+    fn brighter_lights_are_picked_more_often() {
+        let scene = scene(vec![Light::Area(rectangle(100.0)), Light::Area(rectangle(300.0))]);
+        let distribution = LightDistribution::new(&scene, LightSelection::Power);
+        assert!((distribution.probabilities[0] - 0.25).abs() < 1e-12);
+        assert_eq!(distribution.pick(0.2).unwrap().0, 0);
+        assert_eq!(distribution.pick(0.3).unwrap().0, 1);
+        assert_eq!(distribution.pick(1.0).unwrap().0, 1);
+        let uniform = LightDistribution::new(&scene, LightSelection::Uniform);
+        assert_eq!(uniform.probabilities, vec![0.5, 0.5]);
+    }
+
+    #[test]
+    fn delta_distribution_skips_area_lights() {
+        let point = Light::Point(PointLight {
+            position: Point3::zero(),
+            color: Color {
+                red: 255.0,
+                green: 255.0,
+                blue: 255.0,
+            },
+            intensity: 1.0,
+        });
+        let scene = scene(vec![Light::Area(rectangle(100.0)), point]);
+        let distribution = LightDistribution::delta_lights(&scene);
+        assert_eq!(distribution.pick(0.0), Some((1, 1.0)));
+        assert!(LightDistribution::delta_lights(&self::scene(vec![Light::Area(rectangle(1.0))])).pick(0.5).is_none());
+    }
+
+    #[test]
+    fn spectral_paths_multiply_the_spectra() {
+        let cyan = Color {
+            red: 0.0,
+            green: 255.0,
+            blue: 255.0,
+        };
+        let yellow = Color {
+            red: 255.0,
+            green: 255.0,
+            blue: 0.0,
+        };
+        let point = Light::Point(PointLight {
+            position: Point3 { x: 0.0, y: 0.0, z: 1.0 },
+            color: cyan.clone(),
+            intensity: 1.0,
+        });
+        let scene = scene(vec![point]);
+        let light_sampler = DirectLightSampler {
+            scene: &scene,
+            distribution: None,
+            heuristic: None,
+        };
+        let up = Vector3 { x: 0.0, y: 0.0, z: 1.0 };
+        let context = ShadingContext {
+            normal: up.clone(),
+            view_direction: up,
+            albedo: &yellow,
+            front_face: true,
+            wavelength: Some(450.0),
+        };
+        let mut sampler = IndependentSampler::new(0);
+        let direct = light_sampler.sample(&Lambert, &context, &Point3::zero(), 0.0, None, &mut sampler);
+        // a yellow surface reflects little of the blue the cyan light emits, though their rgb product is green
+        let expected = rgb_to_spectrum(&yellow, 450.0) / 255.0 * rgb_to_spectrum(&cyan, 450.0);
+        assert!((direct.red - expected).abs() < 1e-3 * expected, "{} {}", direct.red, expected);
+        assert!((direct.red - rgb_to_spectrum(&(&(&yellow * &cyan) * (1.0 / 255.0)), 450.0)).abs() > 0.5 * expected);
+    }
+}
@@ -0,0 +1,254 @@
+use crate::camera::Camera;
+use crate::intersection::{Intersectable, Ray};
+use crate::point::Point3;
+use crate::sampler::Sampler;
+use crate::scene::Element;
+use crate::vector::Vector3;
+
+/** Placement of an element or camera: scaled, rotated around x, then y, then z, then moved */
+#[derive(Clone, Debug)]
+pub struct Transform {
+    pub translation: Vector3,
+    /** Rotation angles around the axes, in degrees */
+    pub rotation: Vector3,
+    pub scale: f64,
+}
+
+impl Default for Transform {
+    fn default() -> Transform {
+        Transform {
+            translation: Vector3::zero(),
+            rotation: Vector3::zero(),
+            scale: 1.0,
+        }
+    }
+}
+
+// rotation in the plane of two coordinates
+fn rotate_plane(a: f64, b: f64, degrees: f64) -> (f64, f64) {
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    (a * cos - b * sin, a * sin + b * cos)
+}
+
+impl Transform {
+    /** Transform between the two, component by component */
+    pub fn lerp(&self, other: &Transform, t: f64) -> Transform {
+        let mix = |a: &Vector3, b: &Vector3| Vector3 {
+            x: a.x + (b.x - a.x) * t,
+            y: a.y + (b.y - a.y) * t,
+            z: a.z + (b.z - a.z) * t,
+        };
+        Transform {
+            translation: mix(&self.translation, &other.translation),
+            rotation: mix(&self.rotation, &other.rotation),
+            scale: self.scale + (other.scale - self.scale) * t,
+        }
+    }
+
+    fn rotate(&self, v: &Vector3) -> Vector3 {
+        let (y, z) = rotate_plane(v.y, v.z, self.rotation.x);
+        let (z, x) = rotate_plane(z, v.x, self.rotation.y);
+        let (x, y) = rotate_plane(x, y, self.rotation.z);
+        Vector3 { x, y, z }
+    }
+
+    fn inverse_rotate(&self, v: &Vector3) -> Vector3 {
+        let (x, y) = rotate_plane(v.x, v.y, -self.rotation.z);
+        let (z, x) = rotate_plane(v.z, x, -self.rotation.y);
+        let (y, z) = rotate_plane(y, z, -self.rotation.x);
+        Vector3 { x, y, z }
+    }
+
+    pub fn apply_point(&self, p: &Point3) -> Point3 {
+        &Point3::zero() + &(self.apply_vector(&p.to_vector()) + self.translation.clone())
+    }
+
+    pub fn apply_vector(&self, v: &Vector3) -> Vector3 {
+        &self.rotate(v) * self.scale
+    }
+
+    /** Normals only turn, the scale is the same along every axis */
+    pub fn apply_normal(&self, n: &Vector3) -> Vector3 {
+        self.rotate(n).normalize()
+    }
+
+    pub fn inverse_point(&self, p: &Point3) -> Point3 {
+        &Point3::zero() + &self.inverse_vector(&(p.to_vector() - self.translation.clone()))
+    }
+
+    pub fn inverse_vector(&self, v: &Vector3) -> Vector3 {
+        &self.inverse_rotate(v) * (1.0 / self.scale)
+    }
+}
+
+/** Transform reached at a point in time */
+#[derive(Clone, Debug)]
+pub struct Keyframe {
+    pub time: f64,
+    pub transform: Transform,
+}
+
+/** Transform at the time, interpolated between the surrounding keyframes and held before the first and after the last */
+pub fn transform_at(keyframes: &[Keyframe], time: f64) -> Transform {
+    let next = keyframes.iter().position(|k| k.time > time);
+    match next {
+        None => keyframes.last().map(|k| k.transform.clone()).unwrap_or_default(),
+        Some(0) => keyframes[0].transform.clone(),
+        Some(i) => {
+            let (a, b) = (&keyframes[i - 1], &keyframes[i]);
+            a.transform.lerp(&b.transform, (time - a.time) / (b.time - a.time))
+        }
+    }
+}
+
+/** Element given in its own space, placed in the scene by keyframes sorted by time */
+pub struct AnimatedElement {
+    pub element: Box<Element>,
+    pub keyframes: Vec<Keyframe>,
+}
+
+impl AnimatedElement {
+    /** The ray in the element's space at the ray's time, with the factor turning world distances into local ones */
+    pub fn local_ray(&self, ray: &Ray) -> (Ray, Transform, f64) {
+        let transform = transform_at(&self.keyframes, ray.time);
+        let direction = transform.inverse_vector(&ray.direction);
+        let stretch = direction.length();
+        let local = Ray {
+            origin: transform.inverse_point(&ray.origin),
+            direction: &direction * (1.0 / stretch),
+            time: ray.time,
+        };
+        (local, transform, stretch)
+    }
+}
+
+impl Intersectable for AnimatedElement {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        let (local, _, stretch) = self.local_ray(ray);
+        self.element.intersect(&local).map(|distance| distance / stretch)
+    }
+}
+
+/** Camera moved by keyframes, the rays of the wrapped camera are placed at their time */
+pub struct KeyframedCamera {
+    pub camera: Box<dyn Camera>,
+    pub keyframes: Vec<Keyframe>,
+}
+
+impl Camera for KeyframedCamera {
+    fn generate_ray(&self, film_x: f64, film_y: f64, time: f64, width: u32, height: u32, sampler: &mut dyn Sampler) -> Option<Ray> {
+        let ray = self.camera.generate_ray(film_x, film_y, time, width, height, sampler)?;
+        let transform = transform_at(&self.keyframes, time);
+        Some(Ray {
+            origin: transform.apply_point(&ray.origin),
+            direction: transform.apply_vector(&ray.direction).normalize(),
+            time,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test_motion {
+    use super::*;
+    use crate::camera::PerspectiveCamera;
+    use crate::integrator::{render_radiance, DebugIntegrator, DebugView, RenderSettings};
+    use crate::material::Material;
+    use crate::sampler::IndependentSampler;
+    use crate::scene::{Scene, Sphere};
+    use std::sync::Arc;
+
+    fn moved(x: f64) -> Transform {
+        Transform {
+            translation: Vector3 { x, y: 0.0, z: 0.0 },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn transforms_invert_and_interpolate() {
+        let transform = Transform {
+            translation: Vector3 { x: 1.0, y: -2.0, z: 3.0 },
+            rotation: Vector3 { x: 30.0, y: -45.0, z: 60.0 },
+            scale: 2.0,
+        };
+        let p = Point3 { x: 0.3, y: 0.7, z: -1.1 };
+        let back = transform.inverse_point(&transform.apply_point(&p));
+        assert!((back.x - p.x).abs() < 1e-12 && (back.y - p.y).abs() < 1e-12 && (back.z - p.z).abs() < 1e-12);
+        // a quarter turn around z takes x to y
+        let turn = Transform {
+            rotation: Vector3 { x: 0.0, y: 0.0, z: 90.0 },
+            ..Default::default()
+        };
+        let y = turn.apply_vector(&Vector3 { x: 1.0, y: 0.0, z: 0.0 });
+        assert!((y.y - 1.0).abs() < 1e-12 && y.x.abs() < 1e-12);
+        let keyframes = vec![Keyframe { time: 0.0, transform: moved(0.0) }, Keyframe { time: 2.0, transform: moved(4.0) }];
+        assert_eq!(transform_at(&keyframes, 0.5).translation.x, 1.0);
+        assert_eq!(transform_at(&keyframes, -1.0).translation.x, 0.0);
+        assert_eq!(transform_at(&keyframes, 3.0).translation.x, 4.0);
+    }
+
+    fn sliding_ball() -> Scene {
+        let ball = Element::Sphere(Sphere {
+            center: Point3 { x: 0.0, y: 0.0, z: -5.0 },
+            radius: 0.25,
+            material: Arc::new(Material::default()),
+        });
+        Scene {
+            width: 9,
+            height: 1,
+            fov: 10.0,
+            elements: vec![Element::Animated(AnimatedElement {
+                element: Box::new(ball),
+                keyframes: vec![Keyframe { time: 0.0, transform: moved(-1.0) }, Keyframe { time: 1.0, transform: moved(1.0) }],
+            })],
+            lights: Vec::new(),
+            medium: None,
+        }
+    }
+
+    #[test]
+    fn rays_meet_elements_where_they_are_at_their_time() {
+        let scene = sliding_ball();
+        let towards = |x: f64, time: f64| Ray {
+            origin: Point3 { x, y: 0.0, z: 0.0 },
+            direction: Vector3 { x: 0.0, y: 0.0, z: -1.0 },
+            time,
+        };
+        assert!(scene.trace_hit(&towards(-1.0, 0.0)).is_some());
+        assert!(scene.trace_hit(&towards(-1.0, 1.0)).is_none());
+        let hit = scene.trace_hit(&towards(1.0, 1.0)).unwrap();
+        assert!((hit.distance - 4.75).abs() < 1e-9);
+        assert!((hit.normal.z - 1.0).abs() < 1e-9);
+        // a camera following the ball keeps it in the center
+        let camera = KeyframedCamera {
+            camera: Box::new(PerspectiveCamera { fov: 10.0, lens: None }),
+            keyframes: vec![Keyframe { time: 0.0, transform: moved(-1.0) }, Keyframe { time: 1.0, transform: moved(1.0) }],
+        };
+        let mut sampler = IndependentSampler::new(0);
+        for time in [0.0, 0.3, 1.0] {
+            let ray = camera.generate_ray(4.5, 0.5, time, 9, 1, &mut sampler).unwrap();
+            assert!(scene.trace_hit(&ray).is_some());
+        }
+    }
+
+    #[test]
+    fn open_shutter_blurs_moving_elements() {
+        let scene = sliding_ball();
+        let depth = DebugIntegrator {
+            view: DebugView::Depth { max_distance: 10.0 },
+        };
+        let still = render_radiance(&scene, &depth, &RenderSettings::default());
+        let settings = RenderSettings {
+            samples_per_pixel: 64,
+            shutter_open: 0.0,
+            shutter_close: 1.0,
+            ..Default::default()
+        };
+        let blurred = render_radiance(&scene, &depth, &settings);
+        // at time 0 the ball sits in the pixel left of the center, during the exposure it sweeps across
+        assert!(still[3].red > 0.0);
+        assert_eq!(still[4].red, 0.0);
+        assert!(blurred[4].red > 0.0 && blurred[4].red < still[3].red);
+        assert!(blurred[3].red < still[3].red);
+    }
+}
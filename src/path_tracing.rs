@@ -0,0 +1,805 @@
+use crate::adaptive::AdaptiveSampling;
+use crate::bsdf::Bsdf;
+use crate::camera::{Camera, ThinLens};
+use crate::filter::Filter;
+use crate::integrator::{black, render_radiance, spawn_ray, Integrator, PixelSampling, RenderSettings, Spectrum};
+use crate::intersection::Ray;
+use crate::medium::{medium_after, Medium, MediumInteraction};
+use crate::mis::{DirectLightSampler, DirectLighting, LightDistribution};
+use crate::point::Point3;
+use crate::sampler::{Sampler, SamplerKind};
+use crate::scene::{Color, Light, Scene};
+use crate::shading::{Lambert, ShadingContext};
+use crate::spectral::{at_wavelength, sample_wavelength, wavelength_to_rgb};
+use crate::vector::Vector3;
+use std::sync::Arc;
+
+pub struct PathTracingSettings {
+    pub samples_per_pixel: u32,
+    /** Bounces before russian roulette may end a path */
+    pub russian_roulette_depth: u32,
+    /** Hard limit on the number of bounces */
+    pub max_depth: u32,
+    pub seed: u64,
+    pub direct_lighting: DirectLighting,
+    pub pixel_sampling: PixelSampling,
+    pub filter: Filter,
+    pub sampler: SamplerKind,
+    pub adaptive: Option<AdaptiveSampling>,
+    pub lens: Option<ThinLens>,
+    pub camera: Option<Arc<dyn Camera>>,
+    pub shutter_open: f64,
+    pub shutter_close: f64,
+    /** Traces one wavelength per path instead of rgb, dispersive glass only splits light this way */
+    pub spectral: bool,
+}
+
+impl Default for PathTracingSettings {
+    fn default() -> PathTracingSettings {
+        PathTracingSettings {
+            samples_per_pixel: 16,
+            russian_roulette_depth: 3,
+            max_depth: 64,
+            seed: 0,
+            direct_lighting: DirectLighting::default(),
+            pixel_sampling: PixelSampling::Jittered,
+            filter: Filter::default(),
+            sampler: SamplerKind::Sobol,
+            adaptive: None,
+            lens: None,
+            camera: None,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+            spectral: false,
+        }
+    }
+}
+
+/** Unbiased path tracer sampling the material bsdfs, emissive materials act as lights */
+pub struct PathTracingIntegrator {
+    /** Bounces before russian roulette may end a path */
+    pub russian_roulette_depth: u32,
+    /** Hard limit on the number of bounces */
+    pub max_depth: u32,
+    pub direct_lighting: DirectLighting,
+    /** Samples a wavelength per path and upsamples every color it meets to that wavelength */
+    pub spectral: bool,
+    // light selection of the direct lighting for the scene passed to new(), None sends shadow rays to every light
+    light_distribution: Option<LightDistribution>,
+}
+
+// light selection of the direct lighting, takes a pass over the scene
+fn light_distribution(scene: &Scene, direct_lighting: DirectLighting) -> Option<LightDistribution> {
+    match direct_lighting {
+        DirectLighting::LightSampling => None,
+        DirectLighting::OneLight { selection } | DirectLighting::MultipleImportance { selection, .. } => {
+            Some(LightDistribution::new(scene, selection))
+        }
+        DirectLighting::BsdfSampling => Some(LightDistribution::delta_lights(scene)),
+    }
+}
+
+impl PathTracingIntegrator {
+    /** Integrator for the scene, the light selection is built once here instead of per path */
+    pub fn new(scene: &Scene, settings: &PathTracingSettings) -> PathTracingIntegrator {
+        PathTracingIntegrator {
+            russian_roulette_depth: settings.russian_roulette_depth,
+            max_depth: settings.max_depth,
+            direct_lighting: settings.direct_lighting,
+            spectral: settings.spectral,
+            light_distribution: light_distribution(scene, settings.direct_lighting),
+        }
+    }
+
+    // light of the environment lights reaching the camera along a ray leaving the scene
+    fn add_environment(
+        &self,
+        radiance: &mut Color,
+        throughput: &Color,
+        ray: &Ray,
+        light_sampler: &DirectLightSampler,
+        previous: &Option<(Point3, f64)>,
+        wavelength: Option<f64>,
+    ) {
+        for (index, light) in light_sampler.scene.lights.iter().enumerate() {
+            if let Light::Environment(l) = light {
+                // environment lights may also have been sampled at the previous vertex
+                let weight = match (previous, self.direct_lighting) {
+                    (Some(_), DirectLighting::LightSampling | DirectLighting::OneLight { .. }) => 0.0,
+                    (Some((_, bsdf_pdf)), DirectLighting::MultipleImportance { heuristic, .. }) => {
+                        let selection = light_sampler.distribution.map_or(0.0, |d| d.probabilities[index]);
+                        heuristic.weight(*bsdf_pdf, selection * l.pdf(&ray.direction))
+                    }
+                    _ => 1.0,
+                };
+                if weight > 0.0 {
+                    *radiance += &(throughput * &(&at_wavelength(&l.radiance(&ray.direction), wavelength) * weight));
+                }
+            }
+        }
+    }
+
+    // russian roulette, false when the path ends
+    fn survives(&self, depth: u32, throughput: &mut Color, sampler: &mut dyn Sampler) -> bool {
+        if depth + 1 < self.russian_roulette_depth {
+            return true;
+        }
+        let survival = throughput.red.max(throughput.green).max(throughput.blue).min(0.95);
+        if sampler.get_1d() >= survival {
+            return false;
+        }
+        *throughput = &*throughput * (1.0 / survival);
+        true
+    }
+}
+
+impl Integrator for PathTracingIntegrator {
+    fn li(&self, ray: &Ray, scene: &Scene, sampler: &mut dyn Sampler) -> Spectrum {
+        if !self.spectral {
+            return self.trace(ray, scene, sampler, None);
+        }
+        let (wavelength, pdf) = sample_wavelength(sampler.get_1d());
+        // all channels of the path carry the radiance at its wavelength
+        let radiance = self.trace(ray, scene, sampler, Some(wavelength)).red;
+        wavelength_to_rgb(wavelength, radiance, pdf)
+    }
+}
+
+impl PathTracingIntegrator {
+    fn trace(&self, ray: &Ray, scene: &Scene, sampler: &mut dyn Sampler, wavelength: Option<f64>) -> Spectrum {
+        let mut radiance = black();
+        // fraction of the light at the current vertex reaching the camera
+        let mut throughput = Color {
+            red: 1.0,
+            green: 1.0,
+            blue: 1.0,
+        };
+        // unit directions, distances in media are lengths
+        let mut ray = Ray {
+            origin: ray.origin.clone(),
+            direction: ray.direction.normalize(),
+            time: ray.time,
+        };
+        // another scene than the one of new() has lights of its own, its selection is built for this path
+        let rebuilt: Option<LightDistribution>;
+        let distribution = match &self.light_distribution {
+            Some(d) if d.probabilities.len() != scene.lights.len() => {
+                rebuilt = light_distribution(scene, self.direct_lighting);
+                rebuilt.as_ref()
+            }
+            d => d.as_ref(),
+        };
+        let light_sampler = DirectLightSampler {
+            scene,
+            distribution,
+            heuristic: match self.direct_lighting {
+                DirectLighting::MultipleImportance { heuristic, .. } => Some(heuristic),
+                _ => None,
+            },
+        };
+        // shadow ray origin and bsdf density of the last diffuse or glossy bounce, None after a specular one
+        let mut previous: Option<(Point3, f64)> = None;
+        // the camera is assumed to be outside of all closed meshes
+        let mut medium: Option<&Medium> = scene.medium.as_deref();
+        for depth in 0..self.max_depth {
+            let hit = scene.trace_hit(&ray);
+            if let Some(m) = medium {
+                let t_max = hit.as_ref().map_or(f64::INFINITY, |hit| hit.distance);
+                match m.sample_interaction(&ray, t_max, sampler) {
+                    MediumInteraction::Scattered { distance, weight } => {
+                        throughput = &throughput * &at_wavelength(&weight, wavelength);
+                        let point = &ray.origin + &(&ray.direction * distance);
+                        let context = ShadingContext {
+                            normal: Vector3::zero(),
+                            view_direction: &ray.direction * -1.0,
+                            albedo: &MEDIUM_ALBEDO,
+                            front_face: true,
+                            wavelength,
+                        };
+                        let direct = light_sampler.sample(&m.phase, &context, &point, ray.time, Some(m), sampler);
+                        radiance += &(&throughput * &direct);
+                        let (u1, u2) = sampler.get_2d();
+                        let (direction, pdf) = m.phase.sample(&ray.direction, u1, u2);
+                        if !self.survives(depth, &mut throughput, sampler) {
+                            break;
+                        }
+                        previous = Some((point.clone(), pdf));
+                        ray = Ray {
+                            origin: point,
+                            direction,
+                            time: ray.time,
+                        };
+                        continue;
+                    }
+                    MediumInteraction::Passed { weight } => throughput = &throughput * &at_wavelength(&weight, wavelength),
+                }
+            }
+            let hit = match hit {
+                Some(hit) => hit,
+                None => {
+                    self.add_environment(&mut radiance, &throughput, &ray, &light_sampler, &previous, wavelength);
+                    break;
+                }
+            };
+            let material = hit.element.material();
+            if let Some(emission) = &material.emission {
+                // area lights may also have been reached by sampling them at the previous vertex
+                let weight = match (&previous, scene.area_light(material), self.direct_lighting) {
+                    (Some(_), Some(_), DirectLighting::LightSampling | DirectLighting::OneLight { .. }) => 0.0,
+                    (Some((origin, bsdf_pdf)), Some((index, light)), DirectLighting::MultipleImportance { heuristic, .. }) => {
+                        let selection = light_sampler.distribution.map_or(0.0, |d| d.probabilities[index]);
+                        heuristic.weight(*bsdf_pdf, selection * light.pdf(origin, &hit.point, &hit.geometric_normal))
+                    }
+                    _ => 1.0,
+                };
+                if weight > 0.0 {
+                    radiance += &(&throughput * &(&at_wavelength(emission, wavelength) * weight));
+                }
+            }
+
+            let view_direction = &ray.direction * -1.0;
+            // models without a physical interpretation scatter like a diffuse surface
+            let bsdf: &dyn Bsdf = material.shading_model.bsdf().unwrap_or(&Lambert);
+            let context = ShadingContext {
+                normal: hit.normal.clone(),
+                view_direction,
+                albedo: &material.albedo,
+                front_face: hit.front_face,
+                wavelength,
+            };
+            // point and directional lights can't be hit by a sampled direction, they are looked up directly
+            let interface = material.shading_model.is_interface();
+            if !interface {
+                let direct = light_sampler.sample(bsdf, &context, &hit.point, ray.time, medium, sampler);
+                radiance += &(&throughput * &direct);
+            }
+
+            let u_lobe = sampler.get_1d();
+            let (u1, u2) = sampler.get_2d();
+            let sample = match bsdf.sample(&context, u_lobe, u1, u2) {
+                Some(sample) => sample,
+                None => break,
+            };
+            throughput = &throughput * &at_wavelength(&sample.weight, wavelength);
+            if !self.survives(depth, &mut throughput, sampler) {
+                break;
+            }
+            // crossing a boundary shadow rays pass through keeps the last scattering vertex for weighting the light found behind it
+            let crossed = sample.direction.dot(&hit.geometric_normal) < 0.0;
+            let passed = crossed && material.shading_model.shadow_transmission(1.0, hit.front_face).is_some();
+            if !passed {
+                previous = if sample.specular {
+                    None
+                } else {
+                    Some((&hit.point + &(&hit.geometric_normal * 1e-4), sample.pdf))
+                };
+            }
+            if crossed {
+                medium = medium_after(scene, material.interior.as_deref(), hit.front_face);
+            }
+            ray = spawn_ray(&hit, sample.direction);
+        }
+        radiance
+    }
+}
+
+// phase functions don't use the albedo, the scattering albedo is part of the medium's weights
+const MEDIUM_ALBEDO: Color = Color {
+    red: 255.0,
+    green: 255.0,
+    blue: 255.0,
+};
+
+/** Linear radiance of every pixel, stored row by row */
+pub fn render_path_traced(scene: &Scene, settings: &PathTracingSettings) -> Vec<Color> {
+    let integrator = PathTracingIntegrator::new(scene, settings);
+    let render_settings = RenderSettings {
+        samples_per_pixel: settings.samples_per_pixel,
+        seed: settings.seed,
+        pixel_sampling: settings.pixel_sampling,
+        filter: settings.filter,
+        sampler: settings.sampler,
+        adaptive: settings.adaptive,
+        lens: settings.lens,
+        camera: settings.camera.clone(),
+        shutter_open: settings.shutter_open,
+        shutter_close: settings.shutter_close,
+        ..Default::default()
+    };
+    render_radiance(scene, &integrator, &render_settings)
+}
+
+#[cfg(test)]
+mod test_path_tracing {
+    use super::*;
+    use crate::point::Point3;
+    use crate::area_light::{AreaLight, AreaShape};
+    use crate::bsdf::MetallicRoughness;
+    use crate::integrator::DirectIntegrator;
+    use crate::vector::Vector3;
+    use crate::material::Material;
+    use crate::mis::{LightSelection, MisHeuristic};
+    use crate::environment::EnvironmentLight;
+    use crate::medium::{DensityGrid, HenyeyGreenstein, Interface};
+    use crate::subsurface::SubsurfaceScattering;
+    use crate::scene::{Element, Plane, PointLight, Triangle};
+
+    fn triangle(points: [(f64, f64, f64); 3], color: Color, material: Material) -> Element {
+        let [p1, p2, p3] = points;
+        Element::Triangle(Triangle {
+            point1: Point3 { x: p1.0, y: p1.1, z: p1.2 },
+            point2: Point3 { x: p2.0, y: p2.1, z: p2.2 },
+            point3: Point3 { x: p3.0, y: p3.1, z: p3.2 },
+            normals: None,
+            st: None,
+            tangent_frame: None,
+            material: Arc::new(Material { albedo: color, ..material }),
+        })
+    }
+
+    fn emissive(red: f64) -> Material {
+        Material {
+            emission: Some(Color {
+                red,
+                green: 0.0,
+                blue: 0.0,
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn gray() -> Color {
+        Color {
+            red: 128.0,
+            green: 128.0,
+            blue: 128.0,
+        }
+    }
+
+    #[test]
+    fn emitter_seen_directly() {
+        let scene = Scene {
+            width: 3,
+            height: 3,
+            fov: 90.0,
+            elements: vec![triangle([(-10.0, -10.0, -5.0), (10.0, -10.0, -5.0), (0.0, 10.0, -5.0)], gray(), emissive(300.0))],
+            lights: Vec::new(),
+            medium: None,
+        };
+        let radiance = render_path_traced(&scene, &PathTracingSettings::default());
+        // nothing else to bounce off, the center pixel sees exactly the emission
+        assert_eq!(radiance[4].red, 300.0);
+        assert_eq!(radiance[4].green, 0.0);
+    }
+
+    #[test]
+    fn integrator_selects_the_lights_of_the_scene_it_traces() {
+        let wall = |lights: Vec<Light>| Scene {
+            width: 1,
+            height: 1,
+            fov: 90.0,
+            elements: vec![triangle([(-10.0, -10.0, -5.0), (10.0, -10.0, -5.0), (0.0, 10.0, -5.0)], gray(), Material::default())],
+            lights,
+            medium: None,
+        };
+        let unlit = wall(Vec::new());
+        let lit = wall(vec![Light::Point(PointLight {
+            position: Point3::zero(),
+            color: Color::from_one(255.0),
+            intensity: 50.0,
+        })]);
+        let settings = PathTracingSettings {
+            direct_lighting: DirectLighting::OneLight {
+                selection: LightSelection::Uniform,
+            },
+            ..Default::default()
+        };
+        let integrator = PathTracingIntegrator::new(&unlit, &settings);
+        let ray = Ray {
+            origin: Point3::zero(),
+            direction: Vector3 { x: 0.0, y: 0.0, z: -1.0 },
+            time: 0.0,
+        };
+        let mut sampler = crate::sampler::IndependentSampler::new(0);
+        assert!(integrator.li(&ray, &lit, &mut sampler).red > 0.0);
+    }
+
+    #[test]
+    fn emitter_lights_diffuse_surface() {
+        // a diffuse wall facing the camera, a large emitter behind the camera lights it
+        let scene = Scene {
+            width: 4,
+            height: 4,
+            fov: 60.0,
+            elements: vec![
+                triangle([(-10.0, -10.0, -5.0), (10.0, -10.0, -5.0), (0.0, 10.0, -5.0)], gray(), Material::default()),
+                triangle([(-50.0, -50.0, 5.0), (50.0, -50.0, 5.0), (0.0, 50.0, 5.0)], gray(), emissive(100.0)),
+            ],
+            lights: Vec::new(),
+            medium: None,
+        };
+        let settings = PathTracingSettings {
+            samples_per_pixel: 64,
+            ..Default::default()
+        };
+        let radiance = render_path_traced(&scene, &settings);
+        let again = render_path_traced(&scene, &settings);
+        for (a, b) in radiance.iter().zip(again.iter()) {
+            assert!(a.red > 0.0);
+            assert_eq!(a.red, b.red);
+        }
+    }
+
+    #[test]
+    fn smooth_metal_mirrors_emitter() {
+        let white = Color {
+            red: 255.0,
+            green: 255.0,
+            blue: 255.0,
+        };
+        let metal = Material {
+            shading_model: Box::new(MetallicRoughness {
+                metallic: 1.0,
+                roughness: 0.0,
+            }),
+            ..Default::default()
+        };
+        let scene = Scene {
+            width: 3,
+            height: 3,
+            fov: 60.0,
+            elements: vec![
+                triangle([(-10.0, -10.0, -5.0), (10.0, -10.0, -5.0), (0.0, 10.0, -5.0)], white, metal),
+                triangle([(-50.0, -50.0, 5.0), (50.0, -50.0, 5.0), (0.0, 50.0, 5.0)], gray(), emissive(100.0)),
+            ],
+            lights: Vec::new(),
+            medium: None,
+        };
+        let settings = PathTracingSettings {
+            samples_per_pixel: 4,
+            max_depth: 2,
+            ..Default::default()
+        };
+        let radiance = render_path_traced(&scene, &settings);
+        // a white metal reflects everything at normal incidence
+        assert!((radiance[4].red - 100.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn area_light_is_not_counted_twice() {
+        let mut scene = Scene {
+            width: 3,
+            height: 3,
+            fov: 60.0,
+            elements: vec![triangle([(-10.0, -10.0, -5.0), (10.0, -10.0, -5.0), (0.0, 10.0, -5.0)], gray(), Material::default())],
+            lights: Vec::new(),
+            medium: None,
+        };
+        // behind the camera, facing the wall
+        scene.add_area_light(AreaLight::new(
+            AreaShape::Rectangle {
+                corner: Point3 { x: -2.0, y: -2.0, z: 2.0 },
+                edge1: Vector3 { x: 4.0, y: 0.0, z: 0.0 },
+                edge2: Vector3 { x: 0.0, y: 4.0, z: 0.0 },
+            },
+            emissive(1000.0).emission.unwrap(),
+        ));
+        let settings = RenderSettings {
+            samples_per_pixel: 256,
+            ..Default::default()
+        };
+        let direct = render_radiance(&scene, &DirectIntegrator, &settings)[4].red;
+        let path_traced = render_path_traced(
+            &scene,
+            &PathTracingSettings {
+                samples_per_pixel: 256,
+                ..Default::default()
+            },
+        )[4]
+            .red;
+        assert!(direct > 0.0);
+        // light reaching the wall through bounce rays is the same light next event estimation already found
+        assert!((path_traced - direct).abs() < 0.05 * direct);
+    }
+
+    #[test]
+    fn direct_lighting_strategies_agree() {
+        let glossy = Material {
+            shading_model: Box::new(MetallicRoughness {
+                metallic: 0.0,
+                roughness: 0.3,
+            }),
+            ..Default::default()
+        };
+        let mut scene = Scene {
+            width: 3,
+            height: 3,
+            fov: 60.0,
+            elements: vec![triangle([(-10.0, -10.0, -5.0), (10.0, -10.0, -5.0), (0.0, 10.0, -5.0)], gray(), glossy)],
+            lights: Vec::new(),
+            medium: None,
+        };
+        scene.add_area_light(AreaLight::new(
+            AreaShape::Rectangle {
+                corner: Point3 { x: -1.0, y: -1.0, z: 2.0 },
+                edge1: Vector3 { x: 2.0, y: 0.0, z: 0.0 },
+                edge2: Vector3 { x: 0.0, y: 2.0, z: 0.0 },
+            },
+            emissive(1000.0).emission.unwrap(),
+        ));
+        let render = |direct_lighting| {
+            let settings = PathTracingSettings {
+                samples_per_pixel: 4096,
+                // the wall lit directly, bounce rays need a second vertex to find the light
+                max_depth: 2,
+                direct_lighting,
+                ..Default::default()
+            };
+            render_path_traced(&scene, &settings)[4].red
+        };
+        let light_sampling = render(DirectLighting::LightSampling);
+        let bsdf_sampling = render(DirectLighting::BsdfSampling);
+        let balance = render(DirectLighting::MultipleImportance {
+            heuristic: MisHeuristic::Balance,
+            selection: LightSelection::Uniform,
+        });
+        let power = render(DirectLighting::default());
+        let one_light = render(DirectLighting::OneLight {
+            selection: LightSelection::Power,
+        });
+        assert!(light_sampling > 0.0);
+        for estimate in [balance, power, one_light] {
+            assert!((estimate - light_sampling).abs() < 0.03 * light_sampling);
+        }
+        // bounce rays rarely find the small light, that estimate is a lot noisier
+        assert!((bsdf_sampling - light_sampling).abs() < 0.1 * light_sampling);
+    }
+
+    #[test]
+    fn wall_under_constant_sky() {
+        let mut scene = Scene {
+            width: 3,
+            height: 3,
+            fov: 60.0,
+            elements: vec![Element::Plane(Plane {
+                origin: Point3 { x: 0.0, y: 0.0, z: -5.0 },
+                // planes are hit by rays travelling along their normal
+                normal: Vector3 { x: 0.0, y: 0.0, z: -1.0 },
+                material: Arc::new(Material {
+                    albedo: gray(),
+                    ..Default::default()
+                }),
+            })],
+            lights: Vec::new(),
+            medium: None,
+        };
+        scene.lights.push(Light::Environment(EnvironmentLight::constant(Color {
+            red: 100.0,
+            green: 100.0,
+            blue: 100.0,
+        })));
+        // the wall sees half of the sky, a diffuse surface reflects its albedo of it
+        let expected = 100.0 * 128.0 / 255.0;
+        for direct_lighting in [DirectLighting::LightSampling, DirectLighting::BsdfSampling, DirectLighting::default()] {
+            let settings = PathTracingSettings {
+                samples_per_pixel: 2048,
+                direct_lighting,
+                ..Default::default()
+            };
+            let radiance = render_path_traced(&scene, &settings)[4].red;
+            assert!((radiance - expected).abs() < 0.05 * expected, "{:?} {}", direct_lighting, radiance);
+        }
+    }
+
+    // closed cube with outward facing triangles
+    fn cube(center: Point3, half_size: f64, material: Arc<Material>) -> Vec<Element> {
+        let axis = |x: f64, y: f64, z: f64| Vector3 { x, y, z };
+        let faces = [
+            (axis(1.0, 0.0, 0.0), axis(0.0, 1.0, 0.0), axis(0.0, 0.0, 1.0)),
+            (axis(-1.0, 0.0, 0.0), axis(0.0, 0.0, 1.0), axis(0.0, 1.0, 0.0)),
+            (axis(0.0, 1.0, 0.0), axis(0.0, 0.0, 1.0), axis(1.0, 0.0, 0.0)),
+            (axis(0.0, -1.0, 0.0), axis(1.0, 0.0, 0.0), axis(0.0, 0.0, 1.0)),
+            (axis(0.0, 0.0, 1.0), axis(1.0, 0.0, 0.0), axis(0.0, 1.0, 0.0)),
+            (axis(0.0, 0.0, -1.0), axis(0.0, 1.0, 0.0), axis(1.0, 0.0, 0.0)),
+        ];
+        let mut res = Vec::new();
+        for (n, u, v) in faces.iter() {
+            let corner = |a: f64, b: f64| &center + &(&(n.clone() + u * a + v * b) * half_size);
+            let quad = [corner(-1.0, -1.0), corner(1.0, -1.0), corner(1.0, 1.0), corner(-1.0, 1.0)];
+            for [i, j, k] in [[0, 1, 2], [0, 2, 3]] {
+                res.push(Element::Triangle(Triangle {
+                    point1: quad[i].clone(),
+                    point2: quad[j].clone(),
+                    point3: quad[k].clone(),
+                    normals: None,
+                    st: None,
+                    tangent_frame: None,
+                    material: material.clone(),
+                }));
+            }
+        }
+        res
+    }
+
+    fn under_sky(elements: Vec<Element>, medium: Option<Medium>) -> Scene {
+        Scene {
+            width: 3,
+            height: 3,
+            fov: 30.0,
+            elements,
+            lights: vec![Light::Environment(EnvironmentLight::constant(Color {
+                red: 100.0,
+                green: 100.0,
+                blue: 100.0,
+            }))],
+            medium: medium.map(Arc::new),
+        }
+    }
+
+    #[test]
+    fn scattering_medium_conserves_light() {
+        // a box of white fog under a uniform sky looks like the sky itself
+        let boundary = Arc::new(Material {
+            shading_model: Box::new(Interface),
+            interior: Some(Arc::new(Medium::fog(0.0, 1.0, 0.5))),
+            ..Default::default()
+        });
+        let scene = under_sky(cube(Point3 { x: 0.0, y: 0.0, z: -5.0 }, 1.0, boundary), None);
+        let settings = PathTracingSettings {
+            samples_per_pixel: 4096,
+            ..Default::default()
+        };
+        for pixel in render_path_traced(&scene, &settings) {
+            assert!((pixel.red - 100.0).abs() < 4.0, "{}", pixel.red);
+        }
+    }
+
+    #[test]
+    fn media_absorb_along_the_ray() {
+        let absorbing = Arc::new(Material {
+            shading_model: Box::new(Interface),
+            interior: Some(Arc::new(Medium::fog(0.25, 0.0, 0.0))),
+            ..Default::default()
+        });
+        // the center ray crosses 2 units of the box
+        let scene = under_sky(cube(Point3 { x: 0.0, y: 0.0, z: -5.0 }, 1.0, absorbing), None);
+        let settings = PathTracingSettings {
+            samples_per_pixel: 4096,
+            ..Default::default()
+        };
+        let expected = 100.0 * (-0.5_f64).exp();
+        assert!((render_path_traced(&scene, &settings)[4].red - expected).abs() < 0.03 * expected);
+        // fog around the emitter seen 5 units away
+        let mut foggy = under_sky(
+            vec![triangle([(-10.0, -10.0, -5.0), (10.0, -10.0, -5.0), (0.0, 10.0, -5.0)], gray(), emissive(300.0))],
+            Some(Medium::fog(0.1, 0.0, 0.0)),
+        );
+        foggy.lights.clear();
+        let expected = 300.0 * (-0.5_f64).exp();
+        assert!((render_path_traced(&foggy, &settings)[4].red - expected).abs() < 0.03 * expected);
+    }
+
+    #[test]
+    fn heterogeneous_fog_is_lit_through_its_boundary() {
+        let grid = DensityGrid::new(
+            Point3 { x: -1.0, y: -1.0, z: -6.0 },
+            Point3 { x: 1.0, y: 1.0, z: -4.0 },
+            [2, 2, 2],
+            vec![0.0, 2.0, 1.0, 0.5, 2.0, 0.0, 1.0, 1.5],
+        );
+        let medium = Medium {
+            sigma_a: [0.0; 3],
+            sigma_s: [1.0, 0.5, 0.25],
+            phase: HenyeyGreenstein { g: 0.0 },
+            density: Some(grid),
+        };
+        let boundary = Arc::new(Material {
+            shading_model: Box::new(Interface),
+            interior: Some(Arc::new(medium)),
+            ..Default::default()
+        });
+        let scene = under_sky(cube(Point3 { x: 0.0, y: 0.0, z: -5.0 }, 1.0, boundary), None);
+        let settings = PathTracingSettings {
+            samples_per_pixel: 4096,
+            ..Default::default()
+        };
+        // nothing absorbs, every channel still sees the sky
+        let pixel = &render_path_traced(&scene, &settings)[4];
+        for channel in [pixel.red, pixel.green, pixel.blue] {
+            assert!((channel - 100.0).abs() < 5.0, "{}", channel);
+        }
+    }
+
+    #[test]
+    fn subsurface_scattering_tints_by_albedo() {
+        let white = Color {
+            red: 255.0,
+            green: 255.0,
+            blue: 255.0,
+        };
+        let settings = PathTracingSettings {
+            samples_per_pixel: 4096,
+            max_depth: 256,
+            ..Default::default()
+        };
+        // nothing is absorbed inside a white object, it looks like the sky around it
+        let lossless = Arc::new(SubsurfaceScattering::new(white, [0.5; 3]).into_material());
+        let scene = under_sky(cube(Point3 { x: 0.0, y: 0.0, z: -5.0 }, 1.0, lossless), None);
+        let pixel = &render_path_traced(&scene, &settings)[4];
+        assert!((pixel.red - 100.0).abs() < 5.0, "{}", pixel.red);
+        let orange = Color {
+            red: 230.0,
+            green: 120.0,
+            blue: 40.0,
+        };
+        let tinted = Arc::new(SubsurfaceScattering::new(orange, [0.5; 3]).into_material());
+        let scene = under_sky(cube(Point3 { x: 0.0, y: 0.0, z: -5.0 }, 1.0, tinted), None);
+        let pixel = &render_path_traced(&scene, &settings)[4];
+        assert!(pixel.red > pixel.green && pixel.green > pixel.blue);
+    }
+
+    #[test]
+    fn subsurface_scattering_is_lit_by_point_lights() {
+        let light = || {
+            Light::Point(PointLight {
+                position: Point3 { x: 0.0, y: 3.0, z: 0.0 },
+                color: Color::from_one(255.0),
+                intensity: 50.0,
+            })
+        };
+        let settings = PathTracingSettings {
+            samples_per_pixel: 256,
+            max_depth: 256,
+            ..Default::default()
+        };
+        let render = |material: Material| {
+            let scene = Scene {
+                lights: vec![light()],
+                ..under_sky(cube(Point3 { x: 0.0, y: 0.0, z: -5.0 }, 1.0, Arc::new(material)), None)
+            };
+            render_path_traced(&scene, &settings)[4].red
+        };
+        // point lights can't be hit, the light inside is only found by shadow rays leaving through the boundary
+        let wax = render(SubsurfaceScattering::wax().into_material());
+        let diffuse = render(Material {
+            albedo: SubsurfaceScattering::wax().albedo,
+            ..Default::default()
+        });
+        assert!(wax > 0.25 * diffuse, "{} {}", wax, diffuse);
+    }
+
+    #[test]
+    fn spectral_rendering_agrees_with_rgb() {
+        let orange = Color {
+            red: 200.0,
+            green: 120.0,
+            blue: 40.0,
+        };
+        let white_light = Material {
+            emission: Some(Color::from_one(100.0)),
+            ..Default::default()
+        };
+        let scene = Scene {
+            width: 3,
+            height: 3,
+            fov: 60.0,
+            elements: vec![
+                triangle([(-10.0, -10.0, -5.0), (10.0, -10.0, -5.0), (0.0, 10.0, -5.0)], orange, Material::default()),
+                triangle([(-50.0, -50.0, 5.0), (50.0, -50.0, 5.0), (0.0, 50.0, 5.0)], gray(), white_light),
+            ],
+            lights: Vec::new(),
+            medium: None,
+        };
+        let rgb = &render_path_traced(&scene, &PathTracingSettings::default())[4];
+        let settings = PathTracingSettings {
+            samples_per_pixel: 1024,
+            spectral: true,
+            ..Default::default()
+        };
+        let spectral = &render_path_traced(&scene, &settings)[4];
+        // the upsampled spectra integrate back to about the same color
+        for (a, b) in [(rgb.red, spectral.red), (rgb.green, spectral.green), (rgb.blue, spectral.blue)].iter() {
+            assert!((a - b).abs() < 0.05 * a, "{:?} {:?}", rgb, spectral);
+        }
+    }
+}
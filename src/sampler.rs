@@ -0,0 +1,605 @@
+use crate::sampling::Rng;
+use std::sync::OnceLock;
+
+/** Source of the sample values of a render. Each pixel sample asks for its values dimension by dimension,
+the same pixel, sample index and seed always give the same values. */
+pub trait Sampler {
+    /** Restarts at the first dimension of the given sample of a pixel */
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32);
+    /** Next value in [0, 1) */
+    fn get_1d(&mut self) -> f64;
+    /** Next pair of values in [0, 1)², stratified together */
+    fn get_2d(&mut self) -> (f64, f64);
+}
+
+/** The samplers RenderSettings can pick from */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SamplerKind {
+    Independent,
+    Stratified,
+    Halton,
+    /** Sobol points, Owen scrambled for every pixel */
+    Sobol,
+    /** Low discrepancy points offset by a blue noise mask, the remaining error looks like blue noise */
+    BlueNoise,
+}
+
+impl SamplerKind {
+    pub fn create(&self, samples_per_pixel: u32, seed: u64) -> Box<dyn Sampler> {
+        match self {
+            SamplerKind::Independent => Box::new(IndependentSampler::new(seed)),
+            SamplerKind::Stratified => Box::new(StratifiedSampler::new(samples_per_pixel, seed)),
+            SamplerKind::Halton => Box::new(HaltonSampler::new(seed)),
+            SamplerKind::Sobol => Box::new(SobolSampler::new(seed)),
+            SamplerKind::BlueNoise => Box::new(BlueNoiseSampler::new(seed)),
+        }
+    }
+}
+
+/** Mixes the values into one well distributed 64 bit hash */
+pub fn hash(values: &[u64]) -> u64 {
+    let mut h: u64 = 0x9e3779b97f4a7c15;
+    for v in values {
+        // splitmix64 finalizer
+        let mut z = h ^ v.wrapping_add(0x9e3779b97f4a7c15).wrapping_add(h << 6).wrapping_add(h >> 2);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        h = z ^ (z >> 31);
+    }
+    h
+}
+
+fn hash_to_f64(h: u64) -> f64 {
+    (h >> 11) as f64 / (1u64 << 53) as f64
+}
+
+fn u32_to_f64(v: u32) -> f64 {
+    v as f64 / 4294967296.0
+}
+
+/** Element i of a random permutation of 0..n picked by the seed, after Kensler */
+pub fn permutation_element(mut i: u32, n: u32, seed: u32) -> u32 {
+    let mut w = n.wrapping_sub(1);
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < n {
+            break;
+        }
+    }
+    (i.wrapping_add(seed)) % n
+}
+
+/** Independent uniform values, one random stream per pixel sample */
+pub struct IndependentSampler {
+    seed: u64,
+    rng: Rng,
+}
+
+impl IndependentSampler {
+    pub fn new(seed: u64) -> IndependentSampler {
+        IndependentSampler {
+            seed,
+            rng: Rng::new(seed, hash(&[0, 0, 0])),
+        }
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.rng = Rng::new(self.seed, hash(&[x as u64, y as u64, index as u64]));
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        self.rng.next_f64()
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.rng.next_f64(), self.rng.next_f64())
+    }
+}
+
+// pixel, sample index and dimension the next value is drawn for
+#[derive(Clone, Copy, Default)]
+struct SampleState {
+    x: u32,
+    y: u32,
+    index: u32,
+    dimension: u32,
+}
+
+impl SampleState {
+    fn start(&mut self, x: u32, y: u32, index: u32) {
+        *self = SampleState { x, y, index, dimension: 0 };
+    }
+
+    // hash of the pixel, the current dimension and the extra values, the same for every sample index
+    fn pixel_hash(&self, seed: u64, extra: u64) -> u64 {
+        hash(&[seed, self.x as u64, self.y as u64, self.dimension as u64, extra])
+    }
+
+    // hash that also changes with the sample index
+    fn sample_hash(&self, seed: u64, extra: u64) -> u64 {
+        hash(&[seed, self.x as u64, self.y as u64, self.dimension as u64, self.index as u64, extra])
+    }
+}
+
+/** Jittered strata, shuffled independently in every dimension.
+Pairs form a grid when the sample count is a square, and n-rooks patterns otherwise. */
+pub struct StratifiedSampler {
+    pub samples_per_pixel: u32,
+    seed: u64,
+    state: SampleState,
+}
+
+impl StratifiedSampler {
+    pub fn new(samples_per_pixel: u32, seed: u64) -> StratifiedSampler {
+        StratifiedSampler {
+            samples_per_pixel: samples_per_pixel.max(1),
+            seed,
+            state: SampleState::default(),
+        }
+    }
+
+    // stratum of the current sample, samples beyond the count start another set of strata
+    fn stratum(&self, count: u32, extra: u64) -> u32 {
+        let round = (self.state.index / count) as u64;
+        permutation_element(self.state.index % count, count, self.state.pixel_hash(self.seed, extra ^ (round << 8)) as u32)
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.state.start(x, y, index);
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let n = self.samples_per_pixel;
+        let stratum = self.stratum(n, 0);
+        let jitter = hash_to_f64(self.state.sample_hash(self.seed, 0));
+        self.state.dimension += 1;
+        (stratum as f64 + jitter) / n as f64
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let n = self.samples_per_pixel;
+        let side = (n as f64).sqrt().round() as u32;
+        let (jx, jy) = (hash_to_f64(self.state.sample_hash(self.seed, 1)), hash_to_f64(self.state.sample_hash(self.seed, 2)));
+        let res = if side * side == n {
+            let stratum = self.stratum(n, 1);
+            (((stratum % side) as f64 + jx) / side as f64, ((stratum / side) as f64 + jy) / side as f64)
+        } else {
+            ((self.stratum(n, 1) as f64 + jx) / n as f64, (self.stratum(n, 2) as f64 + jy) / n as f64)
+        };
+        self.state.dimension += 2;
+        res
+    }
+}
+
+const PRIMES: [u64; 64] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97, 101, 103, 107, 109, 113, 127, 131, 137, 139, 149,
+    151, 157, 163, 167, 173, 179, 181, 191, 193, 197, 199, 211, 223, 227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281, 283, 293, 307, 311,
+];
+
+/** Digits of the index in the base, mirrored around the decimal point */
+pub fn radical_inverse(base: u64, mut index: u64) -> f64 {
+    let inverse_base = 1.0 / base as f64;
+    let mut factor = inverse_base;
+    let mut res = 0.0;
+    while index > 0 {
+        res += (index % base) as f64 * factor;
+        index /= base;
+        factor *= inverse_base;
+    }
+    res.min(1.0 - f64::EPSILON)
+}
+
+/** Halton sequence with a prime base per dimension, shifted randomly per pixel.
+Dimensions beyond the prime table fall back to independent values. */
+pub struct HaltonSampler {
+    seed: u64,
+    state: SampleState,
+}
+
+impl HaltonSampler {
+    pub fn new(seed: u64) -> HaltonSampler {
+        HaltonSampler {
+            seed,
+            state: SampleState::default(),
+        }
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.state.start(x, y, index);
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let dimension = self.state.dimension as usize;
+        let res = if dimension < PRIMES.len() {
+            // Cranley-Patterson rotation keeps the sequence stratified
+            let shift = hash_to_f64(self.state.pixel_hash(self.seed, 0));
+            (radical_inverse(PRIMES[dimension], self.state.index as u64) + shift).fract()
+        } else {
+            hash_to_f64(self.state.sample_hash(self.seed, 0))
+        };
+        self.state.dimension += 1;
+        res
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.get_1d(), self.get_1d())
+    }
+}
+
+/** One of the first two dimensions of the Sobol sequence, as 32 bit fixed point */
+pub fn sobol(index: u32, dimension: u32) -> u32 {
+    if dimension == 0 {
+        return index.reverse_bits();
+    }
+    // generator matrix of the primitive polynomial x + 1
+    let mut v: u32 = 1 << 31;
+    let mut res = 0;
+    let mut i = index;
+    while i != 0 {
+        if i & 1 != 0 {
+            res ^= v;
+        }
+        i >>= 1;
+        v ^= v >> 1;
+    }
+    res
+}
+
+// hash based Owen scrambling, after Burley
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x
+}
+
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+/** Owen scrambled Sobol points. Every dimension pair gets its own shuffle and scramble of the first two dimensions,
+so the points of a pixel stay well stratified however many dimensions a path uses. */
+pub struct SobolSampler {
+    seed: u64,
+    state: SampleState,
+}
+
+impl SobolSampler {
+    pub fn new(seed: u64) -> SobolSampler {
+        SobolSampler {
+            seed,
+            state: SampleState::default(),
+        }
+    }
+
+    fn shuffled_index(&self) -> u32 {
+        nested_uniform_scramble(self.state.index, self.state.pixel_hash(self.seed, 0) as u32)
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.state.start(x, y, index);
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let index = self.shuffled_index();
+        let res = u32_to_f64(nested_uniform_scramble(sobol(index, 0), self.state.pixel_hash(self.seed, 1) as u32));
+        self.state.dimension += 1;
+        res
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let index = self.shuffled_index();
+        let res = (
+            u32_to_f64(nested_uniform_scramble(sobol(index, 0), self.state.pixel_hash(self.seed, 1) as u32)),
+            u32_to_f64(nested_uniform_scramble(sobol(index, 1), self.state.pixel_hash(self.seed, 2) as u32)),
+        );
+        self.state.dimension += 2;
+        res
+    }
+}
+
+const MASK_SIZE: usize = 64;
+
+/** Tileable blue noise thresholds in [0, 1), built with Ulichney's void and cluster method */
+pub fn blue_noise_mask() -> &'static [f64] {
+    static MASK: OnceLock<Vec<f64>> = OnceLock::new();
+    MASK.get_or_init(|| void_and_cluster(MASK_SIZE, 1.5, 7))
+}
+
+fn void_and_cluster(size: usize, sigma: f64, seed: u64) -> Vec<f64> {
+    let n = size * size;
+    // gaussian energy of a point, wrapped around the tile
+    let kernel: Vec<f64> = (0..n)
+        .map(|i| {
+            let wrap = |d: usize| d.min(size - d) as f64;
+            let (dx, dy) = (wrap(i % size), wrap(i / size));
+            (-(dx * dx + dy * dy) / (2.0 * sigma * sigma)).exp()
+        })
+        .collect();
+    let mut energy = vec![0.0; n];
+    let update = |energy: &mut Vec<f64>, p: usize, sign: f64| {
+        let (px, py) = (p % size, p / size);
+        for (i, e) in energy.iter_mut().enumerate() {
+            let dx = (i % size + size - px) % size;
+            let dy = (i / size + size - py) % size;
+            *e += sign * kernel[dy * size + dx];
+        }
+    };
+    let extreme = |energy: &Vec<f64>, set: &Vec<bool>, wanted: bool, largest: bool| {
+        let mut best = None;
+        for i in (0..n).filter(|&i| set[i] == wanted) {
+            best = match best {
+                Some(b) if (energy[i] > energy[b]) != largest || energy[i] == energy[b] => Some(b),
+                _ => Some(i),
+            };
+        }
+        best.unwrap()
+    };
+    // random initial pattern with a tenth of the points set
+    let mut rng = Rng::new(seed, 0);
+    let mut set = vec![false; n];
+    let initial = n / 10;
+    let mut count = 0;
+    while count < initial {
+        let p = (rng.next_u32() as usize) % n;
+        if !set[p] {
+            set[p] = true;
+            update(&mut energy, p, 1.0);
+            count += 1;
+        }
+    }
+    // move points from the tightest cluster to the largest void until they stay put
+    loop {
+        let cluster = extreme(&energy, &set, true, true);
+        set[cluster] = false;
+        update(&mut energy, cluster, -1.0);
+        let void = extreme(&energy, &set, false, false);
+        set[void] = true;
+        update(&mut energy, void, 1.0);
+        if void == cluster {
+            break;
+        }
+    }
+    let mut rank = vec![0; n];
+    // remove the initial points cluster by cluster, ranking them downwards
+    let (mut removed, mut removed_energy) = (set.clone(), energy.clone());
+    for r in (0..initial).rev() {
+        let cluster = extreme(&removed_energy, &removed, true, true);
+        removed[cluster] = false;
+        update(&mut removed_energy, cluster, -1.0);
+        rank[cluster] = r;
+    }
+    // then fill the voids, ranking upwards
+    for r in initial..n {
+        let void = extreme(&energy, &set, false, false);
+        set[void] = true;
+        update(&mut energy, void, 1.0);
+        rank[void] = r;
+    }
+    rank.iter().map(|&r| (r as f64 + 0.5) / n as f64).collect()
+}
+
+/** Blue noise dithered sampling after Georgiev and Fajardo: every pixel uses the same Sobol points,
+offset by a blue noise mask that moves to another random position for every dimension.
+The points are shuffled per dimension so that consecutive dimensions are independent. */
+pub struct BlueNoiseSampler {
+    seed: u64,
+    state: SampleState,
+}
+
+impl BlueNoiseSampler {
+    pub fn new(seed: u64) -> BlueNoiseSampler {
+        BlueNoiseSampler {
+            seed,
+            state: SampleState::default(),
+        }
+    }
+
+    // the same order for every pixel keeps the error pattern blue, another one per dimension decorrelates the dimensions
+    fn shuffled_index(&self) -> u32 {
+        nested_uniform_scramble(self.state.index, hash(&[self.seed, self.state.dimension as u64]) as u32)
+    }
+
+    fn mask(&self, extra: u64) -> f64 {
+        let offset = hash(&[self.seed, self.state.dimension as u64, extra]);
+        let x = (self.state.x as usize + (offset as usize % MASK_SIZE)) % MASK_SIZE;
+        let y = (self.state.y as usize + ((offset >> 32) as usize % MASK_SIZE)) % MASK_SIZE;
+        blue_noise_mask()[y * MASK_SIZE + x]
+    }
+}
+
+impl Sampler for BlueNoiseSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.state.start(x, y, index);
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let res = (u32_to_f64(sobol(self.shuffled_index(), 0)) + self.mask(0)).fract();
+        self.state.dimension += 1;
+        res
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let index = self.shuffled_index();
+        let res = (
+            (u32_to_f64(sobol(index, 0)) + self.mask(1)).fract(),
+            (u32_to_f64(sobol(index, 1)) + self.mask(2)).fract(),
+        );
+        self.state.dimension += 2;
+        res
+    }
+}
+
+#[cfg(test)]
+mod test_sampler {
+    use super::*;
+
+    const KINDS: [SamplerKind; 5] = [
+        SamplerKind::Independent,
+        SamplerKind::Stratified,
+        SamplerKind::Halton,
+        SamplerKind::Sobol,
+        SamplerKind::BlueNoise,
+    ];
+
+    fn values(sampler: &mut dyn Sampler, x: u32, y: u32, index: u32) -> Vec<f64> {
+        sampler.start_pixel_sample(x, y, index);
+        let (a, b) = sampler.get_2d();
+        vec![a, b, sampler.get_1d(), sampler.get_1d()]
+    }
+
+    #[test]
+    fn samples_are_reproducible_per_pixel() {
+        for kind in KINDS.iter() {
+            let mut sampler = kind.create(16, 3);
+            let first = values(sampler.as_mut(), 4, 7, 2);
+            // other pixels in between don't change the values
+            values(sampler.as_mut(), 5, 7, 0);
+            assert_eq!(first, values(kind.create(16, 3).as_mut(), 4, 7, 2), "{:?}", kind);
+            assert_eq!(first, values(sampler.as_mut(), 4, 7, 2));
+            assert_ne!(first, values(sampler.as_mut(), 9, 1, 2), "{:?}", kind);
+            assert!(first.iter().all(|v| (0.0..1.0).contains(v)));
+        }
+    }
+
+    #[test]
+    fn samples_are_uniform() {
+        for kind in KINDS.iter() {
+            let mut sampler = kind.create(64, 0);
+            let mut sums = [0.0; 3];
+            let n = 64 * 64;
+            for pixel in 0..64 {
+                for index in 0..64 {
+                    sampler.start_pixel_sample(pixel, 0, index);
+                    // skip some dimensions first
+                    sampler.get_2d();
+                    let (a, b) = sampler.get_2d();
+                    sums[0] += a;
+                    sums[1] += b;
+                    sums[2] += sampler.get_1d();
+                }
+            }
+            for sum in sums.iter() {
+                assert!((sum / n as f64 - 0.5).abs() < 0.02, "{:?} {}", kind, sum / n as f64);
+            }
+        }
+    }
+
+    #[test]
+    fn dimensions_are_independent() {
+        for kind in KINDS.iter() {
+            let mut sampler = kind.create(4096, 0);
+            // every pixel on its own has to converge to the full integral, not just the average over pixels
+            for pixel in 0..8 {
+                // means of the products of consecutive dimensions, 1/4 when they are independent
+                let mut products = [0.0; 3];
+                let n = 4096;
+                for index in 0..n {
+                    sampler.start_pixel_sample(pixel, 0, index);
+                    let (a, b) = (sampler.get_1d(), sampler.get_1d());
+                    let (c, _) = sampler.get_2d();
+                    let (d, _) = sampler.get_2d();
+                    products[0] += a * b;
+                    products[1] += b * c;
+                    products[2] += c * d;
+                }
+                for product in products.iter() {
+                    assert!((product / n as f64 - 0.25).abs() < 0.02, "{:?} {}", kind, product / n as f64);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn stratified_and_sobol_points_fill_every_cell() {
+        for kind in [SamplerKind::Stratified, SamplerKind::Sobol].iter() {
+            let mut sampler = kind.create(16, 1);
+            let points: Vec<(f64, f64)> = (0..16)
+                .map(|i| {
+                    sampler.start_pixel_sample(2, 3, i);
+                    sampler.get_1d();
+                    sampler.get_2d()
+                })
+                .collect();
+            for cell in 0..16 {
+                let (cx, cy) = ((cell % 4) as f64 / 4.0, (cell / 4) as f64 / 4.0);
+                let inside = points.iter().filter(|(x, y)| *x >= cx && *x < cx + 0.25 && *y >= cy && *y < cy + 0.25);
+                assert_eq!(inside.count(), 1, "{:?}", kind);
+            }
+        }
+        // without a square count every row and column holds one sample
+        let mut sampler = StratifiedSampler::new(5, 0);
+        let points: Vec<(f64, f64)> = (0..5)
+            .map(|i| {
+                sampler.start_pixel_sample(0, 0, i);
+                sampler.get_2d()
+            })
+            .collect();
+        for i in 0..5 {
+            let (lo, hi) = (i as f64 / 5.0, (i + 1) as f64 / 5.0);
+            assert_eq!(points.iter().filter(|(x, _)| *x >= lo && *x < hi).count(), 1);
+            assert_eq!(points.iter().filter(|(_, y)| *y >= lo && *y < hi).count(), 1);
+        }
+    }
+
+    #[test]
+    fn halton_follows_radical_inverse() {
+        assert_eq!(radical_inverse(2, 1), 0.5);
+        assert_eq!(radical_inverse(2, 3), 0.75);
+        assert!((radical_inverse(3, 5) - 7.0 / 9.0).abs() < 1e-12);
+        assert_eq!(sobol(1, 1), 1 << 31);
+        assert_eq!(sobol(2, 1), 0xc0000000);
+        let mut seen: Vec<u32> = (0..10).map(|i| permutation_element(i, 10, 1234)).collect();
+        seen.sort();
+        assert_eq!(seen, (0..10).collect::<Vec<u32>>());
+    }
+
+    #[test]
+    fn blue_noise_mask_has_no_low_frequencies() {
+        let mask = blue_noise_mask();
+        // every threshold appears once
+        let mut sorted = mask.to_vec();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert!(sorted.iter().enumerate().all(|(i, v)| (v * 4096.0 - 0.5 - i as f64).abs() < 1e-6));
+        // neighbours differ more than with white noise, where the mean difference is 1/3
+        let mut difference = 0.0;
+        for y in 0..MASK_SIZE {
+            for x in 0..MASK_SIZE {
+                difference += (mask[y * MASK_SIZE + x] - mask[y * MASK_SIZE + (x + 1) % MASK_SIZE]).abs();
+            }
+        }
+        assert!(difference / 4096.0 > 0.4, "{}", difference / 4096.0);
+    }
+}
@@ -0,0 +1,171 @@
+use crate::vector::Vector3;
+use std::f64::consts::PI;
+
+/** Small deterministic random number generator (PCG32) */
+pub struct Rng {
+    state: u64,
+    increment: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64, stream: u64) -> Rng {
+        let mut rng = Rng {
+            state: 0,
+            increment: (stream << 1) | 1,
+        };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+        rng
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old.wrapping_mul(6364136223846793005).wrapping_add(self.increment);
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        let rotation = (old >> 59) as u32;
+        xorshifted.rotate_right(rotation)
+    }
+
+    /** Uniform number in [0, 1) */
+    pub fn next_f64(&mut self) -> f64 {
+        self.next_u32() as f64 / 4294967296.0
+    }
+}
+
+/** Direction around +z with density cos(theta) / pi */
+pub fn cosine_sample_hemisphere(u1: f64, u2: f64) -> Vector3 {
+    let r = u1.sqrt();
+    let phi = 2.0 * PI * u2;
+    Vector3 {
+        x: r * phi.cos(),
+        y: r * phi.sin(),
+        z: (1.0 - u1).max(0.0).sqrt(),
+    }
+}
+
+/** Two unit vectors completing the normal to an orthonormal basis */
+pub fn orthonormal_basis(normal: &Vector3) -> (Vector3, Vector3) {
+    // branchless construction by Duff et al.
+    let sign = 1.0_f64.copysign(normal.z);
+    let a = -1.0 / (sign + normal.z);
+    let b = normal.x * normal.y * a;
+    (
+        Vector3 {
+            x: 1.0 + sign * normal.x * normal.x * a,
+            y: sign * b,
+            z: -sign * normal.x,
+        },
+        Vector3 {
+            x: b,
+            y: sign + normal.y * normal.y * a,
+            z: -normal.y,
+        },
+    )
+}
+
+/** Turns a direction given around +z into a direction around the normal */
+pub fn to_world(local: &Vector3, normal: &Vector3) -> Vector3 {
+    let (tangent, bitangent) = orthonormal_basis(normal);
+    &tangent * local.x + &bitangent * local.y + normal * local.z
+}
+
+/** Piecewise constant density over [0, 1) proportional to the given values */
+pub struct Distribution1D {
+    pub function: Vec<f64>,
+    cdf: Vec<f64>,
+    /** Average of the function, zero functions are sampled uniformly */
+    pub integral: f64,
+}
+
+impl Distribution1D {
+    pub fn new(function: Vec<f64>) -> Distribution1D {
+        let n = function.len() as f64;
+        let mut cdf = vec![0.0];
+        for f in &function {
+            let last = cdf[cdf.len() - 1];
+            cdf.push(last + f.abs() / n);
+        }
+        let integral = cdf[cdf.len() - 1];
+        for (i, c) in cdf.iter_mut().enumerate() {
+            *c = if integral > 0.0 { *c / integral } else { i as f64 / n };
+        }
+        Distribution1D { function, cdf, integral }
+    }
+
+    pub fn count(&self) -> usize {
+        self.function.len()
+    }
+
+    /** Position in [0, 1) for a uniform number, with its density and the index of its segment */
+    pub fn sample(&self, u: f64) -> (f64, f64, usize) {
+        // last segment starting at or below u, which skips empty segments
+        let index = (self.cdf.partition_point(|c| *c <= u).max(1) - 1).min(self.count() - 1);
+        let width = self.cdf[index + 1] - self.cdf[index];
+        let offset = if width > 0.0 { (u - self.cdf[index]) / width } else { 0.0 };
+        let x = ((index as f64 + offset) / self.count() as f64).min(1.0 - f64::EPSILON);
+        (x, self.pdf(index), index)
+    }
+
+    /** Density of the segment, over [0, 1) */
+    pub fn pdf(&self, index: usize) -> f64 {
+        if self.integral > 0.0 {
+            self.function[index].abs() / self.integral
+        } else {
+            1.0
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_sampling {
+    use super::*;
+
+    #[test]
+    fn rng_is_deterministic() {
+        let mut a = Rng::new(42, 7);
+        let mut b = Rng::new(42, 7);
+        let mut c = Rng::new(42, 8);
+        let first: Vec<u32> = (0..4).map(|_| a.next_u32()).collect();
+        assert_eq!(first, (0..4).map(|_| b.next_u32()).collect::<Vec<u32>>());
+        assert_ne!(first, (0..4).map(|_| c.next_u32()).collect::<Vec<u32>>());
+    }
+
+    #[test]
+    fn cosine_samples_average() {
+        // the mean of cos(theta) under a cosine density is 2/3
+        let mut rng = Rng::new(1, 1);
+        let n = 100000;
+        let mut sum = 0.0;
+        for _ in 0..n {
+            let d = cosine_sample_hemisphere(rng.next_f64(), rng.next_f64());
+            assert!((d.length() - 1.0).abs() < 1e-9);
+            sum += d.z;
+        }
+        assert!((sum / n as f64 - 2.0 / 3.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn basis_is_orthonormal() {
+        let normal = Vector3 { x: 0.3, y: -0.5, z: -0.2 }.normalize();
+        let (t, b) = orthonormal_basis(&normal);
+        assert!(t.dot(&b).abs() < 1e-12);
+        assert!(t.dot(&normal).abs() < 1e-12);
+        assert!((t.length() - 1.0).abs() < 1e-12);
+        assert!((to_world(&Vector3 { x: 0.0, y: 0.0, z: 1.0 }, &normal).dot(&normal) - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn distribution_follows_function() {
+        let distribution = Distribution1D::new(vec![1.0, 0.0, 3.0]);
+        assert_eq!(distribution.pdf(0), 0.75);
+        assert_eq!(distribution.pdf(2), 2.25);
+        let (x, pdf, index) = distribution.sample(0.125);
+        assert!((x - 1.0 / 6.0).abs() < 1e-12);
+        assert_eq!((pdf, index), (0.75, 0));
+        // the empty segment is never picked
+        assert_eq!(distribution.sample(0.25).2, 2);
+        assert_eq!(distribution.sample(0.999).2, 2);
+        assert_eq!(Distribution1D::new(vec![0.0, 0.0]).sample(0.75).2, 1);
+    }
+}
@@ -1,29 +1,69 @@
+use crate::area_light::AreaLight;
+use crate::bezier::BezierPatch;
+use crate::environment::EnvironmentLight;
+use crate::material::Material;
+use crate::medium::Medium;
+use crate::motion::AnimatedElement;
+use crate::shading::shading_normal;
 use crate::point::Point3; // get access to point struct
 use crate::vector::Vector3;
 use crate::intersection::{Ray, Intersectable};
+use std::sync::Arc;
 
-pub struct Color {
-    pub red: f64,
-    pub green: f64,
-    pub blue: f64,
-}
+pub use crate::color::Color;
 
 pub struct Sphere {
     pub center: Point3,
     pub radius: f64,
-    pub color: Color,
+    pub material: Arc<Material>,
 }
 
 pub struct Triangle {
     pub point1: Point3,
     pub point2: Point3,
     pub point3: Point3,
-    pub color: Color,
+    /** Per-vertex shading normals, the face normal is used when missing */
+    pub normals: Option<[Vector3; 3]>,
+    /** Per-vertex texture coordinates */
+    pub st: Option<[(f64, f64); 3]>,
+    /** Tangent and bitangent, the directions of increasing s and t */
+    pub tangent_frame: Option<(Vector3, Vector3)>,
+    pub material: Arc<Material>,
 }
 
 pub struct Plane {
     pub origin: Point3, 
     pub normal: Vector3,
+    pub material: Arc<Material>,
+}
+
+/** Flat circle, hit from both sides */
+pub struct Disk {
+    pub center: Point3,
+    pub normal: Vector3,
+    pub radius: f64,
+    pub material: Arc<Material>,
+}
+
+pub struct PointLight {
+    pub position: Point3,
+    pub color: Color,
+    /** Falls off with the squared distance */
+    pub intensity: f64,
+}
+
+pub struct DirectionalLight {
+    /** Direction the light travels in */
+    pub direction: Vector3,
+    pub color: Color,
+    pub intensity: f64,
+}
+
+pub enum Light {
+    Point(PointLight),
+    Directional(DirectionalLight),
+    Area(AreaLight),
+    Environment(EnvironmentLight),
 }
 
 pub struct Scene {
@@ -31,12 +71,20 @@ pub struct Scene {
     pub height: u32,
     pub fov: f64,
     pub elements: Vec<Element>, 
+    pub lights: Vec<Light>,
+    /** Medium around the elements, e.g. fog, for the path tracer */
+    pub medium: Option<Arc<Medium>>,
 }
 
+// meshes make up nearly all elements, boxing the large triangle variant would only add an indirection
+#[allow(clippy::large_enum_variant)]
 pub enum Element {
     Sphere(Sphere),
     Plane(Plane),
     Triangle(Triangle),
+    BezierPatch(BezierPatch),
+    Disk(Disk),
+    Animated(AnimatedElement),
 }
 
 impl Triangle {
@@ -45,26 +93,205 @@ impl Triangle {
         let vec2 = self.point3.to_vector() - self.point1.to_vector();
         vec1.cross(&vec2)
     }
+
+    /** Barycentric weights of a point lying in the triangle plane */
+    pub fn barycentric(&self, p: &Point3) -> (f64, f64, f64) {
+        let normal = self.calculate_normal();
+        let area = normal.norm();
+        let w1 = (&self.point3 - &self.point2).cross(&(p - &self.point2)).dot(&normal) / area;
+        let w2 = (&self.point1 - &self.point3).cross(&(p - &self.point3)).dot(&normal) / area;
+        (w1, w2, 1.0 - w1 - w2)
+    }
+
+    /** Tangent and bitangent from the texture coordinates, None when they are degenerate */
+    pub fn calculate_tangent_frame(&self, st: &[(f64, f64); 3]) -> Option<(Vector3, Vector3)> {
+        let edge1 = &self.point2 - &self.point1;
+        let edge2 = &self.point3 - &self.point1;
+        let (ds1, dt1) = (st[1].0 - st[0].0, st[1].1 - st[0].1);
+        let (ds2, dt2) = (st[2].0 - st[0].0, st[2].1 - st[0].1);
+        let det = ds1 * dt2 - ds2 * dt1;
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        let r = 1.0 / det;
+        let tangent = &(&edge1 * dt2 - &edge2 * dt1) * r;
+        let bitangent = &(&edge2 * ds1 - &edge1 * ds2) * r;
+        Some((tangent, bitangent))
+    }
+
+    /** Interpolated texture coordinates at a hit point */
+    pub fn st_at(&self, p: &Point3) -> Option<(f64, f64)> {
+        self.st.map(|[st1, st2, st3]| {
+            let (w1, w2, w3) = self.barycentric(p);
+            (
+                st1.0 * w1 + st2.0 * w2 + st3.0 * w3,
+                st1.1 * w1 + st2.1 * w2 + st3.1 * w3,
+            )
+        })
+    }
+
+    /** Shading normal at a hit point, interpolated from the vertex normals */
+    pub fn normal_at(&self, p: &Point3) -> Vector3 {
+        match &self.normals {
+            Some([n1, n2, n3]) => {
+                let (w1, w2, w3) = self.barycentric(p);
+                (n1 * w1 + n2 * w2 + n3 * w3).normalize()
+            }
+            None => self.calculate_normal().normalize(),
+        }
+    }
+}
+
+/** Surface point found by tracing a ray */
+pub struct Hit<'a> {
+    pub element: &'a Element,
+    pub distance: f64,
+    pub point: Point3,
+    /** Shading normal, flipped together with the geometric normal */
+    pub normal: Vector3,
+    /** Face normal, turned towards the side the ray arrived from */
+    pub geometric_normal: Vector3,
+    /** Whether the ray arrived from the side the face normal points to, i.e. enters a closed surface */
+    pub front_face: bool,
+    /** Time of the ray, rays leaving the surface keep it */
+    pub time: f64,
+}
+
+impl Element {
+    pub fn material(&self) -> &Material {
+        match self {
+            Element::Sphere(s) => &s.material,
+            Element::Plane(p) => &p.material,
+            Element::Triangle(t) => &t.material,
+            Element::BezierPatch(b) => &b.material,
+            Element::Disk(d) => &d.material,
+            Element::Animated(a) => a.element.material(),
+        }
+    }
+
+    /** Distance along the ray, with the (u, v) of the hit for patches that solve for it while intersecting */
+    pub fn intersect_uv(&self, ray: &Ray) -> Option<(f64, Option<(f64, f64)>)> {
+        match self {
+            Element::BezierPatch(b) => b.intersect_uv(ray).map(|(distance, u, v)| (distance, Some((u, v)))),
+            Element::Animated(a) => {
+                let (local, _, stretch) = a.local_ray(ray);
+                a.element.intersect_uv(&local).map(|(distance, uv)| (distance / stretch, uv))
+            }
+            _ => self.intersect(ray).map(|distance| (distance, None)),
+        }
+    }
+
+    // geometric and shading normal at a point of the surface the ray hits, not yet facing the ray
+    fn normals_at(&self, ray: &Ray, point: &Point3, uv: Option<(f64, f64)>) -> (Vector3, Vector3) {
+        match self {
+            Element::Triangle(t) => {
+                // the face normal is put on the side of the vertex normals, the winding of a mesh may disagree with them
+                let face = t.calculate_normal().normalize();
+                let face = if face.dot(&t.normal_at(point)) < 0.0 { &face * -1.0 } else { face };
+                (face, shading_normal(t, point))
+            }
+            Element::BezierPatch(b) => {
+                let (u, v) = uv.expect("patch hits carry their (u, v)");
+                let normal = b.normal(u, v);
+                (normal.clone(), normal)
+            }
+            Element::Sphere(s) => {
+                let normal = (point - &s.center).normalize();
+                (normal.clone(), normal)
+            }
+            Element::Plane(p) => (p.normal.normalize(), p.normal.normalize()),
+            Element::Disk(d) => (d.normal.normalize(), d.normal.normalize()),
+            Element::Animated(a) => {
+                let (local, transform, _) = a.local_ray(ray);
+                let (geometric, shading) = a.element.normals_at(&local, &transform.inverse_point(point), uv);
+                (transform.apply_normal(&geometric), transform.apply_normal(&shading))
+            }
+        }
+    }
 }
 
 impl Scene {
+    /** Nearest surface hit by the ray, with its hit point and normal */
+    pub fn trace_hit(&self, ray: &Ray) -> Option<Hit<'_>> {
+        let (element, distance, uv) = self.nearest_hit(ray)?;
+        let point: Point3 = &ray.origin + &(&ray.direction * distance);
+        let (geometric_normal, normal) = element.normals_at(ray, &point, uv);
+        // surfaces are two-sided, the side is decided by the face, interpolated and mapped normals may lean across it
+        let front_face = geometric_normal.dot(&ray.direction) <= 0.0;
+        let (geometric_normal, normal) = if front_face {
+            (geometric_normal, normal)
+        } else {
+            (&geometric_normal * -1.0, &normal * -1.0)
+        };
+        Some(Hit {
+            element,
+            distance,
+            point,
+            normal,
+            geometric_normal,
+            front_face,
+            time: ray.time,
+        })
+    }
+
+    /** Adds an area light together with the geometry making it visible */
+    pub fn add_area_light(&mut self, light: AreaLight) {
+        self.elements.extend(light.elements());
+        self.lights.push(Light::Area(light));
+    }
+
+    /** The area light the material belongs to, with its index in lights */
+    pub fn area_light(&self, material: &Material) -> Option<(usize, &AreaLight)> {
+        self.lights.iter().enumerate().find_map(|(i, light)| match light {
+            Light::Area(l) if std::ptr::eq(l.material.as_ref(), material) => Some((i, l)),
+            _ => None,
+        })
+    }
+
+    /** Radiance of the environment lights seen by a ray leaving the scene, black without any */
+    pub fn background(&self, direction: &Vector3) -> Color {
+        let mut res = Color::from_one(0.0);
+        for light in &self.lights {
+            if let Light::Environment(l) = light {
+                res += &l.radiance(direction);
+            }
+        }
+        res
+    }
+
+    /** Whether anything blocks the ray before it travelled the distance */
+    pub fn occluded(&self, ray: &Ray, distance: f64) -> bool {
+        // leave some room so the surface a shadow ray is aimed at doesn't block it
+        let max_distance = distance * (1.0 - 1e-6) - 1e-6;
+        self.elements.iter().any(|e| matches!(e.intersect(ray), Some(d) if d < max_distance))
+    }
+
     pub fn trace(&self, ray: &Ray) -> Option<&Element> {
-        let mut nearest_element: Option<&Element> = None;
+        self.trace_with_distance(ray).map(|(element, _)| element)
+    }
+
+    /** Nearest element hit by the ray together with the distance along the ray */
+    pub fn trace_with_distance(&self, ray: &Ray) -> Option<(&Element, f64)> {
+        self.nearest_hit(ray).map(|(element, distance, _)| (element, distance))
+    }
+
+    /** Nearest element hit by the ray with the distance along it, and the (u, v) of the hit for patches */
+    pub fn nearest_hit(&self, ray: &Ray) -> Option<(&Element, f64, Option<(f64, f64)>)> {
+        let mut nearest_element: Option<(&Element, Option<(f64, f64)>)> = None;
         let mut dist_to_nearest_element: f64 = 10E6;
         for e in &self.elements {
-            let intersect = e.intersect(&ray);
+            let intersect = e.intersect_uv(ray);
             // find nearest element
             match intersect {
-                Some(d) => {
+                Some((d, uv)) => {
                     if d < dist_to_nearest_element {
-                        nearest_element = Some(e);
+                        nearest_element = Some((e, uv));
                         dist_to_nearest_element = d;
                     }
                 }
                 None => {}
             }
         }
-        nearest_element
+        nearest_element.map(|(element, uv)| (element, dist_to_nearest_element, uv))
     }
 }
-
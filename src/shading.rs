@@ -1,7 +1,12 @@
+use crate::bsdf::Bsdf;
 use crate::intersection::Ray;
 use crate::point::Point3;
+use crate::scene::{Color, Light, Scene, Triangle};
+use crate::sampler::Sampler;
+use crate::texture::Texture;
 use crate::vector::Vector3;
 use std::f64;
+use std::f64::consts::PI;
 
 pub fn facing_ratio(ray: &Ray, normal: &Vector3) -> f64 {
     let ratio: f64 = ((normal.normalize()).dot(&ray.direction.normalize())).abs();
@@ -9,9 +14,328 @@ pub fn facing_ratio(ray: &Ray, normal: &Vector3) -> f64 {
     res
 }
 
+/** Light arriving at a shaded point */
+pub struct LightSample {
+    /** Unit direction from the point towards the light */
+    pub direction: Vector3,
+    /** Light color scaled by its intensity at the point, 255 is full white */
+    pub color: Color,
+}
+
+/** Geometry of a shaded point, all directions are unit vectors pointing away from the point */
+pub struct ShadingContext<'a> {
+    pub normal: Vector3,
+    pub view_direction: Vector3,
+    pub albedo: &'a Color,
+    /** Whether the view direction lies on the outer side of the surface */
+    pub front_face: bool,
+    /** Wavelength in nanometers carried by a spectral path, None when rendering in rgb */
+    pub wavelength: Option<f64>,
+}
+
+/** Turns the light arriving at a point into the color seen by the camera */
+pub trait ShadingModel: Send + Sync {
+    fn shade(&self, context: &ShadingContext, lights: &[LightSample]) -> Color;
+
+    /** Scattering function used by the path tracer, None for models without a physical interpretation */
+    fn bsdf(&self) -> Option<&dyn Bsdf> {
+        None
+    }
+
+    /** Whether the surface only bounds a medium, shadow rays and paths pass straight through it */
+    fn is_interface(&self) -> bool {
+        false
+    }
+
+    /** Fraction of the light a shadow ray carries through the surface at the cosine to the normal, None for surfaces blocking it */
+    fn shadow_transmission(&self, _cos_theta: f64, _front_face: bool) -> Option<f64> {
+        if self.is_interface() {
+            Some(1.0)
+        } else {
+            None
+        }
+    }
+}
+
+fn black() -> Color {
+    Color {
+        red: 0.0,
+        green: 0.0,
+        blue: 0.0,
+    }
+}
+
+// adds albedo * light * weight, colors are in [0, 255]
+fn add_reflected(res: &mut Color, albedo: &Color, light: &Color, weight: f64) {
+    *res += &(&(albedo * light) * (weight / 255.0));
+}
+
+const WHITE: Color = Color {
+    red: 255.0,
+    green: 255.0,
+    blue: 255.0,
+};
+
+/** Ideal diffuse surface */
+pub struct Lambert;
+
+impl ShadingModel for Lambert {
+    fn shade(&self, context: &ShadingContext, lights: &[LightSample]) -> Color {
+        let mut res = black();
+        for light in lights {
+            let cos_theta = context.normal.dot(&light.direction).max(0.0);
+            add_reflected(&mut res, context.albedo, &light.color, cos_theta);
+        }
+        res
+    }
+
+    fn bsdf(&self) -> Option<&dyn Bsdf> {
+        Some(self)
+    }
+}
+
+/** Diffuse term plus a highlight around the mirror direction of the light */
+pub struct Phong {
+    pub diffuse: f64,
+    pub specular: f64,
+    pub shininess: f64,
+}
+
+impl ShadingModel for Phong {
+    fn shade(&self, context: &ShadingContext, lights: &[LightSample]) -> Color {
+        let mut res = black();
+        for light in lights {
+            let cos_theta = context.normal.dot(&light.direction);
+            if cos_theta <= 0.0 {
+                continue;
+            }
+            let reflected = &context.normal * (2.0 * cos_theta) - light.direction.clone();
+            let highlight = reflected.dot(&context.view_direction).max(0.0).powf(self.shininess);
+            add_reflected(&mut res, context.albedo, &light.color, self.diffuse * cos_theta);
+            add_reflected(&mut res, &WHITE, &light.color, self.specular * highlight);
+        }
+        res
+    }
+}
+
+/** Phong with the highlight measured by the half vector between light and viewer */
+pub struct BlinnPhong {
+    pub diffuse: f64,
+    pub specular: f64,
+    pub shininess: f64,
+}
+
+impl ShadingModel for BlinnPhong {
+    fn shade(&self, context: &ShadingContext, lights: &[LightSample]) -> Color {
+        let mut res = black();
+        for light in lights {
+            let cos_theta = context.normal.dot(&light.direction);
+            if cos_theta <= 0.0 {
+                continue;
+            }
+            let half = (light.direction.clone() + context.view_direction.clone()).normalize();
+            let highlight = context.normal.dot(&half).max(0.0).powf(self.shininess);
+            add_reflected(&mut res, context.albedo, &light.color, self.diffuse * cos_theta);
+            add_reflected(&mut res, &WHITE, &light.color, self.specular * highlight);
+        }
+        res
+    }
+}
+
+/** Rough diffuse surface, roughness is the standard deviation of the facet angle in radians */
+pub struct OrenNayar {
+    pub roughness: f64,
+}
+
+impl ShadingModel for OrenNayar {
+    fn shade(&self, context: &ShadingContext, lights: &[LightSample]) -> Color {
+        let sigma2 = self.roughness * self.roughness;
+        let a = 1.0 - 0.5 * sigma2 / (sigma2 + 0.33);
+        let b = 0.45 * sigma2 / (sigma2 + 0.09);
+        let n = &context.normal;
+        let cos_theta_r = n.dot(&context.view_direction).clamp(-1.0, 1.0);
+        let mut res = black();
+        for light in lights {
+            let cos_theta_i = n.dot(&light.direction).clamp(-1.0, 1.0);
+            if cos_theta_i <= 0.0 {
+                continue;
+            }
+            // cosine of the azimuth difference, from the directions projected onto the surface
+            let projected_i = light.direction.clone() - n * cos_theta_i;
+            let projected_r = context.view_direction.clone() - n * cos_theta_r;
+            let lengths = projected_i.length() * projected_r.length();
+            let cos_phi = if lengths > 1e-12 { (projected_i.dot(&projected_r) / lengths).max(0.0) } else { 0.0 };
+            let (theta_i, theta_r) = (cos_theta_i.acos(), cos_theta_r.acos());
+            let (alpha, beta) = (theta_i.max(theta_r), theta_i.min(theta_r));
+            let weight = cos_theta_i * (a + b * cos_phi * alpha.sin() * beta.tan());
+            add_reflected(&mut res, context.albedo, &light.color, weight);
+        }
+        res
+    }
+}
+
+/** Albedo as is, ignoring all lights */
+pub struct Unlit;
+
+impl ShadingModel for Unlit {
+    fn shade(&self, context: &ShadingContext, _lights: &[LightSample]) -> Color {
+        context.albedo.clone()
+    }
+}
+
+/** Shading normal mapped from [-1, 1] to colors, for inspecting geometry */
+pub struct NormalDebug;
+
+impl ShadingModel for NormalDebug {
+    fn shade(&self, context: &ShadingContext, _lights: &[LightSample]) -> Color {
+        Color {
+            red: (context.normal.x + 1.0) * 0.5 * 255.0,
+            green: (context.normal.y + 1.0) * 0.5 * 255.0,
+            blue: (context.normal.z + 1.0) * 0.5 * 255.0,
+        }
+    }
+}
+
+/** Lights reaching a point, without lights in the scene the camera carries a headlight */
+pub fn visible_lights(scene: &Scene, point: &Point3, normal: &Vector3, view_direction: &Vector3, time: f64, sampler: &mut dyn Sampler) -> Vec<LightSample> {
+    if scene.lights.is_empty() {
+        return vec![LightSample {
+            direction: view_direction.clone(),
+            color: WHITE,
+        }];
+    }
+    // start the shadow rays slightly above the surface to avoid hitting it again
+    let origin = point + &(normal * 1e-4);
+    let mut res: Vec<LightSample> = Vec::new();
+    let mut add_unoccluded = |direction: Vector3, distance: f64, color: Color| {
+        let shadow_ray = Ray {
+            origin: origin.clone(),
+            direction: direction.clone(),
+            time,
+        };
+        if !scene.occluded(&shadow_ray, distance) {
+            res.push(LightSample { direction, color });
+        }
+    };
+    for light in &scene.lights {
+        match light {
+            Light::Area(l) => {
+                let emission = l.emission();
+                let samples = l.samples.max(1);
+                for _ in 0..samples {
+                    let (u1, u2) = sampler.get_2d();
+                    if let Some(sample) = l.sample(&origin, u1, u2) {
+                        // radiance over the solid angle density, the light colors carry a factor pi
+                        let scale = 1.0 / (PI * sample.pdf * samples as f64);
+                        add_unoccluded(sample.direction, sample.distance, &emission * scale);
+                    }
+                }
+            }
+            Light::Environment(l) => {
+                let samples = l.samples.max(1);
+                for _ in 0..samples {
+                    let (u1, u2) = sampler.get_2d();
+                    if let Some(sample) = l.sample(u1, u2) {
+                        let scale = 1.0 / (PI * sample.pdf * samples as f64);
+                        add_unoccluded(sample.direction, f64::INFINITY, &sample.radiance * scale);
+                    }
+                }
+            }
+            _ => {
+                if let Some((direction, distance, color)) = delta_light(light, &origin) {
+                    add_unoccluded(direction, distance, color);
+                }
+            }
+        }
+    }
+    res
+}
+
+/** Direction, distance and color of a point or directional light seen from a point, None for area and environment lights */
+pub fn delta_light(light: &Light, point: &Point3) -> Option<(Vector3, f64, Color)> {
+    match light {
+        Light::Point(l) => {
+            let to_light = &l.position - point;
+            let distance = to_light.length();
+            let intensity = l.intensity / (distance * distance);
+            Some((to_light.normalize(), distance, &l.color * intensity))
+        }
+        Light::Directional(l) => Some((&l.direction.normalize() * -1.0, f64::INFINITY, &l.color * l.intensity)),
+        Light::Area(_) | Light::Environment(_) => None,
+    }
+}
+
+/** Bilinear texture lookup, the texture repeats outside [0, 1] */
+pub fn sample_texture(texture: &Texture, u: f64, v: f64) -> Color {
+    // texel centers are at half-integer coordinates, v = 0 is the bottom row
+    let x = (u - u.floor()) * texture.width as f64 - 0.5;
+    let y = (1.0 - (v - v.floor())) * texture.height as f64 - 0.5;
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let wrap = |i: f64, n: u32| (i as i64).rem_euclid(n as i64) as u32;
+    let (x0, x1) = (wrap(x0, texture.width), wrap(x0 + 1.0, texture.width));
+    let (y0, y1) = (wrap(y0, texture.height), wrap(y0 + 1.0, texture.height));
+
+    let corners = [
+        (texture.texel(x0, y0), (1.0 - fx) * (1.0 - fy)),
+        (texture.texel(x1, y0), fx * (1.0 - fy)),
+        (texture.texel(x0, y1), (1.0 - fx) * fy),
+        (texture.texel(x1, y1), fx * fy),
+    ];
+    let mut res = Color::from_one(0.0);
+    for (texel, weight) in &corners {
+        res += &(*texel * *weight);
+    }
+    res
+}
+
+/** Grayscale value of a texture in [0, 1], used for height textures */
+pub fn sample_height(texture: &Texture, u: f64, v: f64) -> f64 {
+    let color = sample_texture(texture, u, v);
+    (color.red + color.green + color.blue) / (3.0 * 255.0)
+}
+
+/** Normal used for shading a hit point, perturbed by the normal and bump maps of the material */
+pub fn shading_normal(triangle: &Triangle, p: &Point3) -> Vector3 {
+    let normal = triangle.normal_at(p);
+    let material = &triangle.material;
+    let ((s, t), (tangent, bitangent)) = match (triangle.st_at(p), &triangle.tangent_frame) {
+        (Some(st), Some(frame)) => (st, frame),
+        _ => return normal,
+    };
+    // gram-schmidt, the interpolated normal is not perpendicular to the face tangents
+    let tangent = (tangent.clone() - &normal * normal.dot(tangent)).normalize();
+    let bitangent_sign = if normal.cross(&tangent).dot(bitangent) < 0.0 { -1.0 } else { 1.0 };
+    let bitangent = &normal.cross(&tangent) * bitangent_sign;
+
+    let mut res = normal.clone();
+    if let Some(normal_map) = &material.normal_map {
+        let c = sample_texture(normal_map, s, t);
+        let (x, y, z) = (
+            c.red / 255.0 * 2.0 - 1.0,
+            c.green / 255.0 * 2.0 - 1.0,
+            c.blue / 255.0 * 2.0 - 1.0,
+        );
+        res = (&tangent * x + &bitangent * y + &res * z).normalize();
+    }
+    if let Some(bump_map) = &material.bump_map {
+        // central differences over one texel
+        let ds = 1.0 / bump_map.texture.width as f64;
+        let dt = 1.0 / bump_map.texture.height as f64;
+        let dh_ds = (sample_height(&bump_map.texture, s + ds, t) - sample_height(&bump_map.texture, s - ds, t)) / (2.0 * ds);
+        let dh_dt = (sample_height(&bump_map.texture, s, t + dt) - sample_height(&bump_map.texture, s, t - dt)) / (2.0 * dt);
+        res = (res - &(&tangent * dh_ds + &bitangent * dh_dt) * bump_map.strength).normalize();
+    }
+    res
+}
+
 #[cfg(test)]
 mod test_shading {
     use super::*;
+    use crate::sampler::IndependentSampler;
+    use crate::material::{BumpMap, Material};
+    use crate::scene::{Element, PointLight};
+    use std::sync::Arc;
 
     #[test]
     fn dummy() {
@@ -26,6 +350,7 @@ mod test_shading {
                 y: 1.0,
                 z: -5.0,
             },            
+            time: 0.0,
         };
         let normal = Vector3 {
             x: 0.8,
@@ -34,4 +359,182 @@ mod test_shading {
         };
         let x = facing_ratio(&ray, &normal);
     }
+
+    fn textured_triangle(material: Material) -> Triangle {
+        let mut triangle = Triangle {
+            point1: Point3 { x: 0.0, y: 0.0, z: -5.0 },
+            point2: Point3 { x: 1.0, y: 0.0, z: -5.0 },
+            point3: Point3 { x: 0.0, y: 1.0, z: -5.0 },
+            normals: None,
+            st: Some([(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)]),
+            tangent_frame: None,
+            material: Arc::new(material),
+        };
+        triangle.tangent_frame = triangle.calculate_tangent_frame(&triangle.st.unwrap());
+        triangle
+    }
+
+    #[test]
+    fn flat_normal_map_keeps_normal() {
+        let material = Material {
+            normal_map: Some(Texture::from_fn(1, 1, |_, _| Color {
+                red: 127.5,
+                green: 127.5,
+                blue: 255.0,
+            })),
+            ..Default::default()
+        };
+        let normal = shading_normal(&textured_triangle(material), &Point3 { x: 0.2, y: 0.2, z: -5.0 });
+        assert!((normal.z - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn bump_map_tilts_normal() {
+        // height increases along s, the normal leans towards -s
+        let material = Material {
+            bump_map: Some(BumpMap {
+                texture: Texture::from_fn(16, 16, |u, _| Color {
+                    red: u * 255.0,
+                    green: u * 255.0,
+                    blue: u * 255.0,
+                }),
+                strength: 0.5,
+            }),
+            ..Default::default()
+        };
+        let normal = shading_normal(&textured_triangle(material), &Point3 { x: 0.3, y: 0.3, z: -5.0 });
+        assert!(normal.x < -0.1);
+        assert!(normal.y.abs() < 1e-9);
+    }
+
+    #[test]
+    fn face_decides_the_side_of_a_mapped_normal() {
+        // the normal map leans the normal far towards +x, a grazing ray from -x still hits the front
+        let material = Material {
+            normal_map: Some(Texture::from_fn(1, 1, |_, _| Color {
+                red: 242.0,
+                green: 127.5,
+                blue: 183.0,
+            })),
+            ..Default::default()
+        };
+        let scene = Scene {
+            width: 1,
+            height: 1,
+            fov: 90.0,
+            elements: vec![Element::Triangle(textured_triangle(material))],
+            lights: Vec::new(),
+            medium: None,
+        };
+        let ray = Ray {
+            origin: Point3 { x: -9.8, y: 0.2, z: -4.9 },
+            direction: Vector3 { x: 10.0, y: 0.0, z: -0.1 }.normalize(),
+            time: 0.0,
+        };
+        let hit = scene.trace_hit(&ray).unwrap();
+        assert!(hit.front_face);
+        assert!((hit.geometric_normal.z - 1.0).abs() < 1e-9);
+        assert!(hit.normal.x > 0.5);
+    }
+
+    fn context(albedo: &Color) -> ShadingContext<'_> {
+        ShadingContext {
+            normal: Vector3 { x: 0.0, y: 0.0, z: 1.0 },
+            view_direction: Vector3 { x: 0.0, y: 0.6, z: 0.8 },
+            albedo,
+            front_face: true,
+            wavelength: None,
+        }
+    }
+
+    fn light_from(direction: Vector3) -> Vec<LightSample> {
+        vec![LightSample {
+            direction: direction.normalize(),
+            color: WHITE,
+        }]
+    }
+
+    #[test]
+    fn lambert_follows_cosine() {
+        let albedo = Color { red: 200.0, green: 100.0, blue: 0.0 };
+        let res = Lambert.shade(&context(&albedo), &light_from(Vector3 { x: 0.6, y: 0.0, z: 0.8 }));
+        assert!((res.red - 160.0).abs() < 1e-9);
+        assert!((res.green - 80.0).abs() < 1e-9);
+        let below = Lambert.shade(&context(&albedo), &light_from(Vector3 { x: 0.0, y: 0.0, z: -1.0 }));
+        assert_eq!(below.red, 0.0);
+    }
+
+    #[test]
+    fn highlights_peak_at_mirror_direction() {
+        let albedo = Color { red: 0.0, green: 0.0, blue: 0.0 };
+        let mirror = light_from(Vector3 { x: 0.0, y: -0.6, z: 0.8 });
+        let off = light_from(Vector3 { x: 0.6, y: 0.0, z: 0.8 });
+        let phong = Phong { diffuse: 1.0, specular: 1.0, shininess: 20.0 };
+        let blinn_phong = BlinnPhong { diffuse: 1.0, specular: 1.0, shininess: 20.0 };
+        assert!((phong.shade(&context(&albedo), &mirror).red - 255.0).abs() < 1e-9);
+        assert!((blinn_phong.shade(&context(&albedo), &mirror).red - 255.0).abs() < 1e-9);
+        assert!(phong.shade(&context(&albedo), &off).red < 255.0);
+        assert!(blinn_phong.shade(&context(&albedo), &off).red < 255.0);
+    }
+
+    #[test]
+    fn smooth_oren_nayar_is_lambert() {
+        let albedo = Color { red: 200.0, green: 100.0, blue: 50.0 };
+        let lights = light_from(Vector3 { x: 0.3, y: -0.2, z: 0.9 });
+        let smooth = OrenNayar { roughness: 0.0 }.shade(&context(&albedo), &lights);
+        let lambert = Lambert.shade(&context(&albedo), &lights);
+        assert!((smooth.red - lambert.red).abs() < 1e-9);
+        let rough = OrenNayar { roughness: 0.5 }.shade(&context(&albedo), &lights);
+        assert!(rough.red < lambert.red);
+    }
+
+    #[test]
+    fn debug_models_ignore_lights() {
+        let albedo = Color { red: 10.0, green: 20.0, blue: 30.0 };
+        assert_eq!(Unlit.shade(&context(&albedo), &[]).green, 20.0);
+        let normal_color = NormalDebug.shade(&context(&albedo), &[]);
+        assert_eq!(normal_color.red, 127.5);
+        assert_eq!(normal_color.blue, 255.0);
+    }
+
+    #[test]
+    fn occluded_lights_are_skipped() {
+        let blocker = textured_triangle(Material::default());
+        let scene = Scene {
+            width: 1,
+            height: 1,
+            fov: 90.0,
+            elements: vec![Element::Triangle(blocker)],
+            lights: vec![Light::Point(PointLight {
+                position: Point3 { x: 0.2, y: 0.2, z: 0.0 },
+                color: WHITE,
+                intensity: 100.0,
+            })],
+            medium: None,
+        };
+        let up = Vector3 { x: 0.0, y: 0.0, z: 1.0 };
+        let below = Point3 { x: 0.2, y: 0.2, z: -10.0 };
+        let beside = Point3 { x: 5.0, y: 5.0, z: -10.0 };
+        let mut sampler = IndependentSampler::new(0);
+        assert!(visible_lights(&scene, &below, &up, &up, 0.0, &mut sampler).is_empty());
+        let lights = visible_lights(&scene, &beside, &up, &up, 0.0, &mut sampler);
+        assert_eq!(lights.len(), 1);
+        // inverse square falloff
+        let distance2 = (&Point3 { x: 0.2, y: 0.2, z: 0.0 } - &beside).norm();
+        assert!((lights[0].color.red - 255.0 * 100.0 / distance2).abs() < 1e-2);
+    }
+
+    #[test]
+    fn bilinear_texture_sampling() {
+        let texture = Texture::from_fn(2, 1, |u, _| Color {
+            red: if u < 0.5 { 0.0 } else { 255.0 },
+            green: 0.0,
+            blue: 0.0,
+        });
+        assert_eq!(sample_texture(&texture, 0.25, 0.5).red, 0.0);
+        assert_eq!(sample_texture(&texture, 0.5, 0.5).red, 127.5);
+        // repeats, halfway between the last and the first texel
+        assert_eq!(sample_texture(&texture, 1.0, 0.5).red, 127.5);
+        assert_eq!(sample_height(&texture, 0.75, 0.5), 1.0 / 3.0);
+    }
 }
\ No newline at end of file
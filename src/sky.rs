@@ -0,0 +1,251 @@
+use crate::environment::EnvironmentLight;
+use crate::scene::{Color, DirectionalLight, Light, Scene};
+use crate::vector::Vector3;
+use std::f64::consts::PI;
+
+/** Analytic daylight after Preetham, Shirley and Smits, with the sun as a separate directional light */
+pub struct Sky {
+    /** Direction towards the sun, +y is up */
+    pub sun_direction: Vector3,
+    /** Haziness of the atmosphere, from 2 (clear) to 10 (hazy) */
+    pub turbidity: f64,
+    /** Color of the ground below the horizon, lit by the sun and the sky */
+    pub ground_albedo: Color,
+    /** Color value per kcd/m² of luminance */
+    pub intensity: f64,
+}
+
+impl Default for Sky {
+    fn default() -> Sky {
+        Sky {
+            sun_direction: Vector3 { x: 0.0, y: 1.0, z: -1.0 }.normalize(),
+            turbidity: 3.0,
+            ground_albedo: Color {
+                red: 80.0,
+                green: 80.0,
+                blue: 80.0,
+            },
+            intensity: 6.0,
+        }
+    }
+}
+
+// illuminance of the sun outside the atmosphere, in klux
+const SOLAR_ILLUMINANCE: f64 = 128.0;
+
+// Perez et al. luminance distribution
+fn perez(coefficients: &[f64; 5], cos_theta: f64, gamma: f64) -> f64 {
+    let [a, b, c, d, e] = coefficients;
+    (1.0 + a * (b / cos_theta.max(1e-3)).exp()) * (1.0 + c * (d * gamma).exp() + e * gamma.cos() * gamma.cos())
+}
+
+// zenith chromaticity polynomial in the turbidity and the sun's zenith angle
+fn zenith_chromaticity(m: &[[f64; 4]; 3], turbidity: f64, theta_sun: f64) -> f64 {
+    let t = [turbidity * turbidity, turbidity, 1.0];
+    let s = [theta_sun.powi(3), theta_sun * theta_sun, theta_sun, 1.0];
+    (0..3).map(|i| t[i] * (0..4).map(|j| m[i][j] * s[j]).sum::<f64>()).sum()
+}
+
+// CIE xyY to linear sRGB
+fn xyy_to_rgb(x: f64, y: f64, luminance: f64) -> [f64; 3] {
+    let cx = x / y * luminance;
+    let cz = (1.0 - x - y) / y * luminance;
+    [
+        (3.2406 * cx - 1.5372 * luminance - 0.4986 * cz).max(0.0),
+        (-0.9689 * cx + 1.8758 * luminance + 0.0415 * cz).max(0.0),
+        (0.0557 * cx - 0.2040 * luminance + 1.0570 * cz).max(0.0),
+    ]
+}
+
+impl Sky {
+    // zenith angle of the sun, kept above the horizon where the model holds
+    fn theta_sun(&self) -> f64 {
+        self.sun_direction.normalize().y.clamp(0.0, 1.0).acos().min(PI / 2.0 - 1e-3)
+    }
+
+    /** Luminance straight up, in kcd/m² */
+    pub fn zenith_luminance(&self) -> f64 {
+        let t = self.turbidity;
+        let theta_sun = self.theta_sun();
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_sun);
+        ((4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192).max(0.0)
+    }
+
+    /** Radiance of the sky dome, black below the horizon */
+    pub fn radiance(&self, direction: &Vector3) -> Color {
+        let d = direction.normalize();
+        if d.y < 0.0 {
+            return Color {
+                red: 0.0,
+                green: 0.0,
+                blue: 0.0,
+            };
+        }
+        let t = self.turbidity;
+        let theta_sun = self.theta_sun();
+        let gamma = d.dot(&self.sun_direction.normalize()).clamp(-1.0, 1.0).acos();
+        let luminance_coefficients = [0.1787 * t - 1.4630, -0.3554 * t + 0.4275, -0.0227 * t + 5.3251, 0.1206 * t - 2.5771, -0.0670 * t + 0.3703];
+        let x_coefficients = [-0.0193 * t - 0.2592, -0.0665 * t + 0.0008, -0.0004 * t + 0.2125, -0.0641 * t - 0.8989, -0.0033 * t + 0.0452];
+        let y_coefficients = [-0.0167 * t - 0.2608, -0.0950 * t + 0.0092, -0.0079 * t + 0.2102, -0.0441 * t - 1.6537, -0.0109 * t + 0.0529];
+        // each quantity relative to its value at the zenith
+        let relative = |coefficients: &[f64; 5]| perez(coefficients, d.y, gamma) / perez(coefficients, 1.0, theta_sun);
+        let x_zenith = zenith_chromaticity(
+            &[[0.00166, -0.00375, 0.00209, 0.0], [-0.02903, 0.06377, -0.03202, 0.00394], [0.11693, -0.21196, 0.06052, 0.25886]],
+            t,
+            theta_sun,
+        );
+        let y_zenith = zenith_chromaticity(
+            &[[0.00275, -0.00610, 0.00317, 0.0], [-0.04214, 0.08970, -0.04153, 0.00516], [0.15346, -0.26756, 0.06670, 0.26688]],
+            t,
+            theta_sun,
+        );
+        let [red, green, blue] = xyy_to_rgb(
+            x_zenith * relative(&x_coefficients),
+            y_zenith * relative(&y_coefficients),
+            self.zenith_luminance() * relative(&luminance_coefficients),
+        );
+        Color {
+            red: red * self.intensity,
+            green: green * self.intensity,
+            blue: blue * self.intensity,
+        }
+    }
+
+    /** Sunlight after passing the atmosphere, reddened by Rayleigh and aerosol scattering near the horizon */
+    pub fn sun(&self) -> DirectionalLight {
+        let theta_sun = self.theta_sun();
+        let above_horizon = self.sun_direction.normalize().y > 0.0;
+        // relative optical mass of the air the light travels through
+        let mass = 1.0 / (theta_sun.cos() + 0.15 * (93.885 - theta_sun.to_degrees()).powf(-1.253));
+        let beta = 0.04608 * self.turbidity - 0.04586;
+        let transmittance = |wavelength: f64| (-0.008735 * wavelength.powf(-4.08) * mass).exp() * (-beta * wavelength.powf(-1.3) * mass).exp();
+        // the light colors carry a factor pi
+        let scale = if above_horizon { SOLAR_ILLUMINANCE * self.intensity / PI } else { 0.0 };
+        DirectionalLight {
+            direction: &self.sun_direction.normalize() * -1.0,
+            color: Color {
+                red: transmittance(0.68) * 255.0,
+                green: transmittance(0.55) * 255.0,
+                blue: transmittance(0.44) * 255.0,
+            },
+            intensity: scale / 255.0,
+        }
+    }
+
+    /** The sky as an equirectangular environment, the ground reflecting the light of the sun and the sky */
+    pub fn environment(&self, width: usize, height: usize) -> EnvironmentLight {
+        let direction = |x: usize, y: usize| {
+            let phi = ((x as f64 + 0.5) / width as f64 - 0.5) * 2.0 * PI;
+            let theta = (y as f64 + 0.5) / height as f64 * PI;
+            Vector3 {
+                x: theta.sin() * phi.sin(),
+                y: theta.cos(),
+                z: -theta.sin() * phi.cos(),
+            }
+        };
+        let mut pixels: Vec<Color> = (0..width * height).map(|i| self.radiance(&direction(i % width, i / width))).collect();
+        // irradiance of a horizontal surface, summed over the pixels above the horizon
+        let mut irradiance = [0.0; 3];
+        for y in 0..height {
+            let theta = (y as f64 + 0.5) / height as f64 * PI;
+            if theta >= PI / 2.0 {
+                break;
+            }
+            let solid_angle = 2.0 * PI * PI * theta.sin() / (width * height) as f64;
+            for p in &pixels[y * width..(y + 1) * width] {
+                irradiance[0] += p.red * theta.cos() * solid_angle;
+                irradiance[1] += p.green * theta.cos() * solid_angle;
+                irradiance[2] += p.blue * theta.cos() * solid_angle;
+            }
+        }
+        let sun = self.sun();
+        let sun_cos = self.sun_direction.normalize().y.max(0.0);
+        let ground = Color {
+            red: self.ground_albedo.red / 255.0 * (irradiance[0] / PI + sun.color.red * sun.intensity * sun_cos),
+            green: self.ground_albedo.green / 255.0 * (irradiance[1] / PI + sun.color.green * sun.intensity * sun_cos),
+            blue: self.ground_albedo.blue / 255.0 * (irradiance[2] / PI + sun.color.blue * sun.intensity * sun_cos),
+        };
+        for (i, pixel) in pixels.iter_mut().enumerate() {
+            if direction(i % width, i / width).y < 0.0 {
+                *pixel = ground.clone();
+            }
+        }
+        EnvironmentLight::new(width, height, pixels)
+    }
+
+    /** Adds the sky as background and environment light together with the sun */
+    pub fn add_to(&self, scene: &mut Scene, width: usize, height: usize) {
+        scene.lights.push(Light::Environment(self.environment(width, height)));
+        scene.lights.push(Light::Directional(self.sun()));
+    }
+}
+
+#[cfg(test)]
+mod test_sky {
+    use super::*;
+
+    fn with_sun(elevation: f64) -> Sky {
+        let elevation = elevation.to_radians();
+        Sky {
+            sun_direction: Vector3 {
+                x: 0.0,
+                y: elevation.sin(),
+                z: -elevation.cos(),
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn zenith_matches_model() {
+        let sky = with_sun(40.0);
+        let up = Vector3 { x: 0.0, y: 1.0, z: 0.0 };
+        let expected = sky.zenith_luminance() * sky.intensity;
+        assert!((sky.radiance(&up).luminance() - expected).abs() < 0.02 * expected);
+        // brightest around the sun, a clear sky is blue
+        let towards_sun = sky.radiance(&Vector3 { x: 0.0, y: 0.5, z: -0.8 });
+        let away = sky.radiance(&Vector3 { x: 0.0, y: 0.5, z: 0.8 });
+        assert!(towards_sun.luminance() > away.luminance());
+        assert!(away.blue > away.red);
+        assert_eq!(sky.radiance(&Vector3 { x: 0.0, y: -1.0, z: 0.0 }).luminance(), 0.0);
+    }
+
+    #[test]
+    fn setting_sun_is_red() {
+        let noon = with_sun(80.0).sun();
+        let evening = with_sun(3.0).sun();
+        assert!(noon.intensity > 0.0);
+        assert!(evening.color.red / evening.color.blue > noon.color.red / noon.color.blue);
+        assert!(evening.color.green < noon.color.green);
+        assert_eq!(with_sun(-10.0).sun().intensity, 0.0);
+    }
+
+    #[test]
+    fn ground_reflects_the_daylight() {
+        let sky = with_sun(45.0);
+        let environment = sky.environment(32, 16);
+        let down = Vector3 { x: 0.3, y: -1.0, z: 0.0 };
+        let ground = environment.radiance(&down);
+        let brighter = Sky {
+            ground_albedo: Color {
+                red: 160.0,
+                green: 160.0,
+                blue: 160.0,
+            },
+            ..with_sun(45.0)
+        };
+        assert!(ground.red > 0.0);
+        assert!((brighter.environment(32, 16).radiance(&down).red - 2.0 * ground.red).abs() < 1e-9);
+        let mut scene = Scene {
+            width: 1,
+            height: 1,
+            fov: 90.0,
+            elements: Vec::new(),
+            lights: Vec::new(),
+            medium: None,
+        };
+        sky.add_to(&mut scene, 32, 16);
+        assert_eq!(scene.lights.len(), 2);
+        assert!(scene.background(&Vector3 { x: 0.0, y: 1.0, z: 0.0 }).blue > 0.0);
+    }
+}
@@ -0,0 +1,211 @@
+use crate::scene::Color;
+
+/** Range of the sampled wavelengths in nanometers */
+pub const WAVELENGTH_MIN: f64 = 360.0;
+pub const WAVELENGTH_MAX: f64 = 830.0;
+
+/** Wavelength of a path drawn from a density following the eye's sensitivity, with its density */
+pub fn sample_wavelength(u: f64) -> (f64, f64) {
+    let wavelength = 538.0 - 138.888889 * (0.85691062 - 1.82750197 * u).atanh();
+    (wavelength, wavelength_pdf(wavelength))
+}
+
+pub fn wavelength_pdf(wavelength: f64) -> f64 {
+    if !(WAVELENGTH_MIN..=WAVELENGTH_MAX).contains(&wavelength) {
+        return 0.0;
+    }
+    let cosh = (0.0072 * (wavelength - 538.0)).cosh();
+    0.003939804229326285 / (cosh * cosh)
+}
+
+// basis spectra of Smits' rgb upsampling, 10 bins from 380 to 720 nm
+const SMITS_WHITE: [f64; 10] = [1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000];
+const SMITS_CYAN: [f64; 10] = [0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000];
+const SMITS_MAGENTA: [f64; 10] = [1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959];
+const SMITS_YELLOW: [f64; 10] = [0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840];
+const SMITS_RED: [f64; 10] = [0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149];
+const SMITS_GREEN: [f64; 10] = [0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025];
+const SMITS_BLUE: [f64; 10] = [1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496];
+
+/** Value at the wavelength of a smooth spectrum with the color, after Smits; white gives a flat spectrum */
+pub fn rgb_to_spectrum(color: &Color, wavelength: f64) -> f64 {
+    // wavelengths outside the tabulated range use the nearest bin
+    let bin = (((wavelength - 380.0) / 34.0).floor().max(0.0) as usize).min(9);
+    let (r, g, b) = (color.red, color.green, color.blue);
+    // the smallest channel is white, the middle one a secondary and the rest a primary color
+    let (white, secondary, primary) = if r <= g && r <= b {
+        if g <= b {
+            (r, (g - r) * SMITS_CYAN[bin], (b - g) * SMITS_BLUE[bin])
+        } else {
+            (r, (b - r) * SMITS_CYAN[bin], (g - b) * SMITS_GREEN[bin])
+        }
+    } else if g <= r && g <= b {
+        if r <= b {
+            (g, (r - g) * SMITS_MAGENTA[bin], (b - r) * SMITS_BLUE[bin])
+        } else {
+            (g, (b - g) * SMITS_MAGENTA[bin], (r - b) * SMITS_RED[bin])
+        }
+    } else if r <= g {
+        (b, (r - b) * SMITS_YELLOW[bin], (g - r) * SMITS_GREEN[bin])
+    } else {
+        (b, (g - b) * SMITS_YELLOW[bin], (r - g) * SMITS_RED[bin])
+    };
+    white * SMITS_WHITE[bin] + secondary + primary
+}
+
+// gaussian with different widths left and right of its peak
+fn lobe(wavelength: f64, peak: f64, left: f64, right: f64) -> f64 {
+    let width = if wavelength < peak { left } else { right };
+    let t = (wavelength - peak) / width;
+    (-0.5 * t * t).exp()
+}
+
+// integral of a lobe over all wavelengths
+fn lobe_integral(left: f64, right: f64) -> f64 {
+    (std::f64::consts::PI / 2.0).sqrt() * (left + right)
+}
+
+// (height, peak, left width, right width) of the lobes of Wyman, Sloan and Shirley's fit of the CIE 1931 observer
+const X_LOBES: [(f64, f64, f64, f64); 3] = [(1.056, 599.8, 37.9, 31.0), (0.362, 442.0, 16.0, 26.7), (-0.065, 501.1, 20.4, 26.2)];
+const Y_LOBES: [(f64, f64, f64, f64); 2] = [(0.821, 568.8, 46.9, 40.5), (0.286, 530.9, 16.3, 31.1)];
+const Z_LOBES: [(f64, f64, f64, f64); 2] = [(1.217, 437.0, 11.8, 36.0), (0.681, 459.0, 26.0, 13.8)];
+
+fn matching_function(lobes: &[(f64, f64, f64, f64)], wavelength: f64) -> f64 {
+    lobes.iter().map(|&(height, peak, left, right)| height * lobe(wavelength, peak, left, right)).sum()
+}
+
+fn matching_function_integral(lobes: &[(f64, f64, f64, f64)]) -> f64 {
+    lobes.iter().map(|&(height, _, left, right)| height * lobe_integral(left, right)).sum()
+}
+
+/** CIE 1931 color matching functions x, y and z at the wavelength */
+pub fn cie_xyz(wavelength: f64) -> [f64; 3] {
+    [
+        matching_function(&X_LOBES, wavelength),
+        matching_function(&Y_LOBES, wavelength),
+        matching_function(&Z_LOBES, wavelength),
+    ]
+}
+
+/** Linear sRGB with the D65 white point from CIE XYZ */
+pub fn xyz_to_rgb(xyz: [f64; 3]) -> Color {
+    let [x, y, z] = xyz;
+    Color {
+        red: 3.2404542 * x - 1.5371385 * y - 0.4985314 * z,
+        green: -0.9692660 * x + 1.8760108 * y + 0.0415560 * z,
+        blue: 0.0556434 * x - 0.2040259 * y + 1.0572252 * z,
+    }
+}
+
+/** The color's spectrum at the wavelength of a spectral path in every channel, the color itself in rgb */
+pub fn at_wavelength(color: &Color, wavelength: Option<f64>) -> Color {
+    match wavelength {
+        Some(wavelength) => Color::from_one(rgb_to_spectrum(color, wavelength)),
+        None => color.clone(),
+    }
+}
+
+/** Color contributed by radiance sampled at one wavelength with the given density, white balanced so a flat spectrum gives equal channels */
+pub fn wavelength_to_rgb(wavelength: f64, radiance: f64, pdf: f64) -> Color {
+    if pdf <= 0.0 {
+        return Color::from_one(0.0);
+    }
+    let rgb = xyz_to_rgb(cie_xyz(wavelength));
+    let white = xyz_to_rgb([
+        matching_function_integral(&X_LOBES),
+        matching_function_integral(&Y_LOBES),
+        matching_function_integral(&Z_LOBES),
+    ]);
+    let scale = radiance / pdf;
+    Color {
+        red: rgb.red * scale / white.red,
+        green: rgb.green * scale / white.green,
+        blue: rgb.blue * scale / white.blue,
+    }
+}
+
+/** Index of refraction changing with the wavelength, wavelengths in the formulas are in micrometers */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Dispersion {
+    /** n = a + b / λ² */
+    Cauchy { a: f64, b: f64 },
+    /** n² = 1 + Σ b λ² / (λ² - c) */
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+impl Dispersion {
+    /** Schott N-BK7, the common crown glass of lenses */
+    pub fn bk7() -> Dispersion {
+        Dispersion::Sellmeier {
+            b: [1.03961212, 0.231792344, 1.01046945],
+            c: [0.00600069867, 0.0200179144, 103.560653],
+        }
+    }
+
+    /** Schott N-SF11, a dense flint glass spreading the colors about three times as far as crown glass */
+    pub fn dense_flint() -> Dispersion {
+        Dispersion::Sellmeier {
+            b: [1.73759695, 0.313747346, 1.89878101],
+            c: [0.013188707, 0.0623068142, 155.23629],
+        }
+    }
+
+    pub fn index_of_refraction(&self, wavelength: f64) -> f64 {
+        let l2 = (wavelength / 1000.0) * (wavelength / 1000.0);
+        match self {
+            Dispersion::Cauchy { a, b } => a + b / l2,
+            Dispersion::Sellmeier { b, c } => (1.0 + (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum::<f64>()).sqrt(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_spectral {
+    use super::*;
+    use crate::sampler::{IndependentSampler, Sampler};
+
+    // color of a spectrum estimated from sampled wavelengths
+    fn integrate<F: Fn(f64) -> f64>(spectrum: F) -> Color {
+        let mut sampler = IndependentSampler::new(3);
+        let mut sum = Color::from_one(0.0);
+        let n = 100000;
+        for _ in 0..n {
+            let (wavelength, pdf) = sample_wavelength(sampler.get_1d());
+            sum += &wavelength_to_rgb(wavelength, spectrum(wavelength), pdf);
+        }
+        &sum * (1.0 / n as f64)
+    }
+
+    #[test]
+    fn upsampled_colors_integrate_back_to_rgb() {
+        let white = integrate(|_| 1.0);
+        for c in [white.red, white.green, white.blue].iter() {
+            assert!((c - 1.0).abs() < 0.02, "{:?}", white);
+        }
+        let orange = Color {
+            red: 0.9,
+            green: 0.4,
+            blue: 0.1,
+        };
+        let back = integrate(|wavelength| rgb_to_spectrum(&orange, wavelength));
+        assert!(back.red > back.green && back.green > back.blue, "{:?}", back);
+        assert!((back.red - 0.9).abs() < 0.1 && (back.green - 0.4).abs() < 0.1 && back.blue.abs() < 0.1, "{:?}", back);
+        // the Y matching function peaks in the green
+        assert!(cie_xyz(555.0)[1] > 0.95 && cie_xyz(450.0)[1] < 0.1);
+        let (wavelength, pdf) = sample_wavelength(0.5);
+        assert!(wavelength > 500.0 && wavelength < 600.0 && pdf > 0.0);
+    }
+
+    #[test]
+    fn glass_bends_blue_more_than_red() {
+        let bk7 = Dispersion::bk7();
+        // catalog index at the helium d line
+        assert!((bk7.index_of_refraction(587.6) - 1.5168).abs() < 1e-4);
+        assert!(bk7.index_of_refraction(450.0) > bk7.index_of_refraction(650.0));
+        let cauchy = Dispersion::Cauchy { a: 1.5046, b: 0.0042 };
+        assert!((cauchy.index_of_refraction(587.6) - bk7.index_of_refraction(587.6)).abs() < 1e-3);
+        let flint = Dispersion::dense_flint();
+        let spread = |d: &Dispersion| d.index_of_refraction(486.1) - d.index_of_refraction(656.3);
+        assert!(spread(&flint) > 2.5 * spread(&bk7));
+    }
+}
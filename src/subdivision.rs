@@ -0,0 +1,547 @@
+use crate::load_geo_scene::GeoData;
+use crate::point::Point3;
+use crate::vector::Vector3;
+use std::collections::HashMap;
+use std::f64::consts::PI;
+
+pub enum SubdivisionScheme {
+    /** Loop for pure triangle meshes, Catmull-Clark otherwise */
+    Auto,
+    CatmullClark,
+    Loop,
+}
+
+pub struct SubdivisionSettings {
+    pub scheme: SubdivisionScheme,
+    pub levels: usize,
+    /** Sharp edges given by their two vertex indices */
+    pub creases: Vec<(usize, usize)>,
+    /** Edges whose faces meet at a larger angle (degrees) are treated as creases */
+    pub crease_angle: Option<f64>,
+    /** Keep the corners of open meshes (boundary vertices with a single face) in place */
+    pub fixed_boundary_corners: bool,
+}
+
+impl Default for SubdivisionSettings {
+    fn default() -> SubdivisionSettings {
+        SubdivisionSettings {
+            scheme: SubdivisionScheme::Auto,
+            levels: 1,
+            creases: Vec::new(),
+            crease_angle: None,
+            fixed_boundary_corners: true,
+        }
+    }
+}
+
+// polygon mesh used while subdividing, faces index into points
+struct Mesh {
+    points: Vec<Vector3>,
+    faces: Vec<Vec<usize>>,
+    /** Texture coordinates of every face corner, interpolated linearly (empty without texture coordinates) */
+    face_st: Vec<Vec<(f64, f64)>>,
+    creases: Vec<(usize, usize)>,
+}
+
+// vertices reached over sharp edges, over all edges and the faces around every vertex
+struct Neighbourhood {
+    sharp_neighbours: Vec<Vec<usize>>,
+    neighbours: Vec<Vec<usize>>,
+    vertex_faces: Vec<Vec<usize>>,
+}
+
+struct Edge {
+    faces: Vec<usize>,
+    sharp: bool,
+}
+
+fn edge_key(a: usize, b: usize) -> (usize, usize) {
+    if a < b {
+        (a, b)
+    } else {
+        (b, a)
+    }
+}
+
+fn average(points: &[&Vector3]) -> Vector3 {
+    let mut res = Vector3::zero();
+    for p in points {
+        res = res + (*p).clone();
+    }
+    &res * (1.0 / points.len() as f64)
+}
+
+fn midpoint(a: (f64, f64), b: (f64, f64)) -> (f64, f64) {
+    ((a.0 + b.0) * 0.5, (a.1 + b.1) * 0.5)
+}
+
+fn face_normal(points: &[Vector3], face: &[usize]) -> Vector3 {
+    // newell's method, the length is the area of the polygon times two
+    let mut normal = Vector3::zero();
+    for i in 0..face.len() {
+        let current = &points[face[i]];
+        let next = &points[face[(i + 1) % face.len()]];
+        normal = normal + current.cross(next);
+    }
+    normal
+}
+
+impl Mesh {
+    fn from_geo_data(geo_data: &GeoData, creases: &[(usize, usize)]) -> Mesh {
+        let has_st = geo_data.st_array.len() == geo_data.vertex_index_array.len();
+        let mut faces: Vec<Vec<usize>> = Vec::new();
+        let mut face_st: Vec<Vec<(f64, f64)>> = Vec::new();
+        let mut k = 0;
+        for i in 0..geo_data.num_face {
+            let n = geo_data.face_index_array[i];
+            faces.push(geo_data.vertex_index_array[k..k + n].to_vec());
+            if has_st {
+                face_st.push(geo_data.st_array[k..k + n].to_vec());
+            }
+            k += n;
+        }
+        Mesh {
+            points: geo_data.vertex_array.iter().map(|p| p.to_vector()).collect(),
+            faces,
+            face_st,
+            creases: creases.iter().map(|&(a, b)| edge_key(a, b)).collect(),
+        }
+    }
+
+    fn edges(&self) -> HashMap<(usize, usize), Edge> {
+        let mut edges: HashMap<(usize, usize), Edge> = HashMap::new();
+        for (f, face) in self.faces.iter().enumerate() {
+            for i in 0..face.len() {
+                let key = edge_key(face[i], face[(i + 1) % face.len()]);
+                edges
+                    .entry(key)
+                    .or_insert(Edge {
+                        faces: Vec::new(),
+                        sharp: false,
+                    })
+                    .faces
+                    .push(f);
+            }
+        }
+        for (key, edge) in edges.iter_mut() {
+            // boundary edges follow the crease rules
+            edge.sharp = edge.faces.len() != 2 || self.creases.contains(key);
+        }
+        edges
+    }
+
+    fn mark_crease_angle(&mut self, angle: f64) {
+        let cos_limit = angle.to_radians().cos();
+        let normals: Vec<Vector3> = self
+            .faces
+            .iter()
+            .map(|f| face_normal(&self.points, f).normalize())
+            .collect();
+        let edges = self.edges();
+        for (key, edge) in edges.iter() {
+            if edge.faces.len() == 2
+                && normals[edge.faces[0]].dot(&normals[edge.faces[1]]) < cos_limit
+                && !self.creases.contains(key)
+            {
+                self.creases.push(*key);
+            }
+        }
+    }
+
+    fn is_triangle_mesh(&self) -> bool {
+        self.faces.iter().all(|f| f.len() == 3)
+    }
+
+    // fan triangulation, so loop subdivision can be applied to any polygon mesh
+    fn triangulate(&mut self) {
+        let mut faces: Vec<Vec<usize>> = Vec::new();
+        let mut face_st: Vec<Vec<(f64, f64)>> = Vec::new();
+        for (f, face) in self.faces.iter().enumerate() {
+            for j in 0..face.len() - 2 {
+                faces.push(vec![face[0], face[j + 1], face[j + 2]]);
+                if let Some(st) = self.face_st.get(f) {
+                    face_st.push(vec![st[0], st[j + 1], st[j + 2]]);
+                }
+            }
+        }
+        self.faces = faces;
+        self.face_st = face_st;
+    }
+
+    fn vertex_neighbourhood(&self, edges: &HashMap<(usize, usize), Edge>) -> Neighbourhood {
+        let mut sharp_neighbours: Vec<Vec<usize>> = vec![Vec::new(); self.points.len()];
+        let mut neighbours: Vec<Vec<usize>> = vec![Vec::new(); self.points.len()];
+        let mut vertex_faces: Vec<Vec<usize>> = vec![Vec::new(); self.points.len()];
+        for (&(a, b), edge) in edges.iter() {
+            neighbours[a].push(b);
+            neighbours[b].push(a);
+            if edge.sharp {
+                sharp_neighbours[a].push(b);
+                sharp_neighbours[b].push(a);
+            }
+        }
+        for (f, face) in self.faces.iter().enumerate() {
+            for &v in face {
+                vertex_faces[v].push(f);
+            }
+        }
+        Neighbourhood {
+            sharp_neighbours,
+            neighbours,
+            vertex_faces,
+        }
+    }
+
+    // crease and corner rules shared by both schemes, None for smooth vertices
+    fn sharp_vertex_point(
+        &self,
+        v: usize,
+        sharp_neighbours: &[usize],
+        face_count: usize,
+        fixed_boundary_corners: bool,
+    ) -> Option<Vector3> {
+        let p = &self.points[v];
+        match sharp_neighbours.len() {
+            0 | 1 => None,
+            2 if !(fixed_boundary_corners && face_count == 1) => {
+                let a = &self.points[sharp_neighbours[0]];
+                let b = &self.points[sharp_neighbours[1]];
+                Some(&((p * 6.0) + a.clone() + b.clone()) * (1.0 / 8.0))
+            }
+            _ => Some(p.clone()),
+        }
+    }
+
+    fn catmull_clark(&self, fixed_boundary_corners: bool) -> Mesh {
+        let edges = self.edges();
+        let Neighbourhood {
+            sharp_neighbours,
+            neighbours,
+            vertex_faces,
+        } = self.vertex_neighbourhood(&edges);
+        let num_points = self.points.len();
+
+        let face_points: Vec<Vector3> = self
+            .faces
+            .iter()
+            .map(|f| average(&f.iter().map(|&v| &self.points[v]).collect::<Vec<&Vector3>>()))
+            .collect();
+
+        let mut points: Vec<Vector3> = Vec::new();
+        for v in 0..num_points {
+            let sharp = self.sharp_vertex_point(v, &sharp_neighbours[v], vertex_faces[v].len(), fixed_boundary_corners);
+            let point = match sharp {
+                Some(p) => p,
+                None if neighbours[v].is_empty() => self.points[v].clone(),
+                None => {
+                    // (F + 2R + (n - 3)P) / n
+                    let n = neighbours[v].len() as f64;
+                    let f = average(&vertex_faces[v].iter().map(|&f| &face_points[f]).collect::<Vec<&Vector3>>());
+                    let r = average(&neighbours[v].iter().map(|&u| &self.points[u]).collect::<Vec<&Vector3>>());
+                    let r = &(r + self.points[v].clone()) * 0.5;
+                    &(f + &r * 2.0 + &self.points[v] * (n - 3.0)) * (1.0 / n)
+                }
+            };
+            points.push(point);
+        }
+
+        let mut edge_index: HashMap<(usize, usize), usize> = HashMap::new();
+        let mut keys: Vec<&(usize, usize)> = edges.keys().collect();
+        keys.sort();
+        for key in keys {
+            let edge = &edges[key];
+            let (a, b) = (&self.points[key.0], &self.points[key.1]);
+            let point = if edge.sharp {
+                average(&[a, b])
+            } else {
+                average(&[a, b, &face_points[edge.faces[0]], &face_points[edge.faces[1]]])
+            };
+            edge_index.insert(*key, points.len());
+            points.push(point);
+        }
+
+        let face_offset = points.len();
+        points.extend(face_points);
+
+        let mut faces: Vec<Vec<usize>> = Vec::new();
+        let mut face_st: Vec<Vec<(f64, f64)>> = Vec::new();
+        for (f, face) in self.faces.iter().enumerate() {
+            let n = face.len();
+            for i in 0..n {
+                let previous = face[(i + n - 1) % n];
+                let next = face[(i + 1) % n];
+                faces.push(vec![
+                    face[i],
+                    edge_index[&edge_key(face[i], next)],
+                    face_offset + f,
+                    edge_index[&edge_key(previous, face[i])],
+                ]);
+                if let Some(st) = self.face_st.get(f) {
+                    let count = st.len() as f64;
+                    let center = st.iter().fold((0.0, 0.0), |acc, s| (acc.0 + s.0 / count, acc.1 + s.1 / count));
+                    face_st.push(vec![
+                        st[i],
+                        midpoint(st[i], st[(i + 1) % n]),
+                        center,
+                        midpoint(st[(i + n - 1) % n], st[i]),
+                    ]);
+                }
+            }
+        }
+        Mesh {
+            points,
+            faces,
+            face_st,
+            creases: self.split_creases(&edges, &edge_index),
+        }
+    }
+
+    fn loop_subdivision(&self, fixed_boundary_corners: bool) -> Mesh {
+        let edges = self.edges();
+        let Neighbourhood {
+            sharp_neighbours,
+            neighbours,
+            vertex_faces,
+        } = self.vertex_neighbourhood(&edges);
+
+        let mut points: Vec<Vector3> = Vec::new();
+        for v in 0..self.points.len() {
+            let sharp = self.sharp_vertex_point(v, &sharp_neighbours[v], vertex_faces[v].len(), fixed_boundary_corners);
+            let point = match sharp {
+                Some(p) => p,
+                None if neighbours[v].is_empty() => self.points[v].clone(),
+                None => {
+                    // loop's original weights
+                    let n = neighbours[v].len() as f64;
+                    let w = 3.0 / 8.0 + 0.25 * (2.0 * PI / n).cos();
+                    let beta = (5.0 / 8.0 - w * w) / n;
+                    let mut sum = Vector3::zero();
+                    for &u in &neighbours[v] {
+                        sum = sum + self.points[u].clone();
+                    }
+                    &self.points[v] * (1.0 - n * beta) + &sum * beta
+                }
+            };
+            points.push(point);
+        }
+
+        let mut edge_index: HashMap<(usize, usize), usize> = HashMap::new();
+        let mut keys: Vec<&(usize, usize)> = edges.keys().collect();
+        keys.sort();
+        for key in keys {
+            let edge = &edges[key];
+            let (a, b) = (&self.points[key.0], &self.points[key.1]);
+            let point = if edge.sharp {
+                average(&[a, b])
+            } else {
+                let opposite = |f: usize| {
+                    let face = &self.faces[f];
+                    face.iter().find(|&&v| v != key.0 && v != key.1).copied().unwrap()
+                };
+                let c = &self.points[opposite(edge.faces[0])];
+                let d = &self.points[opposite(edge.faces[1])];
+                &(a.clone() + b.clone()) * (3.0 / 8.0) + &(c.clone() + d.clone()) * (1.0 / 8.0)
+            };
+            edge_index.insert(*key, points.len());
+            points.push(point);
+        }
+
+        let mut faces: Vec<Vec<usize>> = Vec::new();
+        let mut face_st: Vec<Vec<(f64, f64)>> = Vec::new();
+        for (f, face) in self.faces.iter().enumerate() {
+            let (a, b, c) = (face[0], face[1], face[2]);
+            let ab = edge_index[&edge_key(a, b)];
+            let bc = edge_index[&edge_key(b, c)];
+            let ca = edge_index[&edge_key(c, a)];
+            faces.push(vec![a, ab, ca]);
+            faces.push(vec![b, bc, ab]);
+            faces.push(vec![c, ca, bc]);
+            faces.push(vec![ab, bc, ca]);
+            if let Some(st) = self.face_st.get(f) {
+                let (st_ab, st_bc, st_ca) = (midpoint(st[0], st[1]), midpoint(st[1], st[2]), midpoint(st[2], st[0]));
+                face_st.push(vec![st[0], st_ab, st_ca]);
+                face_st.push(vec![st[1], st_bc, st_ab]);
+                face_st.push(vec![st[2], st_ca, st_bc]);
+                face_st.push(vec![st_ab, st_bc, st_ca]);
+            }
+        }
+        Mesh {
+            points,
+            faces,
+            face_st,
+            creases: self.split_creases(&edges, &edge_index),
+        }
+    }
+
+    // every crease edge continues as two edges through its new edge point
+    fn split_creases(
+        &self,
+        edges: &HashMap<(usize, usize), Edge>,
+        edge_index: &HashMap<(usize, usize), usize>,
+    ) -> Vec<(usize, usize)> {
+        let mut creases: Vec<(usize, usize)> = Vec::new();
+        for &(a, b) in &self.creases {
+            if let (Some(edge), Some(&e)) = (edges.get(&(a, b)), edge_index.get(&(a, b))) {
+                if edge.faces.len() == 2 {
+                    creases.push(edge_key(a, e));
+                    creases.push(edge_key(e, b));
+                }
+            }
+        }
+        creases
+    }
+
+    // area weighted average of the normals of the faces around each vertex
+    fn vertex_normals(&self) -> Vec<Vector3> {
+        let mut normals: Vec<Vector3> = vec![Vector3::zero(); self.points.len()];
+        for face in &self.faces {
+            let normal = face_normal(&self.points, face);
+            for &v in face {
+                normals[v] = normals[v].clone() + normal.clone();
+            }
+        }
+        normals
+            .iter()
+            .map(|n| if n.norm() > 0.0 { n.normalize() } else { n.clone() })
+            .collect()
+    }
+
+    fn to_geo_data(&self) -> GeoData {
+        let normals = self.vertex_normals();
+        let mut face_index_array: Vec<usize> = Vec::new();
+        let mut vertex_index_array: Vec<usize> = Vec::new();
+        let mut normal_array: Vec<Point3> = Vec::new();
+        for face in &self.faces {
+            face_index_array.push(face.len());
+            for &v in face {
+                vertex_index_array.push(v);
+                normal_array.push(normals[v].to_point());
+            }
+        }
+        GeoData {
+            num_face: self.faces.len(),
+            face_index_array,
+            vertex_index_array,
+            vertex_array: self.points.iter().map(|p| p.to_point()).collect(),
+            normal_array,
+            st_array: self.face_st.concat(),
+        }
+    }
+}
+
+/** Smooth face-vertex normals of an unmodified mesh, e.g. after moving its vertices */
+pub fn smooth_normals(geo_data: &GeoData) -> Vec<Point3> {
+    Mesh::from_geo_data(geo_data, &[]).to_geo_data().normal_array
+}
+
+/** Subdivides the faces of the mesh, the result carries smooth per-vertex normals */
+pub fn subdivide(geo_data: &GeoData, settings: &SubdivisionSettings) -> GeoData {
+    let mut mesh = Mesh::from_geo_data(geo_data, &settings.creases);
+    if let Some(angle) = settings.crease_angle {
+        mesh.mark_crease_angle(angle);
+    }
+    let use_loop = match settings.scheme {
+        SubdivisionScheme::Auto => mesh.is_triangle_mesh(),
+        SubdivisionScheme::CatmullClark => false,
+        SubdivisionScheme::Loop => true,
+    };
+    if use_loop && !mesh.is_triangle_mesh() {
+        mesh.triangulate();
+    }
+    for _ in 0..settings.levels {
+        mesh = if use_loop {
+            mesh.loop_subdivision(settings.fixed_boundary_corners)
+        } else {
+            mesh.catmull_clark(settings.fixed_boundary_corners)
+        };
+    }
+    mesh.to_geo_data()
+}
+
+#[cfg(test)]
+mod test_subdivision {
+    use super::*;
+
+    fn geo_data(points: Vec<(f64, f64, f64)>, faces: Vec<Vec<usize>>) -> GeoData {
+        GeoData {
+            num_face: faces.len(),
+            face_index_array: faces.iter().map(|f| f.len()).collect(),
+            vertex_index_array: faces.concat(),
+            vertex_array: points.iter().map(|&(x, y, z)| Point3 { x, y, z }).collect(),
+            normal_array: Vec::new(),
+            st_array: Vec::new(),
+        }
+    }
+
+    fn cube() -> GeoData {
+        geo_data(
+            vec![
+                (-1.0, -1.0, -1.0),
+                (1.0, -1.0, -1.0),
+                (1.0, 1.0, -1.0),
+                (-1.0, 1.0, -1.0),
+                (-1.0, -1.0, 1.0),
+                (1.0, -1.0, 1.0),
+                (1.0, 1.0, 1.0),
+                (-1.0, 1.0, 1.0),
+            ],
+            vec![
+                vec![0, 3, 2, 1],
+                vec![4, 5, 6, 7],
+                vec![0, 1, 5, 4],
+                vec![2, 3, 7, 6],
+                vec![1, 2, 6, 5],
+                vec![0, 4, 7, 3],
+            ],
+        )
+    }
+
+    #[test]
+    fn catmull_clark_cube() {
+        let res = subdivide(&cube(), &SubdivisionSettings::default());
+        assert_eq!(res.num_face, 24);
+        assert_eq!(res.vertex_array.len(), 8 + 12 + 6);
+        assert_eq!(res.normal_array.len(), 24 * 4);
+        // the corners are pulled towards the limit surface: (F + 2R + (n - 3)P) / n = 5/9
+        assert!((res.vertex_array[6].x - 5.0 / 9.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn loop_tetrahedron() {
+        let tetrahedron = geo_data(
+            vec![(1.0, 1.0, 1.0), (1.0, -1.0, -1.0), (-1.0, 1.0, -1.0), (-1.0, -1.0, 1.0)],
+            vec![vec![0, 1, 2], vec![0, 3, 1], vec![0, 2, 3], vec![1, 3, 2]],
+        );
+        let settings = SubdivisionSettings {
+            levels: 2,
+            ..Default::default()
+        };
+        let res = subdivide(&tetrahedron, &settings);
+        assert_eq!(res.num_face, 64);
+        assert!(res.face_index_array.iter().all(|&n| n == 3));
+    }
+
+    #[test]
+    fn creases_stay_sharp() {
+        // open quad strip, the shared edge is a crease
+        let strip = geo_data(
+            vec![(0.0, 0.0, 0.0), (1.0, 0.0, 0.0), (2.0, 0.0, 0.0), (0.0, 1.0, 0.0), (1.0, 1.0, 1.0), (2.0, 1.0, 0.0)],
+            vec![vec![0, 1, 4, 3], vec![1, 2, 5, 4]],
+        );
+        let settings = SubdivisionSettings {
+            creases: vec![(1, 4)],
+            ..Default::default()
+        };
+        let res = subdivide(&strip, &settings);
+        // fixed boundary corner
+        assert_eq!(res.vertex_array[0].x, 0.0);
+        assert_eq!(res.vertex_array[0].y, 0.0);
+        // the crease edge point is the midpoint of the edge
+        let midpoints: Vec<&Point3> = res
+            .vertex_array
+            .iter()
+            .filter(|p| p.x == 1.0 && p.y == 0.5 && p.z == 0.5)
+            .collect();
+        assert_eq!(midpoints.len(), 1);
+    }
+}
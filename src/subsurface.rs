@@ -0,0 +1,146 @@
+use crate::bsdf::{fresnel_dielectric, Bsdf, Dielectric};
+use crate::material::Material;
+use crate::medium::{HenyeyGreenstein, Medium};
+use crate::scene::Color;
+use crate::shading::{Lambert, LightSample, ShadingContext, ShadingModel};
+use std::sync::Arc;
+
+/** Random walk subsurface scattering: a dielectric boundary around a scattering medium filling a closed mesh.
+The path tracer follows the light inside, the other integrators shade it like a diffuse surface. */
+pub struct SubsurfaceScattering {
+    /** Color after many scattering events inside, on the 0-255 scale */
+    pub albedo: Color,
+    /** Average distance light travels between two interactions, per color channel */
+    pub mean_free_path: [f64; 3],
+    /** Henyey-Greenstein asymmetry of the scattering inside */
+    pub g: f64,
+    pub boundary: Dielectric,
+}
+
+/** Single scattering albedo giving the multiple scattering albedo of a semi-infinite medium, after Chiang et al. */
+pub fn single_scattering_albedo(albedo: f64) -> f64 {
+    let a = albedo.clamp(0.0, 1.0);
+    let s = 4.09712 + 4.20863 * a - (9.59217 + 41.6808 * a + 17.7126 * a * a).sqrt();
+    (1.0 - s * s).clamp(0.0, 1.0)
+}
+
+impl SubsurfaceScattering {
+    pub fn new(albedo: Color, mean_free_path: [f64; 3]) -> SubsurfaceScattering {
+        SubsurfaceScattering {
+            albedo,
+            mean_free_path,
+            g: 0.0,
+            boundary: Dielectric {
+                index_of_refraction: 1.4,
+                roughness: 0.0,
+                dispersion: None,
+            },
+        }
+    }
+
+    /** Pinkish skin, red light travels furthest */
+    pub fn skin() -> SubsurfaceScattering {
+        SubsurfaceScattering::new(
+            Color {
+                red: 230.0,
+                green: 160.0,
+                blue: 130.0,
+            },
+            [0.12, 0.05, 0.03],
+        )
+    }
+
+    /** Pale candle wax */
+    pub fn wax() -> SubsurfaceScattering {
+        SubsurfaceScattering::new(
+            Color {
+                red: 240.0,
+                green: 225.0,
+                blue: 190.0,
+            },
+            [0.4, 0.3, 0.2],
+        )
+    }
+
+    /** Coefficients of the interior for the albedo and mean free path */
+    pub fn medium(&self) -> Medium {
+        let albedo = [self.albedo.red, self.albedo.green, self.albedo.blue];
+        let mut sigma_a = [0.0; 3];
+        let mut sigma_s = [0.0; 3];
+        for c in 0..3 {
+            let sigma_t = 1.0 / self.mean_free_path[c].max(1e-9);
+            let single = single_scattering_albedo(albedo[c] / 255.0);
+            sigma_s[c] = single * sigma_t;
+            sigma_a[c] = (1.0 - single) * sigma_t;
+        }
+        Medium {
+            sigma_a,
+            sigma_s,
+            phase: HenyeyGreenstein { g: self.g },
+            density: None,
+        }
+    }
+
+    /** Material for a closed mesh, its interior filled with the scattering medium */
+    pub fn into_material(self) -> Material {
+        let interior = Arc::new(self.medium());
+        Material {
+            albedo: self.albedo.clone(),
+            shading_model: Box::new(self),
+            interior: Some(interior),
+            ..Default::default()
+        }
+    }
+}
+
+impl ShadingModel for SubsurfaceScattering {
+    fn shade(&self, context: &ShadingContext, lights: &[LightSample]) -> Color {
+        Lambert.shade(context, lights)
+    }
+
+    fn bsdf(&self) -> Option<&dyn Bsdf> {
+        Some(&self.boundary)
+    }
+
+    // lights are sampled from inside through the boundary, ignoring the bending of the shadow rays
+    fn shadow_transmission(&self, cos_theta: f64, front_face: bool) -> Option<f64> {
+        let ior = self.boundary.index_of_refraction;
+        let eta = if front_face { ior } else { 1.0 / ior };
+        // the same radiance scaling as a path refracted by the boundary
+        Some((1.0 - fresnel_dielectric(cos_theta, eta)) / (eta * eta))
+    }
+}
+
+#[cfg(test)]
+mod test_subsurface {
+    use super::*;
+
+    #[test]
+    fn albedo_inversion_is_monotonic() {
+        assert!(single_scattering_albedo(0.0) < 1e-3);
+        assert!((single_scattering_albedo(1.0) - 1.0).abs() < 1e-3);
+        let mut last = 0.0;
+        for i in 1..=10 {
+            let single = single_scattering_albedo(i as f64 / 10.0);
+            assert!(single > last);
+            // light scattering many times is absorbed more than the single scattering albedo suggests
+            assert!(single >= i as f64 / 10.0 - 1e-3);
+            last = single;
+        }
+    }
+
+    #[test]
+    fn medium_follows_mean_free_path() {
+        let wax = SubsurfaceScattering::wax();
+        let medium = wax.medium();
+        for c in 0..3 {
+            let sigma_t = medium.sigma_a[c] + medium.sigma_s[c];
+            assert!((sigma_t * wax.mean_free_path[c] - 1.0).abs() < 1e-12);
+        }
+        // the blue channel is darker, so absorbs more
+        assert!(medium.sigma_a[2] / medium.sigma_s[2] > medium.sigma_a[0] / medium.sigma_s[0]);
+        let material = SubsurfaceScattering::skin().into_material();
+        assert!(material.interior.is_some());
+        assert!(material.shading_model.bsdf().is_some());
+    }
+}
@@ -0,0 +1,90 @@
+use crate::color::TransferFunction;
+use crate::scene::Color;
+
+/** Image texture, texels stored row by row with (0, 0) in the upper left corner */
+pub struct Texture {
+    pub width: u32,
+    pub height: u32,
+    pub texels: Vec<Color>,
+}
+
+impl Texture {
+    /** Texture with the stored values as they are, for data like normal, bump and displacement maps */
+    pub fn from_file(path: &str) -> image::ImageResult<Texture> {
+        Texture::from_file_encoded(path, TransferFunction::Linear)
+    }
+
+    /** Color texture, the 8 bit values are decoded from sRGB into linear colors */
+    pub fn from_file_srgb(path: &str) -> image::ImageResult<Texture> {
+        Texture::from_file_encoded(path, TransferFunction::Srgb)
+    }
+
+    fn from_file_encoded(path: &str, encoding: TransferFunction) -> image::ImageResult<Texture> {
+        let image = image::open(path)?.to_rgb8();
+        let decode = |v: u8| 255.0 * encoding.decode(v as f64 / 255.0);
+        let texels: Vec<Color> = image
+            .pixels()
+            .map(|p| Color {
+                red: decode(p[0]),
+                green: decode(p[1]),
+                blue: decode(p[2]),
+            })
+            .collect();
+        Ok(Texture {
+            width: image.width(),
+            height: image.height(),
+            texels,
+        })
+    }
+
+    /** Procedural texture, f is called with the (u, v) of every texel center */
+    pub fn from_fn<F: Fn(f64, f64) -> Color>(width: u32, height: u32, f: F) -> Texture {
+        let mut texels: Vec<Color> = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                let u = (x as f64 + 0.5) / width as f64;
+                let v = 1.0 - (y as f64 + 0.5) / height as f64;
+                texels.push(f(u, v));
+            }
+        }
+        Texture { width, height, texels }
+    }
+
+    pub fn texel(&self, x: u32, y: u32) -> &Color {
+        &self.texels[(y * self.width + x) as usize]
+    }
+}
+
+#[cfg(test)]
+mod test_texture {
+    use super::*;
+
+    #[test]
+    fn procedural_texture_layout() {
+        let texture = Texture::from_fn(2, 2, |u, v| Color {
+            red: u * 255.0,
+            green: v * 255.0,
+            blue: 0.0,
+        });
+        // v grows upwards, rows are stored from the top
+        assert_eq!(texture.texel(1, 0).red, 0.75 * 255.0);
+        assert_eq!(texture.texel(1, 0).green, 0.75 * 255.0);
+        assert_eq!(texture.texel(0, 1).green, 0.25 * 255.0);
+    }
+
+    #[test]
+    fn negativ_load_texture() {
+        assert!(Texture::from_file("file_that_does_not_exist.png").is_err());
+    }
+
+    #[test]
+    fn data_textures_keep_their_values() {
+        let path = std::env::temp_dir().join("texture_gray_128.png");
+        let path = path.to_str().unwrap();
+        image::RgbImage::from_pixel(1, 1, image::Rgb([128, 128, 128])).save(path).unwrap();
+        assert_eq!(Texture::from_file(path).unwrap().texel(0, 0).red, 128.0);
+        // the middle of the sRGB curve is about a fifth of white in linear light
+        let color = Texture::from_file_srgb(path).unwrap();
+        assert!((color.texel(0, 0).red - 55.0).abs() < 0.5);
+    }
+}
@@ -1,6 +1,7 @@
 use std::ops::{Mul, Add, Sub};
 use crate::point::Point3;
 
+#[derive(Clone, Debug)]
 pub struct Vector3 {
     pub x: f64,
     pub y: f64,
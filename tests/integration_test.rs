@@ -13,4 +13,145 @@ use image::DynamicImage;
         raytracer_lib::save_image(&image);
     }
     //  1m54.861s   shaders
-    //  1m58.116s   no shader
\ No newline at end of file
+    //  1m58.116s   no shader
+
+#[test]
+fn test_teapot_patch_render() {
+    use image::GenericImageView;
+    use raytracer_lib::bezier::create_scene_from_bpt_file;
+
+    let mut tessellated = create_scene_from_bpt_file(String::from("geometry/teapot.bpt"), Some(8)).unwrap();
+    let mut direct = create_scene_from_bpt_file(String::from("geometry/teapot.bpt"), None).unwrap();
+    tessellated.width = 120;
+    tessellated.height = 80;
+    direct.width = 120;
+    direct.height = 80;
+    let tessellated_image = raytracer_lib::render(&tessellated);
+    let direct_image = raytracer_lib::render(&direct);
+
+    // both intersection paths should cover (nearly) the same pixels
+    let mut covered = 0;
+    let mut mismatched = 0;
+    for (x, y, pixel) in tessellated_image.pixels() {
+        let hit_tessellated = pixel[0] > 0;
+        let hit_direct = direct_image.get_pixel(x, y)[0] > 0;
+        if hit_tessellated {
+            covered += 1;
+        }
+        if hit_tessellated != hit_direct {
+            mismatched += 1;
+        }
+    }
+    assert!(covered > 500);
+    assert!(mismatched * 20 < covered);
+}
+
+#[test]
+fn test_ambient_occlusion_pass() {
+    use raytracer_lib::integrator::{render_ambient_occlusion, AmbientOcclusionSettings};
+
+    let mut scene = load_geo_scene::create_scene_from_file(String::from("geometry/cylinder.geo")).unwrap();
+    scene.width = 60;
+    scene.height = 40;
+    let settings = AmbientOcclusionSettings {
+        samples: 8,
+        ..Default::default()
+    };
+    let image = render_ambient_occlusion(&scene, &settings);
+    assert_eq!(image.color(), image::ColorType::L8);
+    // fixed seeds make the pass reproducible
+    assert_eq!(image.as_bytes(), render_ambient_occlusion(&scene, &settings).as_bytes());
+}
+
+#[test]
+fn test_orthographic_part_view() {
+    use raytracer_lib::camera::OrthographicCamera;
+    use raytracer_lib::integrator::{render_radiance, DebugIntegrator, DebugView, RenderSettings};
+    use std::sync::Arc;
+
+    let mut scene = load_geo_scene::create_scene_from_file(String::from("geometry/cylinder.geo")).unwrap();
+    scene.width = 60;
+    scene.height = 40;
+    let settings = RenderSettings {
+        camera: Some(Arc::new(OrthographicCamera { view_height: 10.0 })),
+        ..Default::default()
+    };
+    let normals = render_radiance(&scene, &DebugIntegrator { view: DebugView::Normal }, &settings);
+    // the part fills part of the view, the rest misses
+    let covered = normals.iter().filter(|c| c.red + c.green + c.blue > 0.0).count();
+    assert!(covered > 100 && covered < 60 * 40);
+}
+
+#[test]
+fn test_dispersive_prism_fringes() {
+    use raytracer_lib::bsdf::Dielectric;
+    use raytracer_lib::material::Material;
+    use raytracer_lib::path_tracing::{render_path_traced, PathTracingSettings};
+    use raytracer_lib::point::Point3;
+    use raytracer_lib::scene::{Color, Element, Scene, Triangle};
+    use raytracer_lib::spectral::Dispersion;
+    use std::sync::Arc;
+
+    let triangle = |[p1, p2, p3]: [(f64, f64, f64); 3], material: &Arc<Material>| {
+        Element::Triangle(Triangle {
+            point1: Point3 { x: p1.0, y: p1.1, z: p1.2 },
+            point2: Point3 { x: p2.0, y: p2.1, z: p2.2 },
+            point3: Point3 { x: p3.0, y: p3.1, z: p3.2 },
+            normals: None,
+            st: None,
+            tangent_frame: None,
+            material: material.clone(),
+        })
+    };
+    let render = |glass: Dielectric| {
+        // a 30 degree wedge facing the camera, its base at +x bends the view towards +x
+        let glass = Arc::new(glass.into_material());
+        let (f1, f2, f3, f4) = ((-10.0, -10.0, -5.0), (10.0, -10.0, -5.0), (10.0, 10.0, -5.0), (-10.0, 10.0, -5.0));
+        let (b1, b2) = ((10.0, -10.0, -16.547), (10.0, 10.0, -16.547));
+        let mut elements: Vec<Element> = [
+            [f1, f2, f3],
+            [f1, f3, f4],
+            [f2, b1, b2],
+            [f2, b2, f3],
+            [f1, f4, b2],
+            [f1, b2, b1],
+            [f1, b1, f2],
+            [f4, f3, b2],
+        ]
+        .iter()
+        .map(|corners| triangle(*corners, &glass))
+        .collect();
+        // a white backdrop whose edge is seen through the middle of the wedge
+        let white = Arc::new(Material {
+            emission: Some(Color::from_one(255.0)),
+            ..Default::default()
+        });
+        elements.push(triangle([(58.3, -1000.0, -100.0), (58.3, 1000.0, -100.0), (3000.0, 0.0, -100.0)], &white));
+        let scene = Scene {
+            width: 20,
+            height: 20,
+            fov: 10.0,
+            elements,
+            lights: Vec::new(),
+            medium: None,
+        };
+        let settings = PathTracingSettings {
+            samples_per_pixel: 16,
+            spectral: true,
+            ..Default::default()
+        };
+        render_path_traced(&scene, &settings)
+    };
+    let flint = Dielectric::dispersive(Dispersion::dense_flint(), 0.0);
+    let constant = Dielectric {
+        dispersion: None,
+        ..Dielectric::dispersive(Dispersion::dense_flint(), 0.0)
+    };
+    let dispersive = render(flint);
+    let plain = render(constant);
+    assert!(plain.iter().any(|c| c.red > 0.0));
+    // blue is bent further than red and sees more of the backdrop, without dispersion they only differ by noise
+    let blue_excess = |image: &[Color]| image.iter().map(|c| c.blue - c.red).sum::<f64>() / image.len() as f64;
+    assert!(blue_excess(&dispersive) > 10.0, "{}", blue_excess(&dispersive));
+    assert!(blue_excess(&plain).abs() < 5.0, "{}", blue_excess(&plain));
+}